@@ -1,46 +1,110 @@
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use serde::Serialize;
-//use utoipa::ToSchema;
+use utoipa::ToSchema;
 
-#[derive(Copy, Clone, Debug)]
-#[repr(u8)]
+use crate::{client::DnsError, request_id};
+
+#[derive(Clone, Debug)]
 pub enum ApiError {
-    RequestServerError,
+    RequestServerError(u16),
     AccountNotFound,
     NotFound,
+    BadRequest(String),
 
     WrongEpisodeId,
     WrongAuthKey,
+    WrongRefreshToken,
     WrongAvatar,
+    WrongSource,
     WrongId,
+    WrongStreamToken,
+    TooManyStreams(i64),
 
     SystemTime,
-    DataBase,
-    ParseInt,
-    UrlParse,
+    DataBase(String),
+    ParseInt(String),
+    UrlParse(String),
     FromUtf8,
-    Reqwest,
+    Reqwest(String),
+    Timeout(String),
+    Dns(String),
+    Tls(String),
+    CircuitOpen,
+    TooLarge(u64),
     Channel,
     Poison,
-    Serde,
+    Serde(String),
     OsRng,
-    Io,
+    Io(String),
+    Crypto,
 }
 
-#[derive(/*ToSchema,*/ Serialize)]
-struct ApiErrorJson {
-    //#[schema(example = "0")]
-    error: u8,
+#[derive(ToSchema, Serialize)]
+pub struct ApiErrorJson {
+    #[schema(example = 401)]
+    status: u16,
+
+    #[schema(example = "wrong_auth_key")]
+    code: &'static str,
+
+    #[schema(example = "auth key is invalid")]
+    message: String,
 
-    //#[schema(example = "AccountNotFound")]
-    hint: String,
+    #[schema(example = "4f2a9c1d7e3b5a60")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
-impl From<ApiError> for ApiErrorJson {
-    fn from(err: ApiError) -> ApiErrorJson {
+impl ApiError {
+    /// Stable machine-readable identifier, safe for clients to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::RequestServerError(_) => "request_server_error",
+            ApiError::AccountNotFound => "account_not_found",
+            ApiError::NotFound => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::WrongEpisodeId => "wrong_episode_id",
+            ApiError::WrongAuthKey => "wrong_auth_key",
+            ApiError::WrongRefreshToken => "wrong_refresh_token",
+            ApiError::WrongAvatar => "wrong_avatar",
+            ApiError::WrongSource => "wrong_source",
+            ApiError::WrongId => "wrong_id",
+            ApiError::WrongStreamToken => "wrong_stream_token",
+            ApiError::TooManyStreams(_) => "too_many_streams",
+            ApiError::SystemTime => "system_time",
+            ApiError::DataBase(_) => "database",
+            ApiError::ParseInt(_) => "parse_int",
+            ApiError::UrlParse(_) => "url_parse",
+            ApiError::FromUtf8 => "from_utf8",
+            ApiError::Reqwest(_) => "upstream_unreachable",
+            ApiError::Timeout(_) => "upstream_timeout",
+            ApiError::Dns(_) => "upstream_dns",
+            ApiError::Tls(_) => "upstream_tls",
+            ApiError::CircuitOpen => "upstream_circuit_open",
+            ApiError::TooLarge(_) => "upstream_too_large",
+            ApiError::Channel => "channel",
+            ApiError::Poison => "poison",
+            ApiError::Serde(_) => "upstream_decode",
+            ApiError::OsRng => "os_rng",
+            ApiError::Io(_) => "io",
+            ApiError::Crypto => "crypto",
+        }
+    }
+}
+
+impl From<&ApiError> for ApiErrorJson {
+    fn from(err: &ApiError) -> ApiErrorJson {
+        // What broke inside PlayerApi is for the logs, not for clients.
+        let message = match err.status_code() {
+            StatusCode::INTERNAL_SERVER_ERROR => "internal error".to_string(),
+            _ => err.to_string(),
+        };
+
         ApiErrorJson {
-            error: err as u8,
-            hint: format!("{:?}", err),
+            status: err.status_code().as_u16(),
+            code: err.code(),
+            message,
+            request_id: request_id::current(),
         }
     }
 }
@@ -64,8 +128,8 @@ impl From<rand::Error> for ApiError {
 }
 
 impl From<url::ParseError> for ApiError {
-    fn from(_: url::ParseError) -> ApiError {
-        ApiError::UrlParse
+    fn from(err: url::ParseError) -> ApiError {
+        ApiError::UrlParse(err.to_string())
     }
 }
 
@@ -76,32 +140,73 @@ impl From<std::string::FromUtf8Error> for ApiError {
 }
 
 impl From<reqwest::Error> for ApiError {
-    fn from(_: reqwest::Error) -> ApiError {
-        ApiError::Reqwest
+    fn from(err: reqwest::Error) -> ApiError {
+        if let Some(status) = err.status() {
+            return ApiError::RequestServerError(status.as_u16());
+        }
+        if err.is_timeout() {
+            return ApiError::Timeout(err.without_url().to_string());
+        }
+        if err.is_decode() {
+            return ApiError::Serde(err.without_url().to_string());
+        }
+
+        // Causes are told apart by their type, the resolver wraps its
+        // failures in its own.
+        let mut source = std::error::Error::source(&err);
+        while let Some(cause) = source {
+            if let Some(error) = cause.downcast_ref::<DnsError>() {
+                return ApiError::Dns(error.to_string());
+            }
+            if let Some(error) = cause.downcast_ref::<native_tls::Error>() {
+                return ApiError::Tls(error.to_string());
+            }
+            source = cause.source();
+        }
+
+        ApiError::Reqwest(err.without_url().to_string())
     }
 }
 
 impl From<serde_json::Error> for ApiError {
-    fn from(_: serde_json::Error) -> ApiError {
-        ApiError::Serde
+    fn from(err: serde_json::Error) -> ApiError {
+        ApiError::Serde(err.to_string())
+    }
+}
+
+impl From<serde_path_to_error::Error<serde_json::Error>> for ApiError {
+    fn from(err: serde_path_to_error::Error<serde_json::Error>) -> ApiError {
+        ApiError::Serde(format!("{} at {}", err.inner(), err.path()))
+    }
+}
+
+impl From<quick_xml::Error> for ApiError {
+    fn from(err: quick_xml::Error) -> ApiError {
+        ApiError::Serde(err.to_string())
+    }
+}
+
+impl From<chacha20poly1305::Error> for ApiError {
+    fn from(_: chacha20poly1305::Error) -> ApiError {
+        ApiError::Crypto
     }
 }
 
 impl From<std::io::Error> for ApiError {
-    fn from(_: std::io::Error) -> ApiError {
-        ApiError::Io
+    fn from(err: std::io::Error) -> ApiError {
+        ApiError::Io(err.to_string())
     }
 }
 
 impl From<sea_orm::DbErr> for ApiError {
-    fn from(_: sea_orm::DbErr) -> ApiError {
-        ApiError::DataBase
+    fn from(err: sea_orm::DbErr) -> ApiError {
+        ApiError::DataBase(err.to_string())
     }
 }
 
 impl From<std::num::ParseIntError> for ApiError {
-    fn from(_: std::num::ParseIntError) -> ApiError {
-        ApiError::ParseInt
+    fn from(err: std::num::ParseIntError) -> ApiError {
+        ApiError::ParseInt(err.to_string())
     }
 }
 
@@ -113,30 +218,108 @@ impl From<tokio::sync::oneshot::error::RecvError> for ApiError {
 
 impl std::fmt::Display for ApiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            ApiError::RequestServerError(status) => {
+                write!(f, "upstream server answered with status {status}")
+            }
+            ApiError::AccountNotFound => write!(f, "account not found or not active"),
+            ApiError::NotFound => write!(f, "not found"),
+            ApiError::BadRequest(msg) => write!(f, "{msg}"),
+            ApiError::WrongEpisodeId => write!(f, "episode id does not belong to this serie"),
+            ApiError::WrongAuthKey => write!(f, "auth key is invalid"),
+            ApiError::WrongRefreshToken => write!(f, "refresh token is invalid or expired"),
+            ApiError::WrongAvatar => write!(f, "avatar does not exist"),
+            ApiError::WrongSource => write!(f, "source does not exist"),
+            ApiError::WrongId => write!(f, "id does not exist"),
+            ApiError::WrongStreamToken => write!(f, "stream link is invalid or expired"),
+            ApiError::TooManyStreams(max) => {
+                write!(f, "all {max} streams of the source are in use")
+            }
+            ApiError::DataBase(msg) => write!(f, "database error: {msg}"),
+            ApiError::ParseInt(msg) => write!(f, "could not parse number: {msg}"),
+            ApiError::UrlParse(msg) => write!(f, "could not parse url: {msg}"),
+            ApiError::Reqwest(msg) => write!(f, "could not reach upstream server: {msg}"),
+            ApiError::Timeout(msg) => write!(f, "upstream server timed out: {msg}"),
+            ApiError::Dns(msg) => write!(f, "could not resolve upstream server: {msg}"),
+            ApiError::Tls(msg) => write!(f, "tls error with upstream server: {msg}"),
+            ApiError::CircuitOpen => write!(f, "upstream server is down, try again later"),
+            ApiError::TooLarge(max) => {
+                write!(f, "upstream response is larger than {max} bytes")
+            }
+            ApiError::Serde(msg) => write!(f, "could not decode upstream response: {msg}"),
+            ApiError::Io(msg) => write!(f, "io error: {msg}"),
+            ApiError::Crypto => write!(f, "could not decrypt stored credentials"),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
 impl ResponseError for ApiError {
-    fn error_response(&self) -> HttpResponse {
+    fn status_code(&self) -> StatusCode {
         match self {
-            ApiError::NotFound => HttpResponse::NotFound().finish(),
-            _ => HttpResponse::InternalServerError().json(ApiErrorJson::from(*self)),
+            ApiError::WrongAuthKey | ApiError::WrongRefreshToken | ApiError::AccountNotFound => {
+                StatusCode::UNAUTHORIZED
+            }
+
+            ApiError::WrongStreamToken => StatusCode::FORBIDDEN,
+            ApiError::TooManyStreams(_) => StatusCode::TOO_MANY_REQUESTS,
+
+            ApiError::NotFound
+            | ApiError::WrongId
+            | ApiError::WrongAvatar
+            | ApiError::WrongSource
+            | ApiError::WrongEpisodeId => StatusCode::NOT_FOUND,
+
+            ApiError::BadRequest(_)
+            | ApiError::ParseInt(_)
+            | ApiError::UrlParse(_)
+            | ApiError::FromUtf8 => StatusCode::BAD_REQUEST,
+
+            ApiError::RequestServerError(_)
+            | ApiError::Reqwest(_)
+            | ApiError::Dns(_)
+            | ApiError::Tls(_)
+            | ApiError::TooLarge(_)
+            | ApiError::Serde(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
+
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code() == StatusCode::INTERNAL_SERVER_ERROR {
+            tracing::error!(
+                request_id = request_id::current().as_deref(),
+                error = %self,
+                "internal error"
+            );
+        }
+
+        HttpResponse::build(self.status_code())
+            .content_type("application/problem+json")
+            .json(ApiErrorJson::from(self))
+    }
 }
 
 pub type ApiResult<T> = Result<T, ApiError>;
 
-pub trait ErrAs<T> {
-    fn err_as<O>(self, op: O) -> Result<T, O>;
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl<T, E> ErrAs<T> for Result<T, E> {
-    fn err_as<O>(self, op: O) -> Result<T, O> {
-        match self {
-            Ok(t) => Ok(t),
-            Err(_) => Err(op),
-        }
+    #[test]
+    fn hides_internal_errors() {
+        let json = ApiErrorJson::from(&ApiError::DataBase("no such table: login".into()));
+        assert_eq!(json.status, 500);
+        assert_eq!(json.code, "database");
+        assert_eq!(json.message, "internal error");
+
+        let json = ApiErrorJson::from(&ApiError::Io("permission denied".into()));
+        assert_eq!(json.message, "internal error");
+
+        let json = ApiErrorJson::from(&ApiError::BadRequest("end must come after start".into()));
+        assert_eq!(json.message, "end must come after start");
     }
 }
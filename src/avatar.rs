@@ -4,41 +4,71 @@ use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFi
 
 use crate::{
     api_error::{ApiError, ApiResult},
+    crypto::Crypto,
     entities::prelude::*,
     extra::BoolResult,
     login,
 };
 
+#[utoipa::path(
+    get,
+    path = "/avatar/get",
+    tag = "avatar",
+    responses(
+        (status = 200, description = "Avatars of the session", body = Vec<Avatar>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/get")]
 pub async fn get(
     credentials: BearerAuth,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     let avatar = AvatarEntity::find()
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .all(db.get_ref())
         .await?;
 
     Ok(HttpResponse::Ok().json(avatar))
 }
 
+#[utoipa::path(
+    get,
+    path = "/avatar/store/{name}",
+    tag = "avatar",
+    params(
+        ("name" = String, Path, description = "Avatar name"),
+    ),
+    responses(
+        (status = 200, description = "Whether the avatar was created", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/store/{name}")]
 async fn store(
     credentials: BearerAuth,
     path: ActixWeb::Path<String>,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let name = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     let exist = AvatarEntity::find()
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .filter(AvatarColumn::Name.eq(&name))
         .one(db.get_ref())
         .await?;
@@ -46,7 +76,7 @@ async fn store(
     if exist.is_none() {
         AvatarEntity::insert(AvatarActiveModel {
             id: Default::default(),
-            session_id: ActiveValue::Set(session.id),
+            account_id: ActiveValue::Set(session.account_id),
             name: ActiveValue::Set(name),
         })
         .exec(db.get_ref())
@@ -58,20 +88,37 @@ async fn store(
     }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/avatar/remove/{id}",
+    tag = "avatar",
+    params(
+        ("id" = i64, Path, description = "Avatar id"),
+    ),
+    responses(
+        (status = 200, description = "Whether the avatar was removed", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar does not exist", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/remove/{id}")]
 async fn remove(
     credentials: BearerAuth,
     path: ActixWeb::Path<i64>,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let id = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     let avatar: AvatarActiveModel = AvatarEntity::find()
         .filter(AvatarColumn::Id.eq(id))
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .one(db.get_ref())
         .await?
         .ok_or(ApiError::WrongId)?
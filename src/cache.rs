@@ -0,0 +1,268 @@
+use sea_orm::{
+    sea_query::OnConflict, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    api_error::ApiResult,
+    config::{CacheBackendKind, CacheConfig},
+    entities::prelude::*,
+    extra::Params,
+    metrics::Metrics,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    server: String,
+    username: String,
+    action: String,
+    id: String,
+}
+
+impl CacheKey {
+    pub fn new(params: &Params<'_>) -> CacheKey {
+        CacheKey {
+            server: params.login.server.clone(),
+            username: params.login.username.clone(),
+            action: params.action.unwrap_or_default().to_string(),
+            id: params
+                .id
+                .as_ref()
+                .map(|x| x.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    fn to_db_key(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}",
+            self.server, self.username, self.action, self.id
+        )
+    }
+}
+
+enum Backend {
+    None,
+    Memory(Mutex<HashMap<CacheKey, (i64, Arc<String>)>>),
+    Sqlite(DatabaseConnection),
+}
+
+pub struct Cache {
+    backend: Backend,
+    config: CacheConfig,
+    metrics: Arc<Metrics>,
+    inflight: Mutex<HashMap<CacheKey, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Lock of a fetch in progress, removed when its first caller is done or
+/// dropped so a failed or cancelled fetch does not leave it behind.
+struct Inflight<'a> {
+    cache: &'a Cache,
+    key: &'a CacheKey,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Drop for Inflight<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut inflight) = self.cache.inflight.lock() {
+            if inflight
+                .get(self.key)
+                .is_some_and(|x| Arc::ptr_eq(x, &self.lock))
+            {
+                inflight.remove(self.key);
+            }
+        }
+    }
+}
+
+impl Cache {
+    pub fn new(config: &CacheConfig, db: &DatabaseConnection, metrics: Arc<Metrics>) -> Cache {
+        let backend = match config.backend {
+            CacheBackendKind::None => Backend::None,
+            CacheBackendKind::Memory => Backend::Memory(Mutex::default()),
+            CacheBackendKind::Sqlite => Backend::Sqlite(db.clone()),
+        };
+
+        Cache {
+            backend,
+            config: config.clone(),
+            metrics,
+            inflight: Mutex::default(),
+        }
+    }
+
+    /// Returns the cached body for `key`, or runs `fetch` and stores its result.
+    /// Concurrent callers for the same key wait for the first fetch to finish
+    /// instead of hitting the upstream server themselves.
+    pub async fn get_or_fetch<F>(&self, key: CacheKey, fetch: F) -> ApiResult<Arc<String>>
+    where
+        F: Future<Output = ApiResult<String>>,
+    {
+        let ttl = self.config.ttl_for(key.action());
+
+        if ttl == 0 || matches!(self.backend, Backend::None) {
+            return Ok(Arc::new(fetch.await?));
+        }
+
+        if let Some(body) = self.lookup(&key).await? {
+            self.metrics.observe_cache(key.action(), true);
+            return Ok(body);
+        }
+
+        let inflight = Inflight {
+            cache: self,
+            key: &key,
+            lock: self
+                .inflight
+                .lock()?
+                .entry(key.clone())
+                .or_default()
+                .clone(),
+        };
+        let _guard = inflight.lock.lock().await;
+
+        if let Some(body) = self.lookup(&key).await? {
+            self.metrics.observe_cache(key.action(), true);
+            return Ok(body);
+        }
+        self.metrics.observe_cache(key.action(), false);
+
+        let body = Arc::new(fetch.await?);
+
+        // The body is good even when it could not be kept.
+        if let Err(error) = self.store(&key, body.clone(), ttl).await {
+            tracing::warn!(action = key.action(), %error, "could not store cached response");
+        }
+
+        Ok(body)
+    }
+
+    async fn lookup(&self, key: &CacheKey) -> ApiResult<Option<Arc<String>>> {
+        let now = chrono::Utc::now().timestamp();
+
+        match &self.backend {
+            Backend::None => Ok(None),
+            Backend::Memory(map) => {
+                let mut map = map.lock()?;
+                match map.get(key) {
+                    Some((expires, body)) if *expires > now => Ok(Some(body.clone())),
+                    Some(_) => {
+                        map.remove(key);
+                        Ok(None)
+                    }
+                    None => Ok(None),
+                }
+            }
+            Backend::Sqlite(db) => Ok(CacheEntity::find_by_id(key.to_db_key())
+                .filter(CacheColumn::Expires.gt(now))
+                .one(db)
+                .await?
+                .map(|x| Arc::new(x.body))),
+        }
+    }
+
+    async fn store(&self, key: &CacheKey, body: Arc<String>, ttl: u64) -> ApiResult<()> {
+        let expires = chrono::Utc::now().timestamp() + ttl as i64;
+
+        match &self.backend {
+            Backend::None => {}
+            Backend::Memory(map) => {
+                map.lock()?.insert(key.clone(), (expires, body));
+            }
+            Backend::Sqlite(db) => {
+                CacheEntity::insert(CacheActiveModel {
+                    key: ActiveValue::Set(key.to_db_key()),
+                    body: ActiveValue::Set(body.to_string()),
+                    expires: ActiveValue::Set(expires),
+                })
+                .on_conflict(
+                    OnConflict::column(CacheColumn::Key)
+                        .update_columns([CacheColumn::Body, CacheColumn::Expires])
+                        .to_owned(),
+                )
+                .exec(db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn purge(&self) -> ApiResult<()> {
+        let now = chrono::Utc::now().timestamp();
+
+        match &self.backend {
+            Backend::None => {}
+            Backend::Memory(map) => {
+                map.lock()?.retain(|_, (expires, _)| *expires > now);
+            }
+            Backend::Sqlite(db) => {
+                CacheEntity::delete_many()
+                    .filter(CacheColumn::Expires.lte(now))
+                    .exec(db)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_error::ApiError;
+    use futures::FutureExt;
+    use sea_orm::Database;
+
+    fn key() -> CacheKey {
+        CacheKey {
+            server: "http://example.com/player_api.php".to_string(),
+            username: "user".to_string(),
+            action: "get_live_streams".to_string(),
+            id: String::new(),
+        }
+    }
+
+    async fn cache() -> Cache {
+        let config = CacheConfig {
+            backend: CacheBackendKind::Memory,
+            default_ttl_secs: 60,
+            ttl_secs: HashMap::new(),
+        };
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Cache::new(&config, &db, Arc::new(Metrics::new().unwrap()))
+    }
+
+    #[actix_web::test]
+    async fn clears_failed_and_cancelled_fetches() {
+        let cache = cache().await;
+
+        let failed = cache
+            .get_or_fetch(key(), async { Err(ApiError::WrongId) })
+            .await;
+        assert!(failed.is_err());
+        assert!(cache.inflight.lock().unwrap().is_empty());
+
+        let cancelled = cache
+            .get_or_fetch(key(), futures::future::pending())
+            .now_or_never();
+        assert!(cancelled.is_none());
+        assert!(cache.inflight.lock().unwrap().is_empty());
+
+        let body = cache
+            .get_or_fetch(key(), async { Ok("body".to_string()) })
+            .await
+            .unwrap();
+        assert_eq!(*body, "body");
+        assert!(cache.inflight.lock().unwrap().is_empty());
+    }
+}
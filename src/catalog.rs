@@ -0,0 +1,480 @@
+use actix_web::web as ActixWeb;
+use ordered_float::OrderedFloat;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, Order,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    client::Client,
+    crypto::Crypto,
+    entities::{catalog_stream::Kind, login::Backend, prelude::*},
+    extra::Params,
+    get::{
+        get_categories, get_lives, get_movies, get_series, Alternative, Category, Direction, Page,
+        Sort, Value,
+    },
+    m3u,
+    search::fold,
+};
+
+const CHUNK_SIZE: usize = 500;
+
+static SYNC_LOCKS: LazyLock<Mutex<HashMap<i64, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Mutex::default);
+
+pub async fn sync_all(
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<()> {
+    let logins = LoginEntity::find().all(db.get_ref()).await?;
+
+    // One provider being down must not hold back the others.
+    for login in logins {
+        let id = login.id;
+        let login = match crypto.open_login(login) {
+            Ok(login) => login,
+            Err(error) => {
+                tracing::warn!(login = id, %error, "could not open login for catalog sync");
+                continue;
+            }
+        };
+
+        for kind in [Kind::Live, Kind::Movie, Kind::Serie] {
+            if let Err(error) = sync(&login, kind, &db, client.clone()).await {
+                tracing::warn!(login = id, ?kind, %error, "catalog sync of login failed");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Mirrors the categories and streams of one kind from the provider, writing
+/// only the streams that were added, removed or changed. Changes are told by
+/// the `added` or `last_modified` date of the stream when it has one.
+pub async fn sync(
+    login: &Login,
+    kind: Kind,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<()> {
+    let lock = SYNC_LOCKS.lock()?.entry(login.id).or_default().clone();
+    let _guard = lock.lock().await;
+
+    let (categories, values) = match login.backend {
+        Backend::Xtream => fetch(login, kind, client).await?,
+        Backend::M3u => {
+            let playlist = m3u::get_playlist(login, kind, client).await?;
+
+            // Kept so the guide is found without reading the playlist again.
+            if login.epg_url.is_none() && playlist.epg_url.is_some() {
+                LoginEntity::update(LoginActiveModel {
+                    id: ActiveValue::Unchanged(login.id),
+                    epg_url: ActiveValue::Set(playlist.epg_url),
+                    ..Default::default()
+                })
+                .exec(db)
+                .await?;
+            }
+
+            (playlist.categories, playlist.values)
+        }
+    };
+
+    let txn = db.begin().await?;
+
+    CatalogCategoryEntity::delete_many()
+        .filter(CatalogCategoryColumn::LoginId.eq(login.id))
+        .filter(CatalogCategoryColumn::Kind.eq(kind))
+        .exec(&txn)
+        .await?;
+
+    for chunk in categories.chunks(CHUNK_SIZE) {
+        CatalogCategoryEntity::insert_many(chunk.iter().map(|x| CatalogCategoryActiveModel {
+            id: Default::default(),
+            login_id: ActiveValue::Set(login.id),
+            kind: ActiveValue::Set(kind),
+            category_id: ActiveValue::Set(x.id),
+            name: ActiveValue::Set(x.name.clone()),
+        }))
+        .exec(&txn)
+        .await?;
+    }
+
+    let existing: HashMap<i64, CatalogStream> = CatalogStreamEntity::find()
+        .filter(CatalogStreamColumn::LoginId.eq(login.id))
+        .filter(CatalogStreamColumn::Kind.eq(kind))
+        .all(&txn)
+        .await?
+        .into_iter()
+        .map(|x| (x.value_id, x))
+        .collect();
+
+    let mut seen = HashSet::new();
+    let mut inserts = Vec::new();
+
+    for value in values {
+        if !seen.insert(value.id) {
+            continue;
+        }
+
+        match existing.get(&value.id) {
+            None => {
+                let mut stream = to_active_model(to_model(login.id, kind, value, 0));
+                stream.id = ActiveValue::NotSet;
+                inserts.push(stream);
+            }
+            // Providers bump the date when they edit a stream, one that did
+            // not move is not compared. Playlists have no dates.
+            Some(row) if value.added != 0 && row.added == value.added => {}
+            Some(row) => {
+                let stream = to_model(login.id, kind, value, row.id);
+                if *row != stream {
+                    CatalogStreamEntity::update(to_active_model(stream))
+                        .exec(&txn)
+                        .await?;
+                }
+            }
+        }
+    }
+
+    let removed = existing
+        .iter()
+        .filter(|(value_id, _)| !seen.contains(value_id))
+        .map(|(_, row)| row.id)
+        .collect::<Vec<i64>>();
+
+    for chunk in removed.chunks(CHUNK_SIZE) {
+        CatalogStreamEntity::delete_many()
+            .filter(CatalogStreamColumn::Id.is_in(chunk.iter().copied()))
+            .exec(&txn)
+            .await?;
+    }
+
+    for chunk in inserts.chunks(CHUNK_SIZE) {
+        CatalogStreamEntity::insert_many(chunk.iter().cloned())
+            .exec(&txn)
+            .await?;
+    }
+
+    let synced_at = chrono::Utc::now().timestamp();
+
+    match CatalogSyncEntity::find()
+        .filter(CatalogSyncColumn::LoginId.eq(login.id))
+        .filter(CatalogSyncColumn::Kind.eq(kind))
+        .one(&txn)
+        .await?
+    {
+        Some(catalog_sync) => {
+            CatalogSyncEntity::update(CatalogSyncActiveModel {
+                id: ActiveValue::Set(catalog_sync.id),
+                synced_at: ActiveValue::Set(synced_at),
+                ..Default::default()
+            })
+            .exec(&txn)
+            .await?;
+        }
+        None => {
+            CatalogSyncEntity::insert(CatalogSyncActiveModel {
+                id: Default::default(),
+                login_id: ActiveValue::Set(login.id),
+                kind: ActiveValue::Set(kind),
+                synced_at: ActiveValue::Set(synced_at),
+            })
+            .exec(&txn)
+            .await?;
+        }
+    }
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+/// The row a value is stored as, compared with the stored one to know
+/// whether it changed.
+fn to_model(login_id: i64, kind: Kind, value: Value, id: i64) -> CatalogStream {
+    let year = value.year.or_else(|| year_from_name(&value.name));
+
+    CatalogStream {
+        id,
+        login_id,
+        kind,
+        value_id: value.id,
+        category_id: value.category_id,
+        name: value.name,
+        icon: value.icon,
+        added: value.added,
+        rating: value.rating,
+        container_extension: value.container_extension,
+        year,
+        url: value.url,
+        epg_channel_id: value.epg_channel_id.filter(|x| !x.is_empty()),
+    }
+}
+
+fn to_active_model(stream: CatalogStream) -> CatalogStreamActiveModel {
+    CatalogStreamActiveModel::from(stream).reset_all()
+}
+
+async fn fetch(
+    login: &Login,
+    kind: Kind,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<(Vec<Category>, Vec<Value>)> {
+    let categories = get_categories(&kind, Params::new(login), client.clone()).await?;
+    let values = match kind {
+        Kind::Live => get_lives(None, Params::new(login), client).await?,
+        Kind::Movie => get_movies(None, Params::new(login), client).await?,
+        Kind::Serie => get_series(None, Params::new(login), client).await?,
+    };
+
+    Ok((categories, values))
+}
+
+/// Providers often leave `year` empty but name titles like "Movie (2001)".
+fn year_from_name(name: &str) -> Option<i64> {
+    let name = name.trim_end();
+    let start = name.rfind('(')?;
+    let year = name[start + 1..].strip_suffix(')')?;
+
+    if year.len() == 4 {
+        year.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Syncs a kind on first use so logins created before the mirror existed, or
+/// whose background refresh has not run yet, still get a catalog.
+pub async fn ensure_synced(
+    login: &Login,
+    kind: Kind,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<()> {
+    let synced = CatalogSyncEntity::find()
+        .filter(CatalogSyncColumn::LoginId.eq(login.id))
+        .filter(CatalogSyncColumn::Kind.eq(kind))
+        .one(db)
+        .await?;
+
+    if synced.is_none() {
+        sync(login, kind, db, client).await?;
+    }
+
+    Ok(())
+}
+
+/// Every value of a kind on the logins of an account, merged.
+pub async fn get_values(
+    logins: &[Login],
+    kind: Kind,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<Value>> {
+    for login in logins {
+        ensure_synced(login, kind, db, client.clone()).await?;
+    }
+
+    let streams = CatalogStreamEntity::find()
+        .filter(CatalogStreamColumn::LoginId.is_in(logins.iter().map(|x| x.id)))
+        .filter(CatalogStreamColumn::Kind.eq(kind))
+        .order_by_asc(CatalogStreamColumn::LoginId)
+        .order_by_asc(CatalogStreamColumn::Id)
+        .all(db)
+        .await?;
+
+    Ok(merge(streams).into_iter().map(|(_, x)| x).collect())
+}
+
+/// A page of values, sorted and counted in the database for a single login.
+/// Titles of several logins are merged first, so they are sorted in memory.
+pub async fn get_page(
+    logins: &[Login],
+    kind: Kind,
+    category_id: Option<i64>,
+    page: &Page,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<(Vec<Value>, u64)> {
+    for login in logins {
+        ensure_synced(login, kind, db, client.clone()).await?;
+    }
+
+    let mut query = CatalogStreamEntity::find()
+        .filter(CatalogStreamColumn::LoginId.is_in(logins.iter().map(|x| x.id)))
+        .filter(CatalogStreamColumn::Kind.eq(kind));
+
+    if let Some(category_id) = category_id {
+        query = query.filter(CatalogStreamColumn::CategoryId.eq(category_id));
+    }
+
+    let order = match page.order {
+        Some(Direction::Desc) => Order::Desc,
+        Some(Direction::Asc) | None => Order::Asc,
+    };
+
+    if logins.len() > 1 {
+        let streams = query
+            .order_by_asc(CatalogStreamColumn::LoginId)
+            .order_by_asc(CatalogStreamColumn::Id)
+            .all(db)
+            .await?;
+
+        let mut values = merge(streams)
+            .into_iter()
+            .map(|(_, x)| x)
+            .collect::<Vec<Value>>();
+
+        match page.sort {
+            Some(Sort::Name) => values.sort_by(|a, b| a.name.cmp(&b.name)),
+            Some(Sort::Added) => values.sort_by_key(|x| x.added),
+            Some(Sort::Rating) => values.sort_by_key(|x| OrderedFloat(x.rating)),
+            None => {}
+        }
+
+        if let Order::Desc = order {
+            values.reverse();
+        }
+
+        let total = values.len() as u64;
+        let values = values
+            .into_iter()
+            .skip(page.offset.unwrap_or(0) as usize)
+            .take(page.limit.unwrap_or(u64::MAX) as usize)
+            .collect();
+
+        return Ok((values, total));
+    }
+
+    let total = query.clone().count(db).await?;
+
+    query = match page.sort {
+        Some(Sort::Name) => query.order_by(CatalogStreamColumn::Name, order.clone()),
+        Some(Sort::Added) => query.order_by(CatalogStreamColumn::Added, order.clone()),
+        Some(Sort::Rating) => query.order_by(CatalogStreamColumn::Rating, order.clone()),
+        None => query,
+    };
+
+    let values = query
+        .order_by(CatalogStreamColumn::Id, order)
+        .offset(page.offset)
+        .limit(page.limit)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(Value::from)
+        .collect();
+
+    Ok((values, total))
+}
+
+pub async fn get_value(
+    login: &Login,
+    kind: Kind,
+    id: i64,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Value> {
+    ensure_synced(login, kind, db, client).await?;
+
+    CatalogStreamEntity::find()
+        .filter(CatalogStreamColumn::LoginId.eq(login.id))
+        .filter(CatalogStreamColumn::Kind.eq(kind))
+        .filter(CatalogStreamColumn::ValueId.eq(id))
+        .one(db)
+        .await?
+        .map(Value::from)
+        .ok_or(ApiError::WrongId)
+}
+
+pub async fn get_catalog_categories(
+    logins: &[Login],
+    kind: Kind,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<CatalogCategory>> {
+    for login in logins {
+        ensure_synced(login, kind, db, client.clone()).await?;
+    }
+
+    Ok(CatalogCategoryEntity::find()
+        .filter(CatalogCategoryColumn::LoginId.is_in(logins.iter().map(|x| x.id)))
+        .filter(CatalogCategoryColumn::Kind.eq(kind))
+        .order_by_asc(CatalogCategoryColumn::LoginId)
+        .order_by_asc(CatalogCategoryColumn::Id)
+        .all(db)
+        .await?)
+}
+
+/// Folds streams of different logins that are the same title into the first
+/// one, listing the others as its alternatives. Streams must come ordered by
+/// preference, titles repeated within a single login are left alone.
+pub fn merge(streams: Vec<CatalogStream>) -> Vec<(Kind, Value)> {
+    let mut merged: Vec<(Kind, Value)> = Vec::with_capacity(streams.len());
+    let mut titles = HashMap::new();
+
+    for stream in streams {
+        let kind = stream.kind;
+        let value = Value::from(stream);
+
+        let Some(title) = title_key(&value) else {
+            merged.push((kind, value));
+            continue;
+        };
+
+        match titles.entry((kind, title)) {
+            Entry::Occupied(entry) => {
+                let (_, first): &mut (Kind, Value) = &mut merged[*entry.get()];
+
+                if first.source != value.source
+                    && !first.alternatives.iter().any(|x| x.source == value.source)
+                {
+                    first.alternatives.push(Alternative {
+                        source: value.source,
+                        id: value.id,
+                    });
+                    continue;
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(merged.len());
+            }
+        }
+
+        merged.push((kind, value));
+    }
+
+    merged
+}
+
+/// Name without accents, case, punctuation or a trailing "(2001)", along with
+/// the year, so providers spelling the same title a bit differently match.
+fn title_key(value: &Value) -> Option<(String, Option<i64>)> {
+    let year = value.year.or_else(|| year_from_name(&value.name));
+
+    let name = value.name.trim_end();
+    let name = match (year_from_name(name), name.rfind('(')) {
+        (Some(_), Some(start)) => &name[..start],
+        _ => name,
+    };
+
+    let name = fold(name)
+        .chars()
+        .filter(|x| x.is_alphanumeric())
+        .collect::<String>();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some((name, year))
+    }
+}
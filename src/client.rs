@@ -0,0 +1,89 @@
+use rand::Rng;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    api_error::ApiError, cache::Cache, config::UpstreamConfig, hosts::Hosts, metrics::Metrics,
+    playback::Playbacks, xmltv::Guides,
+};
+
+pub struct Client {
+    pub http: reqwest::Client,
+    pub cache: Cache,
+    pub hosts: Hosts,
+    pub guides: Guides,
+    pub playbacks: Playbacks,
+    pub retry: Retry,
+    pub max_body_bytes: u64,
+    /// Deadline of the small json actions, see `UpstreamConfig::timeout_secs`.
+    pub timeout: Duration,
+    pub metrics: Arc<Metrics>,
+}
+
+/// How a failing upstream request is repeated on the same host before moving
+/// on to its mirrors. Every player_api action only reads, so any of them is
+/// safe to send again.
+pub struct Retry {
+    pub retries: u32,
+    backoff: Duration,
+}
+
+impl Retry {
+    pub fn new(config: &UpstreamConfig) -> Retry {
+        Retry {
+            retries: config.retries,
+            backoff: config.retry_backoff(),
+        }
+    }
+
+    /// Delay before the given retry, counting from zero. Exponential with full
+    /// jitter, so clients that failed together do not come back together.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let max = self.backoff.saturating_mul(1 << retry.min(10));
+        let millis = max.as_millis() as u64;
+
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
+
+/// System resolver, like the one reqwest uses by default, whose failures
+/// can be told apart from the ones of connecting.
+pub struct Resolver;
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((name.as_str(), 0))
+                .await
+                .map_err(DnsError)?
+                .collect();
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// A host name that did not resolve.
+#[derive(Debug)]
+pub struct DnsError(pub std::io::Error);
+
+impl std::fmt::Display for DnsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for DnsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Whether a request may succeed if sent again to the same host. A name that
+/// does not resolve, a bad certificate or a client error will not change.
+pub fn is_retryable(error: &ApiError) -> bool {
+    match error {
+        ApiError::Reqwest(_) | ApiError::Timeout(_) => true,
+        ApiError::RequestServerError(status) => *status >= 500 || *status == 429,
+        _ => false,
+    }
+}
@@ -0,0 +1,685 @@
+use serde::Deserialize;
+use std::{collections::HashMap, env, net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::logging::Redacted;
+
+const ENV_PREFIX: &str = "PLAYERAPI_";
+const DEFAULT_CONFIG_FILE: &str = "playerapi.toml";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: Vec<String>,
+    /// Url clients reach PlayerApi at, used in the stream links it hands
+    /// out. Required by the relay and signed links, the host a request
+    /// claims can not be trusted with them.
+    pub public_url: String,
+    pub database_url: String,
+    pub upstream: UpstreamConfig,
+    pub refresh: RefreshConfig,
+    pub retention: RetentionConfig,
+    pub cache: CacheConfig,
+    pub crypto: CryptoConfig,
+    pub session: SessionConfig,
+    pub relay: RelayConfig,
+    pub link: LinkConfig,
+    pub playback: PlaybackConfig,
+    pub metrics: MetricsConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Idle time after which an auth key stops working, every use pushes it
+    /// forward.
+    pub ttl_secs: i64,
+    /// Lifetime of a refresh token, a new one is issued on every refresh.
+    pub refresh_ttl_secs: i64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Links point at PlayerApi, which fetches the stream from the provider,
+    /// so clients never see the provider credentials.
+    pub enabled: bool,
+    /// Lifetime of a relayed link, long enough to watch a whole movie.
+    pub token_ttl_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConfig {
+    /// Links are signed urls of PlayerApi that stop working when they expire
+    /// or their session is removed, so they can be handed to devices without
+    /// the auth key.
+    pub signed: bool,
+    pub ttl_secs: u64,
+    /// Lifetime of the links in exported playlists, which players keep and
+    /// replay for days. Removing the session still revokes them.
+    pub playlist_ttl_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaybackConfig {
+    /// A stream stops counting against the connections of its source when
+    /// neither a link nor a heartbeat came for it in this long.
+    pub idle_secs: u64,
+    /// Heartbeats save the position at most this often, starting, stopping
+    /// and switching streams always save it.
+    pub write_interval_secs: u64,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CryptoConfig {
+    /// Hex encoded 32 byte key, read from `key_file` when empty.
+    pub key: String,
+    pub key_file: PathBuf,
+    /// Keys that may still protect stored rows, they are re-encrypted with
+    /// `key` on startup.
+    pub previous_keys: Vec<String>,
+}
+
+impl std::fmt::Debug for CryptoConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CryptoConfig")
+            .field("key", &Redacted)
+            .field("key_file", &self.key_file)
+            .field("previous_keys", &Redacted)
+            .finish()
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Bearer token scrapers send to `/metrics`, which is not served when it
+    /// is empty.
+    pub token: String,
+}
+
+impl std::fmt::Debug for MetricsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetricsConfig")
+            .field("token", &Redacted)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<LogFormat, ()> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    pub accept_invalid_certs: bool,
+    pub connect_timeout_secs: u64,
+    /// Longest wait for the next bytes of a response.
+    pub read_timeout_secs: u64,
+    /// Longest time a player_api action may take, body included. Stream
+    /// lists, playlists and guides can take longer on slow panels, they
+    /// only have the connect and read timeouts.
+    pub timeout_secs: u64,
+    pub user_agent: String,
+    /// Times a request failing with a timeout, a connection error or a server
+    /// error is repeated on the same host.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every following one.
+    pub retry_backoff_ms: u64,
+    /// Largest response body read from a provider.
+    pub max_body_bytes: u64,
+    /// Failures in a row after which a host is skipped in favor of mirrors.
+    pub host_max_failures: u32,
+    /// Time a failing host is skipped before it is tried again.
+    pub host_cooldown_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RefreshConfig {
+    pub interval_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub watching_days: i64,
+    pub home_days: i64,
+    pub playback_event_days: i64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub backend: CacheBackendKind,
+    pub default_ttl_secs: u64,
+    pub ttl_secs: HashMap<String, u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheBackendKind {
+    None,
+    Memory,
+    Sqlite,
+}
+
+impl std::str::FromStr for CacheBackendKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<CacheBackendKind, ()> {
+        match s {
+            "none" => Ok(CacheBackendKind::None),
+            "memory" => Ok(CacheBackendKind::Memory),
+            "sqlite" => Ok(CacheBackendKind::Sqlite),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: vec!["0.0.0.0:8080".to_string()],
+            public_url: String::new(),
+            database_url: "sqlite://data.db?mode=rwc".to_string(),
+            upstream: UpstreamConfig::default(),
+            refresh: RefreshConfig::default(),
+            retention: RetentionConfig::default(),
+            cache: CacheConfig::default(),
+            crypto: CryptoConfig::default(),
+            session: SessionConfig::default(),
+            relay: RelayConfig::default(),
+            link: LinkConfig::default(),
+            playback: PlaybackConfig::default(),
+            metrics: MetricsConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> UpstreamConfig {
+        UpstreamConfig {
+            accept_invalid_certs: true,
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            timeout_secs: 120,
+            user_agent: format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            retries: 2,
+            retry_backoff_ms: 200,
+            max_body_bytes: 512 * 1024 * 1024,
+            host_max_failures: 2,
+            host_cooldown_secs: 60,
+        }
+    }
+}
+
+impl Default for RefreshConfig {
+    fn default() -> RefreshConfig {
+        RefreshConfig {
+            interval_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> RetentionConfig {
+        RetentionConfig {
+            watching_days: 7,
+            home_days: 30,
+            playback_event_days: 90,
+        }
+    }
+}
+
+impl Default for CryptoConfig {
+    fn default() -> CryptoConfig {
+        CryptoConfig {
+            key: String::new(),
+            key_file: PathBuf::from("playerapi.key"),
+            previous_keys: Vec::new(),
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig {
+            ttl_secs: 30 * 24 * 60 * 60,
+            refresh_ttl_secs: 90 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for RelayConfig {
+    fn default() -> RelayConfig {
+        RelayConfig {
+            enabled: false,
+            token_ttl_secs: 6 * 60 * 60,
+        }
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> LinkConfig {
+        LinkConfig {
+            signed: false,
+            ttl_secs: 6 * 60 * 60,
+            playlist_ttl_secs: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> PlaybackConfig {
+        PlaybackConfig {
+            idle_secs: 120,
+            write_interval_secs: 30,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level: "info,sqlx=warn".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        let ttl_secs = [
+            ("get_live_categories", 60 * 60),
+            ("get_vod_categories", 60 * 60),
+            ("get_series_categories", 60 * 60),
+            ("get_vod_info", 24 * 60 * 60),
+            ("get_series_info", 6 * 60 * 60),
+            ("get_short_epg", 5 * 60),
+            ("get_simple_data_table", 30 * 60),
+            ("xmltv", 30 * 60),
+        ];
+
+        CacheConfig {
+            backend: CacheBackendKind::Memory,
+            default_ttl_secs: 0,
+            ttl_secs: ttl_secs
+                .into_iter()
+                .map(|(action, ttl)| (action.to_string(), ttl))
+                .collect(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Time to live of a cached upstream response, zero disables caching.
+    /// Requests without an action (account info) are never cached, neither
+    /// are stream lists, which are decoded while they arrive.
+    pub fn ttl_for(&self, action: &str) -> u64 {
+        if action.is_empty() {
+            return 0;
+        }
+        self.ttl_secs
+            .get(action)
+            .copied()
+            .unwrap_or(self.default_ttl_secs)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env(String, String),
+    Arg(String),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "could not read {path:?}: {err}"),
+            ConfigError::Parse(path, err) => write!(f, "could not parse {path:?}: {err}"),
+            ConfigError::Env(name, value) => write!(f, "invalid value {value:?} for {name}"),
+            ConfigError::Arg(msg) => write!(f, "{msg}"),
+            ConfigError::Invalid(msg) => write!(f, "invalid configuration: {msg}"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Args {
+    config: Option<PathBuf>,
+    bind: Vec<String>,
+    database_url: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, ConfigError> {
+        let mut result = Args::default();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+
+            let mut value = |name: &str| -> Result<String, ConfigError> {
+                match inline {
+                    Some(value) => Ok(value.to_string()),
+                    None => args
+                        .next()
+                        .ok_or_else(|| ConfigError::Arg(format!("{name} requires a value"))),
+                }
+            };
+
+            match flag.as_str() {
+                "--config" | "-c" => result.config = Some(PathBuf::from(value(&flag)?)),
+                "--bind" | "-b" => result.bind.push(value(&flag)?),
+                "--database-url" => result.database_url = Some(value(&flag)?),
+                "--port" | "-p" => {
+                    let port = value(&flag)?;
+                    result.bind.push(port_to_bind(&port)?);
+                }
+                _ if !arg.starts_with('-') => result.bind.push(port_to_bind(&arg)?),
+                _ => return Err(ConfigError::Arg(format!("unknown argument {arg:?}"))),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+fn port_to_bind(port: &str) -> Result<String, ConfigError> {
+    port.parse::<u16>()
+        .map(|port| format!("0.0.0.0:{port}"))
+        .map_err(|_| ConfigError::Arg(format!("could not parse port {port:?}")))
+}
+
+fn env_var(name: &str) -> Option<(String, String)> {
+    let name = format!("{ENV_PREFIX}{name}");
+    env::var(&name).ok().map(|value| (name, value))
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str, target: &mut T) -> Result<(), ConfigError> {
+    if let Some((name, value)) = env_var(name) {
+        *target = value.parse().map_err(|_| ConfigError::Env(name, value))?;
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Builds the configuration from defaults, the TOML file, `PLAYERAPI_*`
+    /// environment variables and command line flags, in increasing priority.
+    pub fn load() -> Result<Config, ConfigError> {
+        let args = Args::parse(env::args().skip(1))?;
+
+        let path = args
+            .config
+            .clone()
+            .or_else(|| env_var("CONFIG").map(|(_, value)| PathBuf::from(value)));
+
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None if std::path::Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(PathBuf::from(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+
+        config.apply_env()?;
+        config.apply_args(args);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: PathBuf) -> Result<Config, ConfigError> {
+        let text =
+            std::fs::read_to_string(&path).map_err(|err| ConfigError::Read(path.clone(), err))?;
+        toml::from_str(&text).map_err(|err| ConfigError::Parse(path, err))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some((_, value)) = env_var("BIND") {
+            self.bind = value.split(',').map(|x| x.trim().to_string()).collect();
+        }
+        if let Some((_, value)) = env_var("DATABASE_URL") {
+            self.database_url = value;
+        }
+        if let Some((_, value)) = env_var("LOG_LEVEL") {
+            self.log.level = value;
+        }
+        if let Some((_, value)) = env_var("UPSTREAM_USER_AGENT") {
+            self.upstream.user_agent = value;
+        }
+        if let Some((_, value)) = env_var("PUBLIC_URL") {
+            self.public_url = value;
+        }
+        if let Some((_, value)) = env_var("METRICS_TOKEN") {
+            self.metrics.token = value;
+        }
+        if let Some((_, value)) = env_var("CRYPTO_KEY") {
+            self.crypto.key = value;
+        }
+        if let Some((_, value)) = env_var("CRYPTO_KEY_FILE") {
+            self.crypto.key_file = PathBuf::from(value);
+        }
+        if let Some((_, value)) = env_var("CRYPTO_PREVIOUS_KEYS") {
+            self.crypto.previous_keys = value.split(',').map(|x| x.trim().to_string()).collect();
+        }
+
+        env_parse(
+            "UPSTREAM_ACCEPT_INVALID_CERTS",
+            &mut self.upstream.accept_invalid_certs,
+        )?;
+        env_parse(
+            "UPSTREAM_CONNECT_TIMEOUT_SECS",
+            &mut self.upstream.connect_timeout_secs,
+        )?;
+        env_parse(
+            "UPSTREAM_READ_TIMEOUT_SECS",
+            &mut self.upstream.read_timeout_secs,
+        )?;
+        env_parse("UPSTREAM_TIMEOUT_SECS", &mut self.upstream.timeout_secs)?;
+        env_parse("UPSTREAM_RETRIES", &mut self.upstream.retries)?;
+        env_parse("UPSTREAM_MAX_BODY_BYTES", &mut self.upstream.max_body_bytes)?;
+        env_parse(
+            "UPSTREAM_RETRY_BACKOFF_MS",
+            &mut self.upstream.retry_backoff_ms,
+        )?;
+        env_parse(
+            "UPSTREAM_HOST_MAX_FAILURES",
+            &mut self.upstream.host_max_failures,
+        )?;
+        env_parse(
+            "UPSTREAM_HOST_COOLDOWN_SECS",
+            &mut self.upstream.host_cooldown_secs,
+        )?;
+        env_parse("RELAY_ENABLED", &mut self.relay.enabled)?;
+        env_parse("RELAY_TOKEN_TTL_SECS", &mut self.relay.token_ttl_secs)?;
+        env_parse("LINK_SIGNED", &mut self.link.signed)?;
+        env_parse("LINK_TTL_SECS", &mut self.link.ttl_secs)?;
+        env_parse("LINK_PLAYLIST_TTL_SECS", &mut self.link.playlist_ttl_secs)?;
+        env_parse("PLAYBACK_IDLE_SECS", &mut self.playback.idle_secs)?;
+        env_parse(
+            "PLAYBACK_WRITE_INTERVAL_SECS",
+            &mut self.playback.write_interval_secs,
+        )?;
+        env_parse("REFRESH_INTERVAL_SECS", &mut self.refresh.interval_secs)?;
+        env_parse("RETENTION_WATCHING_DAYS", &mut self.retention.watching_days)?;
+        env_parse("RETENTION_HOME_DAYS", &mut self.retention.home_days)?;
+        env_parse(
+            "RETENTION_PLAYBACK_EVENT_DAYS",
+            &mut self.retention.playback_event_days,
+        )?;
+        env_parse("CACHE_BACKEND", &mut self.cache.backend)?;
+        env_parse("LOG_FORMAT", &mut self.log.format)?;
+        env_parse("CACHE_DEFAULT_TTL_SECS", &mut self.cache.default_ttl_secs)?;
+        env_parse("SESSION_TTL_SECS", &mut self.session.ttl_secs)?;
+        env_parse(
+            "SESSION_REFRESH_TTL_SECS",
+            &mut self.session.refresh_ttl_secs,
+        )?;
+
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: Args) {
+        if !args.bind.is_empty() {
+            self.bind = args.bind;
+        }
+        if let Some(database_url) = args.database_url {
+            self.database_url = database_url;
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.bind.is_empty() {
+            return Err(ConfigError::Invalid(
+                "at least one bind address is required".into(),
+            ));
+        }
+        for bind in &self.bind {
+            bind.parse::<SocketAddr>().map_err(|_| {
+                ConfigError::Invalid(format!("bind address {bind:?} is not ip:port"))
+            })?;
+        }
+        if self.database_url.is_empty() {
+            return Err(ConfigError::Invalid("database_url is empty".into()));
+        }
+        if self.upstream.user_agent.is_empty() {
+            return Err(ConfigError::Invalid("upstream.user_agent is empty".into()));
+        }
+        if self.upstream.connect_timeout_secs == 0
+            || self.upstream.read_timeout_secs == 0
+            || self.upstream.timeout_secs == 0
+        {
+            return Err(ConfigError::Invalid(
+                "upstream timeouts must be positive".into(),
+            ));
+        }
+        if self.upstream.max_body_bytes == 0 {
+            return Err(ConfigError::Invalid(
+                "upstream.max_body_bytes must be positive".into(),
+            ));
+        }
+        if self.upstream.host_max_failures == 0 {
+            return Err(ConfigError::Invalid(
+                "upstream.host_max_failures must be positive".into(),
+            ));
+        }
+        if self.refresh.interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "refresh.interval_secs must be positive".into(),
+            ));
+        }
+        if self.retention.watching_days <= 0
+            || self.retention.home_days <= 0
+            || self.retention.playback_event_days <= 0
+        {
+            return Err(ConfigError::Invalid(
+                "retention windows must be positive".into(),
+            ));
+        }
+        if self.session.ttl_secs <= 0 || self.session.refresh_ttl_secs <= 0 {
+            return Err(ConfigError::Invalid(
+                "session lifetimes must be positive".into(),
+            ));
+        }
+        if self.relay.token_ttl_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "relay.token_ttl_secs must be positive".into(),
+            ));
+        }
+        if self.link.ttl_secs == 0 || self.link.playlist_ttl_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "link lifetimes must be positive".into(),
+            ));
+        }
+        if self.playback.idle_secs == 0 || self.playback.write_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "playback intervals must be positive".into(),
+            ));
+        }
+        if !self.public_url.is_empty() && url::Url::parse(&self.public_url).is_err() {
+            return Err(ConfigError::Invalid("public_url is not a valid url".into()));
+        }
+        if self.public_url.is_empty() && (self.relay.enabled || self.link.signed) {
+            return Err(ConfigError::Invalid(
+                "public_url is required by the relay and signed links".into(),
+            ));
+        }
+        if !self.crypto.key.is_empty() && !is_hex_key(&self.crypto.key) {
+            return Err(ConfigError::Invalid(
+                "crypto.key must be 64 hex characters".into(),
+            ));
+        }
+        if !self.crypto.previous_keys.iter().all(|x| is_hex_key(x)) {
+            return Err(ConfigError::Invalid(
+                "crypto.previous_keys must be 64 hex characters each".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn bind_addrs(&self) -> Vec<SocketAddr> {
+        self.bind.iter().filter_map(|x| x.parse().ok()).collect()
+    }
+}
+
+fn is_hex_key(key: &str) -> bool {
+    key.len() == 64 && key.bytes().all(|x| x.is_ascii_hexdigit())
+}
+
+impl UpstreamConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn retry_backoff(&self) -> Duration {
+        Duration::from_millis(self.retry_backoff_ms)
+    }
+}
+
+impl RefreshConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+}
@@ -0,0 +1,692 @@
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sea_orm::{
+    ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+use std::io::{Error, ErrorKind};
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    config::CryptoConfig,
+    entities::prelude::*,
+    session::token_prefix,
+};
+
+const PREFIX: &str = "enc2";
+/// Passwords sealed by older versions, bound to the server and username.
+const LEGACY_PREFIX: &str = "enc1";
+const NONCE_LEN: usize = 24;
+
+struct Key {
+    id: String,
+    cipher: XChaCha20Poly1305,
+    mac: Hmac<Sha256>,
+    signer: Hmac<Sha256>,
+}
+
+impl Key {
+    fn from_hex(key: &str) -> Result<Key, Error> {
+        let bytes =
+            hex::decode(key.trim()).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+
+        if bytes.len() != 32 {
+            return Err(Error::new(ErrorKind::InvalidData, "key must be 32 bytes"));
+        }
+
+        let digest = Sha256::new()
+            .chain_update(b"playerapi login key")
+            .chain_update(&bytes)
+            .finalize();
+
+        // Tokens are hashed with their own key, so the cipher key is never
+        // used for anything else.
+        let mac_key = Sha256::new()
+            .chain_update(b"playerapi token key")
+            .chain_update(&bytes)
+            .finalize();
+
+        let signer_key = Sha256::new()
+            .chain_update(b"playerapi link key")
+            .chain_update(&bytes)
+            .finalize();
+
+        Ok(Key {
+            id: hex::encode(&digest[..4]),
+            cipher: XChaCha20Poly1305::new(bytes.as_slice().into()),
+            mac: <Hmac<Sha256> as Mac>::new_from_slice(&mac_key)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?,
+            signer: <Hmac<Sha256> as Mac>::new_from_slice(&signer_key)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?,
+        })
+    }
+
+    fn mac(&self, token: &str) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(token.as_bytes());
+        mac
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenMatch {
+    No,
+    Current,
+    /// Matched a hash made with a previous key, it should be hashed again.
+    Previous,
+}
+
+/// Seals provider passwords before they are stored. Values carry the id of the
+/// key that sealed them so rows written under a previous key stay readable
+/// until they are rotated.
+pub struct Crypto {
+    current: Key,
+    previous: Vec<Key>,
+}
+
+impl Crypto {
+    pub fn load(config: &CryptoConfig) -> Result<Crypto, Error> {
+        let current = if config.key.is_empty() {
+            Key::from_hex(&read_or_create_key_file(config)?)?
+        } else {
+            Key::from_hex(&config.key)?
+        };
+
+        let previous = config
+            .previous_keys
+            .iter()
+            .map(|x| Key::from_hex(x))
+            .collect::<Result<Vec<Key>, Error>>()?;
+
+        Ok(Crypto { current, previous })
+    }
+
+    pub fn encrypt(&self, plaintext: &str, aad: &str) -> ApiResult<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.current.cipher.encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: aad.as_bytes(),
+            },
+        )?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+
+        Ok(format!(
+            "{PREFIX}:{}:{}",
+            self.current.id,
+            hex::encode(sealed)
+        ))
+    }
+
+    pub fn decrypt(&self, value: &str, aad: &str) -> ApiResult<String> {
+        let mut parts = value.splitn(3, ':');
+
+        let (Some(PREFIX | LEGACY_PREFIX), Some(id), Some(sealed)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ApiError::Crypto);
+        };
+
+        let key = std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|x| x.id == id)
+            .ok_or(ApiError::Crypto)?;
+
+        let sealed = hex::decode(sealed).map_err(|_| ApiError::Crypto)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(ApiError::Crypto);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+        let plaintext = key.cipher.decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )?;
+
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    fn is_current(&self, value: &str) -> bool {
+        value
+            .strip_prefix(PREFIX)
+            .and_then(|x| x.strip_prefix(':'))
+            .and_then(|x| x.strip_prefix(self.current.id.as_str()))
+            .is_some_and(|x| x.starts_with(':'))
+    }
+
+    /// Keyed hash stored in place of a bearer token, so a copy of the database
+    /// does not hand out working tokens.
+    pub fn hash_token(&self, token: &str) -> String {
+        hex::encode(self.current.mac(token).finalize().into_bytes())
+    }
+
+    /// Checks a token against a stored hash in constant time.
+    pub fn verify_token(&self, token: &str, hash: &str) -> TokenMatch {
+        let Ok(hash) = hex::decode(hash) else {
+            return TokenMatch::No;
+        };
+
+        if self.current.mac(token).verify_slice(&hash).is_ok() {
+            return TokenMatch::Current;
+        }
+
+        if self
+            .previous
+            .iter()
+            .any(|x| x.mac(token).verify_slice(&hash).is_ok())
+        {
+            return TokenMatch::Previous;
+        }
+
+        TokenMatch::No
+    }
+
+    /// Seals a payload into a token that can be put in a url. Clients can not
+    /// read it nor forge one, `purpose` keeps tokens of one kind from being
+    /// accepted as another.
+    pub fn seal_token(&self, payload: &str, purpose: &str) -> ApiResult<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.current.cipher.encrypt(
+            &nonce,
+            Payload {
+                msg: payload.as_bytes(),
+                aad: purpose.as_bytes(),
+            },
+        )?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+
+        Ok(format!(
+            "{}.{}",
+            self.current.id,
+            BASE64_URL_SAFE_NO_PAD.encode(sealed)
+        ))
+    }
+
+    pub fn open_token(&self, token: &str, purpose: &str) -> ApiResult<String> {
+        let (id, sealed) = token.split_once('.').ok_or(ApiError::Crypto)?;
+
+        let key = std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|x| x.id == id)
+            .ok_or(ApiError::Crypto)?;
+
+        let sealed = BASE64_URL_SAFE_NO_PAD
+            .decode(sealed)
+            .map_err(|_| ApiError::Crypto)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(ApiError::Crypto);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+        let plaintext = key.cipher.decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: purpose.as_bytes(),
+            },
+        )?;
+
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    /// Signature of a message handed out in a url, readable by anyone but
+    /// only made by the server.
+    pub fn sign(&self, message: &str) -> String {
+        let mut signer = self.current.signer.clone();
+        signer.update(message.as_bytes());
+
+        format!(
+            "{}.{}",
+            self.current.id,
+            BASE64_URL_SAFE_NO_PAD.encode(signer.finalize().into_bytes())
+        )
+    }
+
+    /// Checks a signature made by `sign` in constant time.
+    pub fn verify_signature(&self, message: &str, signature: &str) -> bool {
+        let Some((id, signature)) = signature.split_once('.') else {
+            return false;
+        };
+        let Some(key) = std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|x| x.id == id)
+        else {
+            return false;
+        };
+        let Ok(signature) = BASE64_URL_SAFE_NO_PAD.decode(signature) else {
+            return false;
+        };
+
+        let mut signer = key.signer.clone();
+        signer.update(message.as_bytes());
+        signer.verify_slice(&signature).is_ok()
+    }
+
+    pub fn seal_login(&self, mut login: Login) -> ApiResult<Login> {
+        login.password = self.encrypt(&login.password, &login_aad(&login))?;
+        Ok(login)
+    }
+
+    pub fn open_login(&self, mut login: Login) -> ApiResult<Login> {
+        let aad = match is_legacy(&login.password) {
+            true => legacy_login_aad(&login),
+            false => login_aad(&login),
+        };
+        login.password = self.decrypt(&login.password, &aad)?;
+        Ok(login)
+    }
+}
+
+/// Binds a sealed password to its row, so it can not be copied to another
+/// one. The id never changes, unlike the server of the row.
+fn login_aad(login: &Login) -> String {
+    format!("login {}", login.id)
+}
+
+fn legacy_login_aad(login: &Login) -> String {
+    format!("{}\n{}", login.server, login.username)
+}
+
+fn is_legacy(value: &str) -> bool {
+    value
+        .strip_prefix(LEGACY_PREFIX)
+        .is_some_and(|x| x.starts_with(':'))
+}
+
+fn is_sealed(value: &str) -> bool {
+    is_legacy(value)
+        || value
+            .strip_prefix(PREFIX)
+            .is_some_and(|x| x.starts_with(':'))
+}
+
+fn read_or_create_key_file(config: &CryptoConfig) -> Result<String, Error> {
+    match std::fs::read_to_string(&config.key_file) {
+        Ok(key) => Ok(key),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let mut bytes = [0u8; 32];
+            rand::rngs::OsRng.try_fill_bytes(&mut bytes)?;
+            let key = hex::encode(bytes);
+
+            std::fs::write(&config.key_file, &key)?;
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&config.key_file, std::fs::Permissions::from_mode(0o600))?;
+            }
+
+            tracing::warn!(key_file = ?config.key_file, "generated a new credentials key");
+
+            Ok(key)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Seals plain text passwords left by older versions and moves rows sealed
+/// with a previous key to the current one. Fails without changing anything
+/// when a sealed password does not open.
+pub async fn reencrypt_logins(db: &DatabaseConnection, crypto: &Crypto) -> ApiResult<u64> {
+    let txn = db.begin().await?;
+    let mut count = 0;
+
+    for login in LoginEntity::find().all(&txn).await? {
+        if crypto.is_current(&login.password) {
+            continue;
+        }
+
+        // A sealed value that does not open was sealed with a key that is
+        // no longer configured. Sealing it again would lose the password.
+        let login = match is_sealed(&login.password) {
+            true => crypto.open_login(login.clone()).inspect_err(|_| {
+                tracing::error!(
+                    login = login.id,
+                    "password does not open with any configured key"
+                );
+            })?,
+            false => login,
+        };
+        let login = crypto.seal_login(login)?;
+
+        LoginEntity::update(LoginActiveModel {
+            id: ActiveValue::Set(login.id),
+            password: ActiveValue::Set(login.password),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await?;
+
+        count += 1;
+    }
+
+    txn.commit().await?;
+
+    Ok(count)
+}
+
+/// Hashes tokens left in plain text by older versions. Clients keep using the
+/// same tokens, only what is stored changes.
+pub async fn hash_session_tokens(db: &DatabaseConnection, crypto: &Crypto) -> ApiResult<u64> {
+    let txn = db.begin().await?;
+    let mut count = 0;
+
+    for session in SessionEntity::find()
+        .filter(SessionColumn::AuthPrefix.eq(""))
+        .all(&txn)
+        .await?
+    {
+        SessionEntity::update(SessionActiveModel {
+            id: ActiveValue::Set(session.id),
+            auth_prefix: ActiveValue::Set(token_prefix(&session.auth_key).to_string()),
+            auth_key: ActiveValue::Set(crypto.hash_token(&session.auth_key)),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await?;
+
+        count += 1;
+    }
+
+    for session in SessionEntity::find()
+        .filter(SessionColumn::RefreshPrefix.is_null())
+        .filter(SessionColumn::RefreshToken.is_not_null())
+        .all(&txn)
+        .await?
+    {
+        let refresh_token = session.refresh_token.unwrap_or_default();
+
+        SessionEntity::update(SessionActiveModel {
+            id: ActiveValue::Set(session.id),
+            refresh_prefix: ActiveValue::Set(Some(token_prefix(&refresh_token).to_string())),
+            refresh_token: ActiveValue::Set(Some(crypto.hash_token(&refresh_token))),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await?;
+
+        count += 1;
+    }
+
+    txn.commit().await?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrator::Migrator;
+    use sea_orm::Database;
+    use sea_orm_migration::MigratorTrait;
+
+    const OLD_KEY: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const NEW_KEY: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+
+    fn crypto(key: &str, previous_keys: &[&str]) -> Crypto {
+        Crypto::load(&CryptoConfig {
+            key: key.to_string(),
+            previous_keys: previous_keys.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn login(password: &str) -> Login {
+        Login {
+            id: 7,
+            account_id: 1,
+            server: "http://example.com/player_api.php".to_string(),
+            mirrors: Default::default(),
+            backend: Default::default(),
+            epg_url: None,
+            username: "user".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn seals_and_opens_logins() {
+        let crypto = crypto(NEW_KEY, &[]);
+
+        let sealed = crypto.seal_login(login("secret")).unwrap();
+        assert_ne!(sealed.password, "secret");
+        assert!(crypto.is_current(&sealed.password));
+
+        assert_eq!(crypto.open_login(sealed).unwrap().password, "secret");
+    }
+
+    #[test]
+    fn sealed_passwords_are_bound_to_their_row() {
+        let crypto = crypto(NEW_KEY, &[]);
+        let sealed = crypto.seal_login(login("secret")).unwrap();
+
+        let mut moved = sealed.clone();
+        moved.server = "http://other.com/player_api.php".to_string();
+        assert_eq!(crypto.open_login(moved).unwrap().password, "secret");
+
+        let mut copied = sealed;
+        copied.id = 8;
+        assert!(crypto.open_login(copied).is_err());
+    }
+
+    #[test]
+    fn opens_legacy_passwords() {
+        let crypto = crypto(NEW_KEY, &[]);
+        let legacy = login("secret");
+        let sealed = crypto
+            .encrypt("secret", &legacy_login_aad(&legacy))
+            .unwrap()
+            .replacen(PREFIX, LEGACY_PREFIX, 1);
+
+        assert!(!crypto.is_current(&sealed));
+        assert_eq!(
+            crypto.open_login(login(&sealed)).unwrap().password,
+            "secret"
+        );
+    }
+
+    #[test]
+    fn opens_values_of_previous_keys() {
+        let old = crypto(OLD_KEY, &[]);
+        let sealed = old.seal_login(login("secret")).unwrap();
+        let token = old.seal_token("payload", "stream").unwrap();
+        let signature = old.sign("message");
+
+        let rotated = crypto(NEW_KEY, &[OLD_KEY]);
+        assert!(!rotated.is_current(&sealed.password));
+        assert_eq!(
+            rotated.open_login(sealed.clone()).unwrap().password,
+            "secret"
+        );
+        assert_eq!(rotated.open_token(&token, "stream").unwrap(), "payload");
+        assert!(rotated.verify_signature("message", &signature));
+
+        let forgotten = crypto(NEW_KEY, &[]);
+        assert!(forgotten.open_login(sealed).is_err());
+        assert!(forgotten.open_token(&token, "stream").is_err());
+        assert!(!forgotten.verify_signature("message", &signature));
+    }
+
+    #[test]
+    fn tokens_only_open_for_their_purpose() {
+        let crypto = crypto(NEW_KEY, &[]);
+        let token = crypto.seal_token("payload", "stream").unwrap();
+
+        assert_eq!(crypto.open_token(&token, "stream").unwrap(), "payload");
+        assert!(crypto.open_token(&token, "other").is_err());
+        assert!(crypto
+            .open_token(&token.replace('.', "x"), "stream")
+            .is_err());
+    }
+
+    #[test]
+    fn signatures_cover_the_message() {
+        let crypto = crypto(NEW_KEY, &[]);
+        let signature = crypto.sign("play/movie/1/mp4?expires=10");
+
+        assert!(crypto.verify_signature("play/movie/1/mp4?expires=10", &signature));
+        assert!(!crypto.verify_signature("play/movie/1/mp4?expires=99", &signature));
+        assert!(!crypto.verify_signature("play/movie/1/mp4?expires=10", "nope"));
+    }
+
+    #[test]
+    fn hashes_tokens_with_current_and_previous_keys() {
+        let old = crypto(OLD_KEY, &[]);
+        let rotated = crypto(NEW_KEY, &[OLD_KEY]);
+
+        let hash = rotated.hash_token("token");
+        assert_ne!(hash, "token");
+        assert_eq!(rotated.verify_token("token", &hash), TokenMatch::Current);
+        assert_eq!(rotated.verify_token("other", &hash), TokenMatch::No);
+        assert_eq!(
+            rotated.verify_token("token", &old.hash_token("token")),
+            TokenMatch::Previous
+        );
+        assert_eq!(rotated.verify_token("token", "not hex"), TokenMatch::No);
+    }
+
+    #[actix_web::test]
+    async fn reencrypts_plain_and_rotated_passwords() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let account = AccountEntity::insert(AccountActiveModel {
+            id: ActiveValue::NotSet,
+            created: ActiveValue::Set(0),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let old = crypto(OLD_KEY, &[]);
+        let mut rotated = login("rotated");
+        rotated.id = 8;
+
+        let passwords = [
+            "plain".to_string(),
+            old.seal_login(rotated).unwrap().password,
+        ];
+        for (id, password) in (7..).zip(passwords) {
+            let mut row = login(&password);
+            row.id = id;
+            row.account_id = account.last_insert_id;
+            LoginEntity::insert(LoginActiveModel::from(row))
+                .exec(&db)
+                .await
+                .unwrap();
+        }
+
+        let crypto = crypto(NEW_KEY, &[OLD_KEY]);
+        assert_eq!(reencrypt_logins(&db, &crypto).await.unwrap(), 2);
+
+        let opened: Vec<String> = LoginEntity::find()
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .inspect(|x| assert!(crypto.is_current(&x.password)))
+            .map(|x| crypto.open_login(x).unwrap().password)
+            .collect();
+        assert_eq!(opened, ["plain", "rotated"]);
+    }
+
+    #[actix_web::test]
+    async fn keeps_passwords_sealed_with_unknown_keys() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let account = AccountEntity::insert(AccountActiveModel {
+            id: ActiveValue::NotSet,
+            created: ActiveValue::Set(0),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let mut lost = login("lost");
+        lost.id = 7;
+        lost.account_id = account.last_insert_id;
+        let lost = crypto(OLD_KEY, &[]).seal_login(lost).unwrap();
+        let mut plain = login("plain");
+        plain.id = 8;
+        plain.account_id = account.last_insert_id;
+        for row in [lost.clone(), plain] {
+            LoginEntity::insert(LoginActiveModel::from(row))
+                .exec(&db)
+                .await
+                .unwrap();
+        }
+
+        assert!(reencrypt_logins(&db, &crypto(NEW_KEY, &[])).await.is_err());
+
+        let passwords: Vec<String> = LoginEntity::find()
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.password)
+            .collect();
+        assert_eq!(passwords, [lost.password, "plain".to_string()]);
+    }
+
+    #[actix_web::test]
+    async fn hashes_plain_session_tokens() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let account = AccountEntity::insert(AccountActiveModel {
+            id: ActiveValue::NotSet,
+            created: ActiveValue::Set(0),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        SessionEntity::insert(SessionActiveModel {
+            id: ActiveValue::NotSet,
+            account_id: ActiveValue::Set(account.last_insert_id),
+            auth_prefix: ActiveValue::Set(String::new()),
+            auth_key: ActiveValue::Set("0123456789abcdef".to_string()),
+            device: ActiveValue::Set(String::new()),
+            created: ActiveValue::Set(0),
+            last_used: ActiveValue::Set(0),
+            expires: ActiveValue::Set(0),
+            refresh_prefix: ActiveValue::Set(None),
+            refresh_token: ActiveValue::Set(Some("fedcba9876543210".to_string())),
+            refresh_expires: ActiveValue::Set(0),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let crypto = crypto(NEW_KEY, &[]);
+        assert_eq!(hash_session_tokens(&db, &crypto).await.unwrap(), 2);
+        assert_eq!(hash_session_tokens(&db, &crypto).await.unwrap(), 0);
+
+        let session = SessionEntity::find().one(&db).await.unwrap().unwrap();
+        assert_eq!(session.auth_prefix, "01234567");
+        assert_eq!(
+            crypto.verify_token("0123456789abcdef", &session.auth_key),
+            TokenMatch::Current
+        );
+        assert_eq!(session.refresh_prefix.as_deref(), Some("fedcba98"));
+        assert_eq!(
+            crypto.verify_token("fedcba9876543210", &session.refresh_token.unwrap()),
+            TokenMatch::Current
+        );
+    }
+}
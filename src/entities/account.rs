@@ -0,0 +1,50 @@
+use sea_orm::entity::prelude::*;
+
+/// Owner of the logins and everything saved for it. Sessions are only tokens
+/// pointing here, so ending them leaves avatars and progress in place.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "account")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+
+    pub created: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::session::Entity")]
+    Session,
+    #[sea_orm(has_many = "super::login::Entity")]
+    Login,
+    #[sea_orm(has_many = "super::avatar::Entity")]
+    Avatar,
+    #[sea_orm(has_many = "super::home::Entity")]
+    Home,
+}
+
+impl Related<super::session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl Related<super::login::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Login.def()
+    }
+}
+
+impl Related<super::avatar::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Avatar.def()
+    }
+}
+
+impl Related<super::home::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Home.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
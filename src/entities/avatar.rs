@@ -1,14 +1,16 @@
 use sea_orm::entity::prelude::*;
 use serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, ToSchema, Serialize)]
+#[schema(as = Avatar)]
 #[sea_orm(table_name = "avatar")]
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i64,
 
     #[serde(skip_serializing)]
-    pub session_id: i64,
+    pub account_id: i64,
 
     pub name: String,
 }
@@ -16,17 +18,17 @@ pub struct Model {
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(
-        belongs_to = "super::session::Entity",
-        from = "Column::SessionId",
-        to = "super::session::Column::Id",
+        belongs_to = "super::account::Entity",
+        from = "Column::AccountId",
+        to = "super::account::Column::Id",
         on_delete = "Cascade"
     )]
-    Session,
+    Account,
 }
 
-impl Related<super::session::Entity> for Entity {
+impl Related<super::account::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::Session.def()
+        Relation::Account.def()
     }
 }
 
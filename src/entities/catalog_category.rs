@@ -0,0 +1,45 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+pub use super::catalog_stream::Kind;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, ToSchema, Serialize)]
+#[schema(as = CatalogCategory)]
+#[sea_orm(table_name = "catalog_category")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_serializing)]
+    pub id: i64,
+
+    /// Login the category belongs to.
+    #[serde(rename = "source")]
+    pub login_id: i64,
+
+    #[serde(skip_serializing)]
+    pub kind: Kind,
+
+    #[serde(rename = "id")]
+    pub category_id: i64,
+
+    pub name: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::login::Entity",
+        from = "Column::LoginId",
+        to = "super::login::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Login,
+}
+
+impl Related<super::login::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Login.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,80 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Clone, Debug, PartialEq, Default, DeriveEntityModel, ToSchema, Serialize)]
+#[schema(as = CatalogStream)]
+#[sea_orm(table_name = "catalog_stream")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_serializing)]
+    pub id: i64,
+
+    #[serde(skip_serializing)]
+    pub login_id: i64,
+
+    #[serde(skip_serializing)]
+    pub kind: Kind,
+
+    pub value_id: i64,
+    pub category_id: Option<i64>,
+    pub name: String,
+    pub icon: String,
+    pub added: i64,
+    pub rating: f64,
+    pub container_extension: String,
+    pub year: Option<i64>,
+
+    /// Where to play the stream, only known for logins read from a playlist.
+    #[serde(skip_serializing)]
+    pub url: Option<String>,
+
+    pub epg_channel_id: Option<String>,
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    Default,
+    EnumIter,
+    DeriveActiveEnum,
+    ToSchema,
+    Deserialize,
+    Serialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    #[default]
+    #[sea_orm(string_value = "Live")]
+    Live,
+
+    #[sea_orm(string_value = "Movie")]
+    Movie,
+
+    #[sea_orm(string_value = "Serie")]
+    Serie,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::login::Entity",
+        from = "Column::LoginId",
+        to = "super::login::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Login,
+}
+
+impl Related<super::login::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Login.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,7 +1,9 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Default, DeriveEntityModel, Serialize)]
+#[derive(Clone, Debug, PartialEq, Default, DeriveEntityModel, ToSchema, Serialize)]
+#[schema(as = Favorite)]
 #[sea_orm(table_name = "favorite")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -11,6 +13,10 @@ pub struct Model {
     #[serde(skip_serializing)]
     pub avatar_id: i64,
 
+    /// Login the value id belongs to.
+    #[serde(rename = "source")]
+    pub login_id: i64,
+
     #[serde(skip_serializing)]
     pub kind: Kind,
 
@@ -19,7 +25,10 @@ pub struct Model {
     pub icon: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Default, EnumIter, DeriveActiveEnum, Deserialize, Serialize)]
+#[derive(
+    Clone, Debug, PartialEq, Default, EnumIter, DeriveActiveEnum, ToSchema, Deserialize, Serialize,
+)]
+#[schema(as = FavoriteKind)]
 #[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
 #[serde(rename_all = "snake_case")]
 pub enum Kind {
@@ -43,6 +52,13 @@ pub enum Relation {
 		on_delete = "Cascade"
     )]
     Avatar,
+    #[sea_orm(
+        belongs_to = "super::login::Entity",
+        from = "Column::LoginId",
+        to = "super::login::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Login,
 }
 
 impl Related<super::avatar::Entity> for Entity {
@@ -51,4 +67,10 @@ impl Related<super::avatar::Entity> for Entity {
     }
 }
 
+impl Related<super::login::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Login.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
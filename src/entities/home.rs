@@ -1,7 +1,9 @@
 use sea_orm::entity::prelude::*;
 use serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Default, DeriveEntityModel, Serialize)]
+#[derive(Clone, Debug, PartialEq, Default, DeriveEntityModel, ToSchema, Serialize)]
+#[schema(as = Home)]
 #[sea_orm(table_name = "home")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -9,7 +11,11 @@ pub struct Model {
     pub id: i64,
 
     #[serde(skip_serializing)]
-    pub session_id: i64,
+    pub account_id: i64,
+
+    /// Login the value id belongs to.
+    #[serde(rename = "source")]
+    pub login_id: i64,
 
     #[serde(skip_serializing_if = "Kind::is_normal")]
     pub kind: Kind,
@@ -19,7 +25,8 @@ pub struct Model {
     pub icon: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Default, EnumIter, DeriveActiveEnum, Serialize)]
+#[derive(Clone, Debug, PartialEq, Default, EnumIter, DeriveActiveEnum, ToSchema, Serialize)]
+#[schema(as = HomeKind)]
 #[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
 #[serde(rename_all = "snake_case")]
 pub enum Kind {
@@ -53,17 +60,30 @@ impl Kind {
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(
-        belongs_to = "super::session::Entity",
-        from = "Column::SessionId",
-        to = "super::session::Column::Id"
+        belongs_to = "super::account::Entity",
+        from = "Column::AccountId",
+        to = "super::account::Column::Id"
 		on_delete = "Cascade"
     )]
-    Session,
+    Account,
+    #[sea_orm(
+        belongs_to = "super::login::Entity",
+        from = "Column::LoginId",
+        to = "super::login::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Login,
+}
+
+impl Related<super::account::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Account.def()
+    }
 }
 
-impl Related<super::session::Entity> for Entity {
+impl Related<super::login::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::Session.def()
+        Relation::Login.def()
     }
 }
 
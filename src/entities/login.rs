@@ -1,44 +1,118 @@
-use sea_orm::entity::prelude::*;
+use sea_orm::{entity::prelude::*, FromJsonQueryResult};
 use serde::{Deserialize, Serialize};
-//use utoipa::{IntoParams, ToSchema};
+use utoipa::ToSchema;
 
-#[derive(
-    Clone,
-    Debug,
-    PartialEq,
-    Eq,
-    DeriveEntityModel,
-    /*ToSchema, IntoParams,*/ Deserialize,
-    Serialize,
-)]
+use crate::logging::Redacted;
+
+#[derive(Clone, PartialEq, Eq, DeriveEntityModel, ToSchema, Deserialize, Serialize)]
+#[schema(as = Login)]
 #[sea_orm(table_name = "login")]
 pub struct Model {
     #[sea_orm(primary_key)]
     #[serde(skip_serializing, skip_deserializing)]
     pub id: i64,
 
-    //#[schema(example = "https://limetv.me", required = true)]
+    #[serde(skip_serializing, skip_deserializing)]
+    pub account_id: i64,
+
+    #[schema(example = "https://limetv.me", required = true)]
     pub server: String,
-    //#[schema(example = "teste123", required = true)]
+    #[schema(example = "teste123", required = true)]
     pub username: String,
-    //#[schema(example = "!@123098@!", required = true)]
+    #[schema(example = "!@123098@!", required = true)]
     pub password: String,
+
+    /// Other hosts of the same panel, tried when `server` is down.
+    #[schema(value_type = Vec<String>, example = json!(["http://limetv.xyz:8080"]))]
+    #[sea_orm(column_type = "Json")]
+    #[serde(default, skip_serializing)]
+    pub mirrors: Mirrors,
+
+    /// How the catalog of the login is read.
+    #[serde(default, skip_serializing)]
+    pub backend: Backend,
+
+    /// XMLTV guide of an `m3u` login. Panels serve it as `xmltv.php`, and
+    /// plain playlists often name it in their header, so it is rarely needed.
+    #[schema(example = "http://limetv.xyz:8080/epg.xml")]
+    #[serde(default, skip_serializing)]
+    pub epg_url: Option<String>,
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Default,
+    EnumIter,
+    DeriveActiveEnum,
+    ToSchema,
+    Deserialize,
+    Serialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(None)")]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// `player_api.php` actions, `server` is the panel.
+    #[default]
+    #[sea_orm(string_value = "xtream")]
+    Xtream,
+
+    /// An M3U playlist, `server` is either a panel, whose `get.php` is used,
+    /// or the url of the playlist itself.
+    #[sea_orm(string_value = "m3u")]
+    M3u,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct Mirrors(pub Vec<String>);
+
+impl Model {
+    /// The server the login was added with, followed by its mirrors.
+    pub fn servers(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.server.as_str()).chain(self.mirrors.0.iter().map(String::as_str))
+    }
+}
+
+impl std::fmt::Debug for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Login")
+            .field("id", &self.id)
+            .field("account_id", &self.account_id)
+            .field("server", &self.server)
+            .field("username", &self.username)
+            .field("password", &Redacted)
+            .field("mirrors", &self.mirrors.0)
+            .field("backend", &self.backend)
+            .field("epg_url", &self.epg_url)
+            .finish()
+    }
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(
-        belongs_to = "super::session::Entity",
-        from = "Column::Id",
-        to = "super::session::Column::Id",
+        belongs_to = "super::account::Entity",
+        from = "Column::AccountId",
+        to = "super::account::Column::Id",
         on_delete = "Cascade"
     )]
-    Session,
+    Account,
+    #[sea_orm(has_one = "super::userinfo::Entity")]
+    UserInfo,
+}
+
+impl Related<super::account::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Account.def()
+    }
 }
 
-impl Related<super::session::Entity> for Entity {
+impl Related<super::userinfo::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::Session.def()
+        Relation::UserInfo.def()
     }
 }
 
@@ -1,9 +1,15 @@
 pub mod prelude;
 
+pub mod account;
 pub mod avatar;
+pub mod cache;
+pub mod catalog_category;
+pub mod catalog_stream;
+pub mod catalog_sync;
 pub mod favorite;
 pub mod home;
 pub mod login;
+pub mod playback_event;
 pub mod session;
 pub mod userinfo;
 pub mod watching;
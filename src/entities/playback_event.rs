@@ -0,0 +1,67 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+pub use super::catalog_stream::Kind;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, ToSchema, Serialize)]
+#[schema(as = PlaybackEvent)]
+#[sea_orm(table_name = "playback_event")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_serializing)]
+    pub id: i64,
+
+    /// Login the value id belongs to.
+    #[serde(rename = "source")]
+    pub login_id: i64,
+
+    /// Session of the device that played the stream.
+    #[serde(rename = "session")]
+    pub session_id: i64,
+
+    #[serde(rename = "avatar", skip_serializing_if = "Option::is_none")]
+    pub avatar_id: Option<i64>,
+
+    pub kind: Kind,
+    pub value_id: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub episode_id: Option<i64>,
+
+    pub event: Event,
+    /// Position in seconds when the event happened.
+    pub position: i64,
+    pub date: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, ToSchema, Serialize)]
+#[schema(as = PlaybackEventKind)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    #[sea_orm(string_value = "Start")]
+    Start,
+
+    #[sea_orm(string_value = "Stop")]
+    Stop,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::login::Entity",
+        from = "Column::LoginId",
+        to = "super::login::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Login,
+}
+
+impl Related<super::login::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Login.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,5 +1,10 @@
 #![allow(unused_imports)]
 
+pub use super::account::ActiveModel as AccountActiveModel;
+pub use super::account::Column as AccountColumn;
+pub use super::account::Entity as AccountEntity;
+pub use super::account::Model as Account;
+
 pub use super::session::ActiveModel as SessionActiveModel;
 pub use super::session::Column as SessionColumn;
 pub use super::session::Entity as SessionEntity;
@@ -34,3 +39,28 @@ pub use super::home::ActiveModel as HomeActiveModel;
 pub use super::home::Column as HomeColumn;
 pub use super::home::Entity as HomeEntity;
 pub use super::home::Model as Home;
+
+pub use super::cache::ActiveModel as CacheActiveModel;
+pub use super::cache::Column as CacheColumn;
+pub use super::cache::Entity as CacheEntity;
+pub use super::cache::Model as Cache;
+
+pub use super::catalog_category::ActiveModel as CatalogCategoryActiveModel;
+pub use super::catalog_category::Column as CatalogCategoryColumn;
+pub use super::catalog_category::Entity as CatalogCategoryEntity;
+pub use super::catalog_category::Model as CatalogCategory;
+
+pub use super::catalog_stream::ActiveModel as CatalogStreamActiveModel;
+pub use super::catalog_stream::Column as CatalogStreamColumn;
+pub use super::catalog_stream::Entity as CatalogStreamEntity;
+pub use super::catalog_stream::Model as CatalogStream;
+
+pub use super::catalog_sync::ActiveModel as CatalogSyncActiveModel;
+pub use super::catalog_sync::Column as CatalogSyncColumn;
+pub use super::catalog_sync::Entity as CatalogSyncEntity;
+pub use super::catalog_sync::Model as CatalogSync;
+
+pub use super::playback_event::ActiveModel as PlaybackEventActiveModel;
+pub use super::playback_event::Column as PlaybackEventColumn;
+pub use super::playback_event::Entity as PlaybackEventEntity;
+pub use super::playback_event::Model as PlaybackEvent;
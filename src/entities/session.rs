@@ -1,41 +1,72 @@
 use sea_orm::entity::prelude::*;
 use serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+use crate::logging::Redacted;
+
+#[derive(Clone, PartialEq, Eq, DeriveEntityModel, ToSchema, Serialize)]
+#[schema(as = Session)]
 #[sea_orm(table_name = "session")]
 pub struct Model {
     #[sea_orm(primary_key)]
-    #[serde(skip_serializing)]
     pub id: i64,
 
+    #[serde(skip_serializing)]
+    pub account_id: i64,
+
+    /// Start of the auth key, used to find the row before checking the hash.
+    #[serde(skip_serializing)]
+    pub auth_prefix: String,
+    /// Keyed hash of the auth key, see `Crypto::hash_token`.
+    #[serde(skip_serializing)]
     pub auth_key: String,
-}
 
-#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {
-    #[sea_orm(has_many = "super::login::Entity")]
-    Login,
-    #[sea_orm(has_many = "super::userinfo::Entity")]
-    UserInfo,
-    #[sea_orm(has_many = "super::avatar::Entity")]
-    Avatar,
+    #[schema(example = "Living room TV")]
+    pub device: String,
+
+    pub created: i64,
+    pub last_used: i64,
+    pub expires: i64,
+
+    #[serde(skip_serializing)]
+    pub refresh_prefix: Option<String>,
+    /// Keyed hash of the refresh token.
+    #[serde(skip_serializing)]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing)]
+    pub refresh_expires: i64,
 }
 
-impl Related<super::login::Entity> for Entity {
-    fn to() -> RelationDef {
-        Relation::Login.def()
+impl std::fmt::Debug for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("id", &self.id)
+            .field("account_id", &self.account_id)
+            .field("auth_key", &Redacted)
+            .field("device", &self.device)
+            .field("expires", &self.expires)
+            .field(
+                "refresh_token",
+                &self.refresh_token.as_ref().map(|_| Redacted),
+            )
+            .finish()
     }
 }
 
-impl Related<super::userinfo::Entity> for Entity {
-    fn to() -> RelationDef {
-        Relation::UserInfo.def()
-    }
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::account::Entity",
+        from = "Column::AccountId",
+        to = "super::account::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Account,
 }
 
-impl Related<super::avatar::Entity> for Entity {
+impl Related<super::account::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::Avatar.def()
+        Relation::Account.def()
     }
 }
 
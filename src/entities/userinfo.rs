@@ -1,9 +1,11 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use crate::extra::{default_on_null, num_from_str_or_num};
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, ToSchema, Deserialize, Serialize)]
+#[schema(as = UserInfo)]
 #[sea_orm(table_name = "user_info")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -37,22 +39,28 @@ pub struct Model {
     #[serde(default)]
     #[serde(deserialize_with = "num_from_str_or_num")]
     pub max_connections: i64,
+
+    /// Timezone of the server, from its `server_info`. Guide times given as
+    /// text are in it.
+    #[serde(default, skip_deserializing)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(
-        belongs_to = "super::session::Entity",
+        belongs_to = "super::login::Entity",
         from = "Column::Id",
-        to = "super::session::Column::Id",
+        to = "super::login::Column::Id",
         on_delete = "Cascade"
     )]
-    Session,
+    Login,
 }
 
-impl Related<super::session::Entity> for Entity {
+impl Related<super::login::Entity> for Entity {
     fn to() -> RelationDef {
-        Relation::Session.def()
+        Relation::Login.def()
     }
 }
 
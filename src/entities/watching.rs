@@ -1,7 +1,9 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Default, DeriveEntityModel, Serialize)]
+#[derive(Clone, Debug, PartialEq, Default, DeriveEntityModel, ToSchema, Serialize)]
+#[schema(as = Watching)]
 #[sea_orm(table_name = "watching")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -11,12 +13,19 @@ pub struct Model {
     #[serde(skip_serializing)]
     pub avatar_id: i64,
 
+    /// Login the value id belongs to.
+    #[serde(rename = "source")]
+    pub login_id: i64,
+
     pub kind: Kind,
     pub value_id: i64,
     pub name: String,
     pub icon: String,
     pub date: i64,
     pub time: i64,
+    /// Length of the value in seconds, when the player reported it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<i64>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub episode_id: Option<i64>,
@@ -24,7 +33,10 @@ pub struct Model {
     pub container_extension: String,
 }
 
-#[derive(Clone, Debug, PartialEq, EnumIter, Default, DeriveActiveEnum, Deserialize, Serialize)]
+#[derive(
+    Clone, Debug, PartialEq, EnumIter, Default, DeriveActiveEnum, ToSchema, Deserialize, Serialize,
+)]
+#[schema(as = WatchingKind)]
 #[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
 #[serde(rename_all = "snake_case")]
 pub enum Kind {
@@ -45,6 +57,13 @@ pub enum Relation {
 		on_delete = "Cascade"
     )]
     Avatar,
+    #[sea_orm(
+        belongs_to = "super::login::Entity",
+        from = "Column::LoginId",
+        to = "super::login::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Login,
 }
 
 impl Related<super::avatar::Entity> for Entity {
@@ -53,4 +72,10 @@ impl Related<super::avatar::Entity> for Entity {
     }
 }
 
+impl Related<super::login::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Login.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
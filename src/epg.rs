@@ -0,0 +1,558 @@
+use actix_web::{web as ActixWeb, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use futures::{stream, StreamExt, TryStreamExt};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    catalog,
+    client::Client,
+    crypto::Crypto,
+    entities::{catalog_stream::Kind, login::Backend, prelude::*},
+    extra::{get_json, IdType, Params},
+    get::{get_live_epg, Epg, Page, Value},
+    login, xmltv,
+};
+
+/// Channels returned by a single guide request when no `limit` is given.
+const DEFAULT_CHANNELS: u64 = 50;
+
+/// Every channel is one upstream request on panels, so pages are kept small.
+const MAX_CHANNELS: u64 = 200;
+
+/// Channels whose guide is fetched from upstream at the same time.
+pub const CONCURRENT_CHANNELS: usize = 8;
+
+/// Window of the grid when no `end` is given.
+const DEFAULT_WINDOW_SECS: i64 = 6 * 60 * 60;
+
+#[derive(IntoParams, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct ChannelQuery {
+    /// Login the id belongs to, the first login of the account when missing
+    source: Option<i64>,
+    /// Programmes ending after this unix timestamp
+    start: Option<i64>,
+    /// Programmes starting before this unix timestamp
+    end: Option<i64>,
+    /// IANA name local times are shown in, like `Europe/Lisbon`, the timezone
+    /// of the server when missing
+    timezone: Option<String>,
+}
+
+#[derive(IntoParams, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct GuideQuery {
+    /// Login the channels belong to, the first login of the account when missing
+    source: Option<i64>,
+    /// Category of the channels
+    category_id: Option<i64>,
+    /// Comma separated live ids, instead of a category
+    channels: Option<String>,
+    /// Programmes ending after this unix timestamp, now when missing
+    start: Option<i64>,
+    /// Programmes starting before this unix timestamp, six hours after
+    /// `start` when missing
+    end: Option<i64>,
+    /// IANA name local times are shown in, like `Europe/Lisbon`, the timezone
+    /// of the server when missing
+    timezone: Option<String>,
+    /// Maximum number of channels returned, at most 200
+    limit: Option<u64>,
+    /// Number of channels skipped
+    offset: Option<u64>,
+}
+
+#[derive(IntoParams, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct NowQuery {
+    /// Login the channels belong to, the first login of the account when missing
+    source: Option<i64>,
+    /// Category of the channels
+    category_id: Option<i64>,
+    /// Comma separated live ids, instead of a category
+    channels: Option<String>,
+    /// IANA name local times are shown in, like `Europe/Lisbon`, the timezone
+    /// of the server when missing
+    timezone: Option<String>,
+    /// Maximum number of channels returned, at most 200
+    limit: Option<u64>,
+    /// Number of channels skipped
+    offset: Option<u64>,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct GuideChannel {
+    id: i64,
+    source: i64,
+    name: String,
+    icon: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epg_channel_id: Option<String>,
+    programmes: Vec<Epg>,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct NowNext {
+    id: i64,
+    source: i64,
+    name: String,
+    icon: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epg_channel_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    now: Option<Epg>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<Epg>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/epg/channel/{id}",
+    tag = "epg",
+    params(
+        ("id" = i64, Path, description = "Live id"),
+        ChannelQuery,
+    ),
+    responses(
+        (status = 200, description = "Every programme of the live the server knows, or those in the window", body = Vec<Epg>),
+        (status = 400, description = "Timezone is unknown", body = ApiErrorJson),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source or id does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/channel/{id}")]
+async fn channel(
+    credentials: BearerAuth,
+    path: ActixWeb::Path<i64>,
+    query: ActixWeb::Query<ChannelQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let id = path.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let login = login::get_login(&session, query.source, &db, &crypto).await?;
+
+    let timezone = get_timezone(&login, query.timezone.as_deref(), &db).await?;
+
+    let value = catalog::get_value(&login, Kind::Live, id, &db, client.clone()).await?;
+    let mut programmes = get_full_epg(&login, &value, &db, client).await?;
+
+    let start = query.start.unwrap_or(i64::MIN);
+    let end = query.end.unwrap_or(i64::MAX);
+    programmes.retain(|x| x.stop_timestamp > start && x.start_timestamp < end);
+    localize(&mut programmes, timezone);
+
+    Ok(HttpResponse::Ok().json(programmes))
+}
+
+#[utoipa::path(
+    get,
+    path = "/epg/grid",
+    tag = "epg",
+    params(
+        GuideQuery,
+    ),
+    responses(
+        (status = 200, description = "Programmes of each channel in the window, six hours from now by default", body = Vec<GuideChannel>),
+        (status = 400, description = "No channels were given, the window is empty or the timezone is unknown", body = ApiErrorJson),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source or id does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/grid")]
+async fn grid(
+    credentials: BearerAuth,
+    query: ActixWeb::Query<GuideQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let auth_key = credentials.token();
+
+    let start = query
+        .start
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let end = query.end.unwrap_or(start + DEFAULT_WINDOW_SECS);
+
+    if end <= start {
+        return Err(ApiError::BadRequest(
+            "end must come after start".to_string(),
+        ));
+    }
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let login = login::get_login(&session, query.source, &db, &crypto).await?;
+
+    let timezone = get_timezone(&login, query.timezone.as_deref(), &db).await?;
+    let channels = get_channels(
+        &login,
+        query.category_id,
+        query.channels.as_deref(),
+        query.limit,
+        query.offset,
+        &db,
+        client.clone(),
+    )
+    .await?;
+
+    let result: Vec<GuideChannel> = stream::iter(channels)
+        .map(|value| {
+            let (login, db, client) = (&login, &db, client.clone());
+            async move {
+                let mut programmes = get_full_epg(login, &value, db, client).await?;
+                programmes.retain(|x| x.stop_timestamp > start && x.start_timestamp < end);
+                localize(&mut programmes, timezone);
+
+                Ok::<_, ApiError>(GuideChannel {
+                    id: value.id,
+                    source: value.source,
+                    name: value.name,
+                    icon: value.icon,
+                    epg_channel_id: value.epg_channel_id,
+                    programmes,
+                })
+            }
+        })
+        .buffered(CONCURRENT_CHANNELS)
+        .try_collect()
+        .await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[utoipa::path(
+    get,
+    path = "/epg/now",
+    tag = "epg",
+    params(
+        NowQuery,
+    ),
+    responses(
+        (status = 200, description = "Programme on now and the next one of each channel", body = Vec<NowNext>),
+        (status = 400, description = "No channels were given or the timezone is unknown", body = ApiErrorJson),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source or id does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/now")]
+async fn now(
+    credentials: BearerAuth,
+    query: ActixWeb::Query<NowQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let login = login::get_login(&session, query.source, &db, &crypto).await?;
+
+    let timezone = get_timezone(&login, query.timezone.as_deref(), &db).await?;
+    let channels = get_channels(
+        &login,
+        query.category_id,
+        query.channels.as_deref(),
+        query.limit,
+        query.offset,
+        &db,
+        client.clone(),
+    )
+    .await?;
+
+    let time = chrono::Utc::now().timestamp();
+    let result: Vec<NowNext> = stream::iter(channels)
+        .map(|value| {
+            let (login, db, client) = (&login, &db, client.clone());
+            async move {
+                let mut programmes = get_live_epg(value.id, Params::new(login), db, client)
+                    .await?
+                    .into_iter()
+                    .filter(|x| x.stop_timestamp > time);
+
+                let mut current = programmes.next();
+                let mut next = programmes.next();
+
+                // A gap in the guide, nothing is on yet.
+                if current.as_ref().is_some_and(|x| x.start_timestamp > time) {
+                    next = current.take();
+                }
+
+                for epg in current.iter_mut().chain(next.iter_mut()) {
+                    localize(std::slice::from_mut(epg), timezone);
+                }
+
+                Ok::<_, ApiError>(NowNext {
+                    id: value.id,
+                    source: value.source,
+                    name: value.name,
+                    icon: value.icon,
+                    epg_channel_id: value.epg_channel_id,
+                    now: current,
+                    next,
+                })
+            }
+        })
+        .buffered(CONCURRENT_CHANNELS)
+        .try_collect()
+        .await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+/// Lives of a login given by id, in the order they were asked for, or those
+/// of a category in catalog order.
+async fn get_channels(
+    login: &Login,
+    category_id: Option<i64>,
+    channels: Option<&str>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<Value>> {
+    let limit = limit.unwrap_or(DEFAULT_CHANNELS).min(MAX_CHANNELS);
+    let offset = offset.unwrap_or(0);
+
+    match (channels, category_id) {
+        (Some(channels), _) => {
+            let ids = channels
+                .split(',')
+                .filter(|x| !x.trim().is_empty())
+                .map(|x| x.trim().parse::<i64>())
+                .collect::<Result<Vec<i64>, _>>()
+                .map_err(|_| ApiError::BadRequest("channels must be live ids".to_string()))?;
+
+            let mut values = Vec::new();
+            for id in ids.into_iter().skip(offset as usize).take(limit as usize) {
+                values.push(catalog::get_value(login, Kind::Live, id, db, client.clone()).await?);
+            }
+
+            Ok(values)
+        }
+        (None, Some(category_id)) => {
+            let mut page = Page::default();
+            page.limit = Some(limit);
+            page.offset = Some(offset);
+
+            let (values, _) = catalog::get_page(
+                std::slice::from_ref(login),
+                Kind::Live,
+                Some(category_id),
+                &page,
+                db,
+                client,
+            )
+            .await?;
+
+            Ok(values)
+        }
+        (None, None) => Err(ApiError::BadRequest(
+            "either category_id or channels is needed".to_string(),
+        )),
+    }
+}
+
+/// Every programme of a live the server knows, usually a few days around
+/// today.
+pub async fn get_full_epg(
+    login: &Login,
+    value: &Value,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<Epg>> {
+    match login.backend {
+        Backend::M3u => match &value.epg_channel_id {
+            Some(channel_id) => Ok(xmltv::get_guide(login, db, client)
+                .await?
+                .get(channel_id)
+                .cloned()
+                .unwrap_or_default()),
+            None => Ok(Vec::new()),
+        },
+        Backend::Xtream => {
+            get_listings(
+                Params::new(login),
+                "get_simple_data_table",
+                value.id,
+                db,
+                client,
+            )
+            .await
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EpgListings {
+    epg_listings: Vec<Epg>,
+}
+
+/// Listings of a live from a panel, with their text decoded and their local
+/// times in the timezone of the server.
+pub async fn get_listings<'a>(
+    mut params: Params<'a>,
+    action: &'a str,
+    id: i64,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<Epg>> {
+    params.action = Some(action);
+    params.id = Some(IdType::Live(id));
+
+    let mut listings = get_json::<EpgListings>(&params, client).await?.epg_listings;
+
+    let timezone = server_timezone(params.login, db).await?;
+
+    for epg in &mut listings {
+        decode(&mut epg.title);
+        decode(&mut epg.description);
+
+        // Some panels only send local times.
+        if epg.start_timestamp == 0 {
+            epg.start_timestamp = parse_local(epg.start.as_deref(), timezone).unwrap_or_default();
+        }
+        if epg.stop_timestamp == 0 {
+            epg.stop_timestamp = parse_local(epg.stop.as_deref(), timezone).unwrap_or_default();
+        }
+    }
+
+    listings.sort_by_key(|x| x.start_timestamp);
+    localize(&mut listings, timezone);
+
+    Ok(listings)
+}
+
+/// Panels send titles and descriptions in base64, text that does not decode
+/// is kept as it came.
+fn decode(text: &mut String) {
+    if let Ok(bytes) = BASE64_STANDARD.decode(text.trim()) {
+        if let Ok(decoded) = String::from_utf8(bytes) {
+            *text = decoded;
+        }
+    }
+}
+
+/// Timezone of the server of a login, as told by its `server_info`.
+async fn server_timezone(login: &Login, db: &DatabaseConnection) -> ApiResult<Option<Tz>> {
+    Ok(UserInfoEntity::find_by_id(login.id)
+        .one(db)
+        .await?
+        .and_then(|x| x.timezone)
+        .and_then(|x| x.parse::<Tz>().ok()))
+}
+
+/// Timezone asked for, or the one of the server.
+async fn get_timezone(
+    login: &Login,
+    timezone: Option<&str>,
+    db: &DatabaseConnection,
+) -> ApiResult<Option<Tz>> {
+    match timezone {
+        Some(name) => name
+            .parse::<Tz>()
+            .map(Some)
+            .map_err(|_| ApiError::BadRequest(format!("unknown timezone {name}"))),
+        None => server_timezone(login, db).await,
+    }
+}
+
+/// Local times look like `2024-01-01 20:00:00`, UTC when the timezone is not
+/// known.
+fn parse_local(text: Option<&str>, timezone: Option<Tz>) -> Option<i64> {
+    let time = NaiveDateTime::parse_from_str(text?.trim(), "%Y-%m-%d %H:%M:%S").ok()?;
+
+    match timezone {
+        Some(timezone) => timezone
+            .from_local_datetime(&time)
+            .earliest()
+            .map(|x| x.timestamp()),
+        None => Some(time.and_utc().timestamp()),
+    }
+}
+
+/// Sets the local times of programmes in `timezone`, they are left out when
+/// it is not known.
+pub fn localize(programmes: &mut [Epg], timezone: Option<Tz>) {
+    let format = |timestamp: i64| {
+        let timezone = timezone?;
+        let time = chrono::DateTime::from_timestamp(timestamp, 0)?;
+        Some(
+            time.with_timezone(&timezone)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+        )
+    };
+
+    for epg in programmes {
+        epg.start = format(epg.start_timestamp);
+        epg.stop = format(epg.stop_timestamp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_local_times_in_the_server_timezone() {
+        let utc = parse_local(Some("2024-01-01 20:00:00"), None);
+        assert_eq!(utc, Some(1704139200));
+
+        let paris = parse_local(Some("2024-01-01 20:00:00"), Some(chrono_tz::Europe::Paris));
+        assert_eq!(paris, Some(1704139200 - 3600));
+
+        assert_eq!(parse_local(Some("20:00"), None), None);
+        assert_eq!(parse_local(None, None), None);
+    }
+
+    #[test]
+    fn parses_local_times_around_clock_changes() {
+        let paris = Some(chrono_tz::Europe::Paris);
+
+        // Skipped when clocks go forward, the hour does not exist.
+        assert_eq!(parse_local(Some("2024-03-31 02:30:00"), paris), None);
+
+        // Repeated when clocks go back, the first one is taken.
+        assert_eq!(
+            parse_local(Some("2024-10-27 02:30:00"), paris),
+            Some(1729989000)
+        );
+    }
+
+    #[test]
+    fn decodes_base64_text_only() {
+        let mut text = "SGVsbG8gd29ybGQ=".to_string();
+        decode(&mut text);
+        assert_eq!(text, "Hello world");
+
+        let mut text = "Plain title".to_string();
+        decode(&mut text);
+        assert_eq!(text, "Plain title");
+
+        // Valid base64 that is not text is kept as it came.
+        let mut text = "//79".to_string();
+        decode(&mut text);
+        assert_eq!(text, "//79");
+    }
+}
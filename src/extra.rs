@@ -1,11 +1,28 @@
-use actix_web::web as ActixWeb;
-use api_error::{ApiError, ApiResult, ErrAs};
+use actix_web::{
+    body::{BodySize, MessageBody},
+    web as ActixWeb,
+};
+use api_error::ApiResult;
 use entities::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use utoipa::ToSchema;
 
-use crate::{api_error, entities};
+use crate::{
+    api_error::{self, ApiError},
+    cache::CacheKey,
+    client::{is_retryable, Client},
+    entities,
+    hosts::is_host_failure,
+    logging::redact_url,
+};
 
-#[derive(Serialize)]
+#[derive(ToSchema, Serialize)]
 pub struct BoolResult {
     pub result: bool,
 }
@@ -25,6 +42,18 @@ pub enum IdType {
     Category(i64),
 }
 
+/// The query parameter sent upstream, stable so it can be part of keys.
+impl std::fmt::Display for IdType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdType::Live(id) => write!(f, "stream_id={id}"),
+            IdType::Movie(id) => write!(f, "vod_id={id}"),
+            IdType::Serie(id) => write!(f, "series_id={id}"),
+            IdType::Category(id) => write!(f, "category_id={id}"),
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct Params<'a> {
     #[serde(flatten)]
@@ -39,7 +68,7 @@ pub struct Params<'a> {
 }
 
 impl<'a> Params<'a> {
-    pub fn new(login: &Login) -> Params {
+    pub fn new(login: &Login) -> Params<'_> {
         Params {
             login,
             action: None,
@@ -48,61 +77,339 @@ impl<'a> Params<'a> {
     }
 }
 
-pub async fn get_json<T>(
-    params: &Params<'_>,
-    client: ActixWeb::Data<reqwest::Client>,
-) -> ApiResult<T>
+#[tracing::instrument(
+    skip_all,
+    err(level = "warn"),
+    fields(action = params.action.unwrap_or_default())
+)]
+pub async fn get_json<T>(params: &Params<'_>, client: ActixWeb::Data<Client>) -> ApiResult<T>
 where
     T: serde::de::DeserializeOwned,
 {
-    let request = client
-        .get(&params.login.server)
-        .query(params)
-        .send()
-        .await?;
-
-    if cfg!(debug_assertions) {
-        println!("get_json: {:?}", request);
-        println!();
+    let key = CacheKey::new(params);
+    let fetch = fetch(
+        params.login,
+        action(params),
+        &client,
+        request(params, Some(client.timeout)),
+        read_text,
+    );
+    let response = client.cache.get_or_fetch(key, fetch).await?;
+
+    let deserializer = &mut serde_json::Deserializer::from_str(&response);
+    Ok(serde_path_to_error::deserialize(deserializer)?)
+}
+
+/// Same as `get_json` for responses that can be too big to hold as text,
+/// like the stream lists of large libraries. The body is decoded while it
+/// arrives and is never cached, the catalog tables already mirror it.
+#[tracing::instrument(
+    skip_all,
+    err(level = "warn"),
+    fields(action = params.action.unwrap_or_default())
+)]
+pub async fn get_json_stream<T>(params: &Params<'_>, client: ActixWeb::Data<Client>) -> ApiResult<T>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+{
+    fetch(
+        params.login,
+        action(params),
+        &client,
+        request(params, None),
+        read_stream,
+    )
+    .await
+}
+
+/// Requests without an action are the account lookup done on login.
+fn action<'a>(params: &Params<'a>) -> &'a str {
+    params.action.unwrap_or("user_info")
+}
+
+fn request<'a>(
+    params: &'a Params<'a>,
+    timeout: Option<Duration>,
+) -> impl Fn(&reqwest::Client, &str) -> ApiResult<reqwest::RequestBuilder> + 'a {
+    move |http, server| {
+        let request = http.get(server).query(params);
+        Ok(match timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        })
     }
+}
 
-    let request = request
-        .error_for_status()
-        .err_as(ApiError::RequestServerError)?;
-    let response = request.text().await?;
+/// Sends a request built by `request` for each host of the login, retrying
+/// each one before moving on, and reads the first response that worked with
+/// `read`. `action` names the request in metrics and logs.
+pub async fn fetch<T, B, F, Fut>(
+    login: &Login,
+    action: &str,
+    client: &Client,
+    request: B,
+    read: F,
+) -> ApiResult<T>
+where
+    B: Fn(&reqwest::Client, &str) -> ApiResult<reqwest::RequestBuilder>,
+    F: Fn(reqwest::Response, u64) -> Fut,
+    Fut: Future<Output = ApiResult<T>>,
+{
+    let mut result = Err(ApiError::CircuitOpen);
+
+    // Mirrors are only tried when a host looks down, any other error is
+    // the same on every host of the panel.
+    for server in client.hosts.order(login) {
+        result = fetch_host(server, action, client, &request, &read).await;
+
+        match &result {
+            Err(err) if is_host_failure(err) => {
+                client.hosts.failure(server, err);
+                continue;
+            }
+            _ => break,
+        }
+    }
 
-    Ok(serde_json::from_str::<T>(&response)?)
+    result
 }
 
-pub fn get_days_ago(days: i64) -> i64 {
-    chrono::Utc::now().timestamp() - (days * 24 * 60 * 60)
+async fn fetch_host<T, B, F, Fut>(
+    server: &str,
+    action: &str,
+    client: &Client,
+    request: &B,
+    read: &F,
+) -> ApiResult<T>
+where
+    B: Fn(&reqwest::Client, &str) -> ApiResult<reqwest::RequestBuilder>,
+    F: Fn(reqwest::Response, u64) -> Fut,
+    Fut: Future<Output = ApiResult<T>>,
+{
+    let mut retry = 0;
+
+    loop {
+        let start = Instant::now();
+        let result = match send(request(&client.http, server)?).await {
+            Ok(response) => read(response, client.max_body_bytes).await,
+            Err(err) => Err(err),
+        };
+
+        client
+            .metrics
+            .observe_upstream(action, result.is_ok(), start.elapsed());
+
+        match result {
+            Ok(value) => {
+                client.hosts.success(server);
+                return Ok(value);
+            }
+            Err(err) if retry < client.retry.retries && is_retryable(&err) => {
+                let delay = client.retry.delay(retry);
+                tracing::debug!(%err, retry, ?delay, "retrying upstream request");
+
+                tokio::time::sleep(delay).await;
+                retry += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
 }
 
-pub trait CustomDeref<T> {
-    fn deref_or_else<E>(&self, err: E) -> Result<&T, E>;
+async fn send(request: reqwest::RequestBuilder) -> ApiResult<reqwest::Response> {
+    let response = request.send().await?;
 
-    fn deref_map<'a, U, F>(&'a self, f: F) -> Option<U>
-    where
-        T: std::ops::Deref,
-        F: FnOnce(&'a T::Target) -> U,
-        <T as std::ops::Deref>::Target: 'a;
+    tracing::debug!(
+        url = redact_url(response.url()),
+        status = response.status().as_u16(),
+        "upstream response"
+    );
+
+    Ok(response.error_for_status()?)
+}
+
+pub async fn read_text(mut response: reqwest::Response, max_bytes: u64) -> ApiResult<String> {
+    if response.content_length().is_some_and(|x| x > max_bytes) {
+        return Err(ApiError::TooLarge(max_bytes));
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() as u64 + chunk.len() as u64 > max_bytes {
+            return Err(ApiError::TooLarge(max_bytes));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8(body)
+        .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned()))
+}
+
+async fn read_stream<T>(response: reqwest::Response, max_bytes: u64) -> ApiResult<T>
+where
+    T: serde::de::DeserializeOwned + Send + 'static,
+{
+    read_blocking(response, max_bytes, |reader| {
+        let deserializer =
+            &mut serde_json::Deserializer::from_reader(std::io::BufReader::new(reader));
+        let value = serde_path_to_error::deserialize(&mut *deserializer)?;
+        deserializer.end()?;
+
+        Ok(value)
+    })
+    .await
+}
+
+/// Hands the body chunk by chunk to `decode` running on a blocking thread,
+/// so only the decoded value is ever held in memory.
+pub async fn read_blocking<T, D>(
+    mut response: reqwest::Response,
+    max_bytes: u64,
+    decode: D,
+) -> ApiResult<T>
+where
+    T: Send + 'static,
+    D: FnOnce(ChunkReader) -> ApiResult<T> + Send + 'static,
+{
+    if response.content_length().is_some_and(|x| x > max_bytes) {
+        return Err(ApiError::TooLarge(max_bytes));
+    }
+
+    let (sender, receiver) = tokio::sync::mpsc::channel(16);
+
+    let decode = tokio::task::spawn_blocking(move || {
+        decode(ChunkReader {
+            receiver,
+            chunk: ActixWeb::Bytes::new(),
+        })
+    });
+
+    // The sender is dropped once the body ends, which the reader sees as the
+    // end of the input.
+    let receive = async move {
+        let mut size = 0;
+
+        while let Some(chunk) = response.chunk().await? {
+            size += chunk.len() as u64;
+            if size > max_bytes {
+                return Err(ApiError::TooLarge(max_bytes));
+            }
+
+            // The decoder stopped early, its own error tells why.
+            if sender.send(chunk).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    };
+
+    let received = receive.await;
+    let decoded = decode.await.map_err(|_| ApiError::Channel)?;
+
+    received?;
+    decoded
+}
+
+pub struct ChunkReader {
+    receiver: tokio::sync::mpsc::Receiver<ActixWeb::Bytes>,
+    chunk: ActixWeb::Bytes,
 }
 
-impl<T> CustomDeref<T> for Option<T> {
-    fn deref_or_else<E>(&self, err: E) -> Result<&T, E> {
-        self.as_ref().ok_or(err)
+impl std::io::Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+
+        Ok(len)
     }
+}
+
+/// Response body writing a list as a JSON array a few values at a time,
+/// instead of serializing all of them to one string first.
+pub struct JsonArray<I> {
+    values: I,
+    first: bool,
+    done: bool,
+}
 
-    fn deref_map<'a, U, F>(&'a self, f: F) -> Option<U>
+impl<I> JsonArray<I> {
+    pub fn new<T>(values: I) -> JsonArray<I>
     where
-        T: std::ops::Deref,
-        F: FnOnce(&'a T::Target) -> U,
-        <T as std::ops::Deref>::Target: 'a,
+        I: Iterator<Item = serde_json::Result<T>>,
     {
-        self.as_ref().map(|v| f(v.deref()))
+        JsonArray {
+            values,
+            first: true,
+            done: false,
+        }
+    }
+}
+
+impl<I, T> MessageBody for JsonArray<I>
+where
+    I: Iterator<Item = serde_json::Result<T>> + Unpin,
+    T: Serialize,
+{
+    type Error = serde_json::Error;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<ActixWeb::Bytes, Self::Error>>> {
+        const CHUNK_BYTES: usize = 64 * 1024;
+
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let mut buf = Vec::with_capacity(CHUNK_BYTES);
+        if this.first {
+            buf.push(b'[');
+        }
+
+        while buf.len() < CHUNK_BYTES {
+            let Some(value) = this.values.next() else {
+                buf.push(b']');
+                this.done = true;
+                break;
+            };
+
+            let value = match value {
+                Ok(value) => value,
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            };
+
+            if !this.first {
+                buf.push(b',');
+            }
+            this.first = false;
+
+            if let Err(err) = serde_json::to_writer(&mut buf, &value) {
+                return Poll::Ready(Some(Err(err)));
+            }
+        }
+
+        Poll::Ready(Some(Ok(ActixWeb::Bytes::from(buf))))
     }
 }
 
+pub fn get_days_ago(days: i64) -> i64 {
+    chrono::Utc::now().timestamp() - (days * 24 * 60 * 60)
+}
+
 pub fn default_on_null<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -111,6 +418,17 @@ where
     Ok(Option::deserialize(deserializer)?.unwrap_or_default())
 }
 
+/// Discards whatever the server sent. Unlike `skip_deserializing` the field
+/// still shows up in the OpenAPI schema.
+pub fn ignore<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default,
+{
+    serde::de::IgnoredAny::deserialize(deserializer)?;
+    Ok(T::default())
+}
+
 pub fn num_from_str_or_num<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
     T: std::str::FromStr + Default,
@@ -137,11 +455,24 @@ where
     }
 }
 
-// pub fn opt_num_from_str_or_num<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
-// where
-//     D: serde::Deserializer<'de>,
-//     T: std::str::FromStr + serde::Deserialize<'de>,
-// 	<T as std::str::FromStr>::Err: std::fmt::Display + std::fmt::Debug,
-// {
-//     Ok(Some(num_from_str_or_num(deserializer)?))
-// }
+pub fn opt_num_from_str_or_num<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+    D: serde::Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(number) if !number.as_str().is_empty() => number
+            .as_str()
+            .parse()
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        serde_json::Value::String(string) if !string.is_empty() => {
+            string.parse().map(Some).map_err(serde::de::Error::custom)
+        }
+        serde_json::Value::Number(_) | serde_json::Value::String(_) | serde_json::Value::Null => {
+            Ok(None)
+        }
+        _ => Err(serde::de::Error::custom("Not a number or string")),
+    }
+}
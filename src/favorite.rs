@@ -2,36 +2,57 @@ use actix_web::{web as ActixWeb, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use serde::Serialize;
+use utoipa::ToSchema;
 
 use crate::{
     api_error::{ApiError, ApiResult},
-    entities::{favorite::Kind, prelude::*},
+    catalog,
+    client::Client,
+    crypto::Crypto,
+    entities::{catalog_stream::Kind as CatalogKind, favorite::Kind, prelude::*},
     extra::{BoolResult, Params},
-    get::{get_lives, get_movie_info, get_serie_info, Value},
-    login,
+    get::{get_movie_info, get_serie_info, Value},
+    login::{self, SourceQuery},
 };
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(ToSchema, Serialize, Clone, Debug)]
 pub struct Favorites {
     lives: Vec<Favorite>,
     movies: Vec<Favorite>,
     series: Vec<Favorite>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/favorite/get/{avatar}",
+    tag = "favorite",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+    ),
+    responses(
+        (status = 200, description = "Favorites of the avatar", body = Favorites),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar does not exist", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/get/{avatar}")]
 pub async fn get(
     credentials: BearerAuth,
     path: ActixWeb::Path<i64>,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let avatar = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     AvatarEntity::find()
         .filter(AvatarColumn::Id.eq(avatar))
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .one(db.get_ref())
         .await?
         .ok_or(ApiError::WrongAvatar)?;
@@ -62,47 +83,64 @@ pub async fn get(
     Ok(HttpResponse::Ok().json(favorites))
 }
 
+#[utoipa::path(
+    get,
+    path = "/favorite/store/{avatar}/{kind}/{id}",
+    tag = "favorite",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+        ("kind" = FavoriteKind, Path, description = "Kind of the value"),
+        ("id" = i64, Path, description = "Value id"),
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Whether the favorite was added", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar, source or id does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/store/{avatar}/{kind}/{id}")]
 async fn store(
     credentials: BearerAuth,
     path: ActixWeb::Path<(i64, Kind, i64)>,
+    source: ActixWeb::Query<SourceQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<HttpResponse> {
     let (avatar, kind, id) = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     AvatarEntity::find()
         .filter(AvatarColumn::Id.eq(avatar))
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .one(db.get_ref())
         .await?
         .ok_or(ApiError::WrongAvatar)?;
 
-    let login = login::get_login(&session, &db).await?;
+    let login = login::get_login(&session, source.source, &db, &crypto).await?;
 
     let value = match kind {
-        Kind::Live => {
-            let lives = get_lives(None, Params::new(&login), client).await?;
-            lives
-                .into_iter()
-                .find(|x| x.id == id)
-                .ok_or(ApiError::WrongId)?
-        }
+        Kind::Live => catalog::get_value(&login, CatalogKind::Live, id, &db, client).await?,
         Kind::Movie => {
-            let movie_info = get_movie_info(id, Params::new(&login), client).await?;
-            Value::from_movie_info(movie_info)
+            let movie_info = get_movie_info(id, Params::new(&login), &db, client).await?;
+            Value::from_movie_info(movie_info, login.id)
         }
         Kind::Serie => {
-            let serie_info = get_serie_info(id, Params::new(&login), client).await?;
-            Value::from_serie_info(serie_info, id, None, String::new())
+            let serie_info = get_serie_info(id, Params::new(&login), &db, client).await?;
+            Value::from_serie_info(serie_info, id, None, String::new(), login.id)
         }
     };
 
     let exist = FavoriteEntity::find()
         .filter(FavoriteColumn::AvatarId.eq(avatar))
+        .filter(FavoriteColumn::LoginId.eq(login.id))
         .filter(FavoriteColumn::Kind.eq(kind.clone()))
         .filter(FavoriteColumn::ValueId.eq(value.id))
         .one(db.get_ref())
@@ -112,6 +150,7 @@ async fn store(
         FavoriteEntity::insert(FavoriteActiveModel {
             id: Default::default(),
             avatar_id: ActiveValue::Set(avatar),
+            login_id: ActiveValue::Set(login.id),
             kind: ActiveValue::Set(kind),
             value_id: ActiveValue::Set(value.id),
             name: ActiveValue::Set(value.name),
@@ -126,26 +165,50 @@ async fn store(
     }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/favorite/remove/{avatar}/{kind}/{id}",
+    tag = "favorite",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+        ("kind" = FavoriteKind, Path, description = "Kind of the value"),
+        ("id" = i64, Path, description = "Value id"),
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Whether the favorite was removed", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar, source or id does not exist", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/remove/{avatar}/{kind}/{id}")]
 async fn remove(
     credentials: BearerAuth,
     path: ActixWeb::Path<(i64, Kind, i64)>,
+    source: ActixWeb::Query<SourceQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let (avatar, kind, id) = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     AvatarEntity::find()
         .filter(AvatarColumn::Id.eq(avatar))
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .one(db.get_ref())
         .await?
         .ok_or(ApiError::WrongAvatar)?;
 
+    let login = login::get_login(&session, source.source, &db, &crypto).await?;
+
     let favorite: FavoriteActiveModel = FavoriteEntity::find()
         .filter(FavoriteColumn::AvatarId.eq(avatar))
+        .filter(FavoriteColumn::LoginId.eq(login.id))
         .filter(FavoriteColumn::Kind.eq(kind))
         .filter(FavoriteColumn::ValueId.eq(id))
         .one(db.get_ref())
@@ -1,103 +1,287 @@
-use actix_web::{web as ActixWeb, HttpResponse};
+use actix_web::{http::header::ContentType, web as ActixWeb, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use sea_orm::DatabaseConnection;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use utoipa::{IntoParams, ToSchema};
 
+pub use crate::entities::catalog_stream::Kind;
 use crate::{
     api_error::ApiResult,
-    extra::{default_on_null, get_json, num_from_str_or_num, IdType, Params},
-    login,
+    catalog,
+    client::Client,
+    crypto::Crypto,
+    entities::{login::Backend, prelude::*},
+    epg,
+    extra::{
+        default_on_null, get_json, get_json_stream, ignore, num_from_str_or_num,
+        opt_num_from_str_or_num, IdType, JsonArray, Params,
+    },
+    login::{self, SourceQuery},
+    xmltv,
 };
 
-#[derive(Serialize)]
+#[derive(ToSchema, Serialize)]
 #[serde(untagged)]
-enum ResultInfo {
+pub enum ResultInfo {
     Live(Vec<Epg>),
     Movie(Box<MovieInfo>),
     Serie(Box<SerieInfo>),
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum Kind {
-    Live,
-    Movie,
-    Serie,
-}
-
 #[derive(Deserialize)]
 struct Get {
     kind: Kind,
     category_id: Option<i64>,
 }
 
-#[actix_web::routes]
-#[get("/all/{kind}")]
-#[get("/category/{kind}/{category_id}")]
-async fn get(
+#[derive(Clone, Copy, Debug, ToSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sort {
+    Name,
+    Added,
+    Rating,
+}
+
+#[derive(Clone, Copy, Debug, ToSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Default, IntoParams, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct Page {
+    /// Maximum number of values returned
+    pub limit: Option<u64>,
+    /// Number of values skipped
+    pub offset: Option<u64>,
+    pub sort: Option<Sort>,
+    pub order: Option<Direction>,
+    /// Comma separated fields kept in each value, like `id,name`
+    fields: Option<String>,
+}
+
+impl Page {
+    /// Keeps only the comma separated `fields` of a value.
+    fn project(fields: &[String], value: Value) -> serde_json::Result<serde_json::Value> {
+        let mut value = serde_json::to_value(value)?;
+
+        if let serde_json::Value::Object(map) = &mut value {
+            map.retain(|key, _| fields.contains(key));
+        }
+
+        Ok(value)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/get/all/{kind}",
+    tag = "get",
+    params(
+        ("kind" = Kind, Path, description = "Kind of the values"),
+        Page,
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Values of every login, merged", body = Vec<CatalogValue>,
+            headers(("X-Total-Count" = u64, description = "Number of values before paging"))),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/all/{kind}")]
+async fn all(
     credentials: BearerAuth,
     path: ActixWeb::Path<Get>,
+    page: ActixWeb::Query<Page>,
+    source: ActixWeb::Query<SourceQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<HttpResponse> {
-    let get = path.into_inner();
-    let auth_key = credentials.token();
+    list(
+        credentials,
+        path.into_inner(),
+        page,
+        source.source,
+        db,
+        crypto,
+        client,
+    )
+    .await
+}
 
-    let session = login::get_session(auth_key, &db).await?;
-    let login = login::get_login(&session, &db).await?;
+#[utoipa::path(
+    get,
+    path = "/get/category/{kind}/{category_id}",
+    tag = "get",
+    params(
+        ("kind" = Kind, Path, description = "Kind of the values"),
+        ("category_id" = i64, Path, description = "Category of the values"),
+        Page,
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Values of the category", body = Vec<CatalogValue>,
+            headers(("X-Total-Count" = u64, description = "Number of values before paging"))),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/category/{kind}/{category_id}")]
+async fn category(
+    credentials: BearerAuth,
+    path: ActixWeb::Path<Get>,
+    page: ActixWeb::Query<Page>,
+    source: ActixWeb::Query<SourceQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    list(
+        credentials,
+        path.into_inner(),
+        page,
+        source.source,
+        db,
+        crypto,
+        client,
+    )
+    .await
+}
 
-    let params = Params::new(&login);
+async fn list(
+    credentials: BearerAuth,
+    get: Get,
+    page: ActixWeb::Query<Page>,
+    source: Option<i64>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
-    let result = match get.kind {
-        Kind::Live => get_lives(get.category_id, params, client).await?,
-        Kind::Movie => get_movies(get.category_id, params, client).await?,
-        Kind::Serie => get_series(get.category_id, params, client).await?,
+    // Category ids belong to a single login.
+    let logins = if source.is_some() || get.category_id.is_some() {
+        vec![login::get_login(&session, source, &db, &crypto).await?]
+    } else {
+        login::get_logins(&session, &db, &crypto).await?
     };
 
-    Ok(HttpResponse::Ok().json(result))
+    let (values, total) =
+        catalog::get_page(&logins, get.kind, get.category_id, &page, &db, client).await?;
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(("X-Total-Count", total))
+        .content_type(ContentType::json());
+
+    let values = values.into_iter();
+
+    match &page.fields {
+        Some(fields) => {
+            let fields = fields
+                .split(',')
+                .map(|x| x.trim().to_string())
+                .collect::<Vec<String>>();
+
+            Ok(response.body(JsonArray::new(
+                values.map(move |x| Page::project(&fields, x)),
+            )))
+        }
+        None => Ok(response.body(JsonArray::new(values.map(Ok)))),
+    }
 }
 
+#[utoipa::path(
+    get,
+    path = "/get/info/{kind}/{id}",
+    tag = "get",
+    params(
+        ("kind" = Kind, Path, description = "Kind of the value"),
+        ("id" = i64, Path, description = "Value id"),
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Epg of a live, or details of a movie or serie", body = ResultInfo),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/info/{kind}/{id}")]
-async fn info<'a>(
+async fn info(
     credentials: BearerAuth,
     path: ActixWeb::Path<(Kind, i64)>,
+    source: ActixWeb::Query<SourceQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<HttpResponse> {
     let (kind, id) = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
-    let login = login::get_login(&session, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let login = login::get_login(&session, source.source, &db, &crypto).await?;
 
     let params = Params::new(&login);
 
     let result = match kind {
-        Kind::Live => ResultInfo::Live(get_live_epg(id, params, client).await?),
-        Kind::Movie => ResultInfo::Movie(Box::new(get_movie_info(id, params, client).await?)),
-        Kind::Serie => ResultInfo::Serie(Box::new(get_serie_info(id, params, client).await?)),
+        Kind::Live => ResultInfo::Live(get_live_epg(id, params, &db, client).await?),
+        Kind::Movie => ResultInfo::Movie(Box::new(get_movie_info(id, params, &db, client).await?)),
+        Kind::Serie => ResultInfo::Serie(Box::new(get_serie_info(id, params, &db, client).await?)),
     };
 
     Ok(HttpResponse::Ok().json(result))
 }
 
+#[utoipa::path(
+    get,
+    path = "/get/categories/{kind}",
+    tag = "get",
+    params(
+        ("kind" = Kind, Path, description = "Kind of the categories"),
+    ),
+    responses(
+        (status = 200, description = "Categories of every login", body = Vec<CatalogCategory>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/categories/{kind}")]
-async fn categories<'a>(
+async fn categories(
     credentials: BearerAuth,
     path: ActixWeb::Path<Kind>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<HttpResponse> {
     let kind = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
-    let login = login::get_login(&session, &db).await?;
-
-    let params = Params::new(&login);
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let logins = login::get_logins(&session, &db, &crypto).await?;
 
-    let result = get_categories(&kind, params, client).await?;
+    let result = catalog::get_catalog_categories(&logins, kind, &db, client).await?;
 
     Ok(HttpResponse::Ok().json(result))
 }
@@ -105,62 +289,78 @@ async fn categories<'a>(
 pub async fn get_lives<'a>(
     category_id: Option<i64>,
     mut params: Params<'a>,
-    client: ActixWeb::Data<reqwest::Client>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<Vec<Value>> {
     params.action = Some("get_live_streams");
     params.id = category_id.map(IdType::Category);
 
-    get_json(&params, client).await
+    get_json_stream(&params, client).await
 }
 
-#[derive(Serialize, Deserialize)]
-struct Epg {
+#[derive(Clone, ToSchema, Serialize, Deserialize)]
+pub struct Epg {
     #[serde(default)]
     #[serde(deserialize_with = "default_on_null")]
-    title: String,
+    pub title: String,
 
     #[serde(default)]
     #[serde(deserialize_with = "default_on_null")]
-    description: String,
+    pub description: String,
 
     #[serde(default)]
     #[serde(deserialize_with = "num_from_str_or_num")]
-    start_timestamp: i64,
+    pub start_timestamp: i64,
 
     #[serde(default)]
     #[serde(deserialize_with = "num_from_str_or_num")]
-    stop_timestamp: i64,
-}
+    pub stop_timestamp: i64,
 
-#[derive(Serialize, Deserialize)]
-struct EpgListings {
-    epg_listings: Vec<Epg>,
+    /// Local start time, in the requested timezone or the one of the server.
+    #[schema(example = "2024-01-01 20:00:00")]
+    #[serde(default)]
+    #[serde(deserialize_with = "default_on_null")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+
+    /// Local stop time, same as `start`.
+    #[serde(default)]
+    #[serde(alias = "end")]
+    #[serde(deserialize_with = "default_on_null")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<String>,
 }
 
-async fn get_live_epg<'a>(
+pub async fn get_live_epg<'a>(
     id: i64,
-    mut params: Params<'a>,
-    client: ActixWeb::Data<reqwest::Client>,
+    params: Params<'a>,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<Vec<Epg>> {
-    params.action = Some("get_short_epg");
-    params.id = Some(IdType::Live(id));
+    if params.login.backend == Backend::M3u {
+        let value = catalog::get_value(params.login, Kind::Live, id, db, client.clone()).await?;
+
+        return match value.epg_channel_id {
+            Some(channel) => xmltv::get_short_epg(params.login, &channel, db, client).await,
+            None => Ok(Vec::new()),
+        };
+    }
 
-    Ok(get_json::<EpgListings>(&params, client).await?.epg_listings)
+    epg::get_listings(params, "get_short_epg", id, db, client).await
 }
 
 pub async fn get_movies<'a>(
     category_id: Option<i64>,
     mut params: Params<'a>,
-    client: ActixWeb::Data<reqwest::Client>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<Vec<Value>> {
     params.action = Some("get_vod_streams");
     params.id = category_id.map(IdType::Category);
 
-    get_json(&params, client).await
+    get_json_stream(&params, client).await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Info {
+#[derive(Debug, Default, ToSchema, Serialize, Deserialize)]
+pub struct Info {
     #[serde(default)]
     #[serde(deserialize_with = "default_on_null")]
     name: String,
@@ -204,8 +404,8 @@ struct Info {
     last_modified: i64,
 }
 
-#[derive(Serialize, Deserialize)]
-struct MovieData {
+#[derive(ToSchema, Serialize, Deserialize)]
+pub struct MovieData {
     #[serde(alias = "stream_id")]
     id: i64,
 
@@ -222,7 +422,7 @@ struct MovieData {
     container_extension: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(ToSchema, Serialize, Deserialize)]
 pub struct MovieInfo {
     info: Info,
 
@@ -230,11 +430,36 @@ pub struct MovieInfo {
     data: MovieData,
 }
 
+impl MovieInfo {
+    /// Playlists only know what their entry says.
+    fn from_value(value: Value) -> MovieInfo {
+        MovieInfo {
+            info: Info {
+                name: value.name.clone(),
+                icon: value.icon,
+                ..Default::default()
+            },
+            data: MovieData {
+                id: value.id,
+                added: value.added,
+                name: value.name,
+                container_extension: value.container_extension,
+            },
+        }
+    }
+}
+
 pub async fn get_movie_info<'a>(
     id: i64,
     mut params: Params<'a>,
-    client: ActixWeb::Data<reqwest::Client>,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<MovieInfo> {
+    if params.login.backend == Backend::M3u {
+        let value = catalog::get_value(params.login, Kind::Movie, id, db, client).await?;
+        return Ok(MovieInfo::from_value(value));
+    }
+
     params.action = Some("get_vod_info");
     params.id = Some(IdType::Movie(id));
 
@@ -244,30 +469,30 @@ pub async fn get_movie_info<'a>(
 pub async fn get_series<'a>(
     category_id: Option<i64>,
     mut params: Params<'a>,
-    client: ActixWeb::Data<reqwest::Client>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<Vec<Value>> {
     params.action = Some("get_series");
     params.id = category_id.map(IdType::Category);
 
-    get_json(&params, client).await
+    get_json_stream(&params, client).await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Season {
+#[derive(Debug, ToSchema, Serialize, Deserialize)]
+pub struct Season {
     #[serde(default)]
     #[serde(deserialize_with = "default_on_null")]
     poster_path: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct EpisodeInfo {
+#[derive(Debug, ToSchema, Serialize, Deserialize)]
+pub struct EpisodeInfo {
     #[serde(default)]
     #[serde(alias = "movie_image")]
     #[serde(deserialize_with = "default_on_null")]
     image: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, ToSchema, Serialize, Deserialize)]
 pub struct Episode {
     #[serde(deserialize_with = "num_from_str_or_num")]
     pub id: i64,
@@ -283,18 +508,49 @@ pub struct Episode {
     info: EpisodeInfo,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, ToSchema, Serialize, Deserialize)]
 pub struct SerieInfo {
     info: Info,
     seasons: Vec<Season>,
     pub episodes: BTreeMap<String, Vec<Episode>>,
 }
 
+impl SerieInfo {
+    /// Playlists list every episode as its own entry, so the serie is that
+    /// single episode.
+    fn from_value(value: Value) -> SerieInfo {
+        let episode = Episode {
+            id: value.id,
+            title: value.name.clone(),
+            container_extension: value.container_extension,
+            info: EpisodeInfo {
+                image: value.icon.clone(),
+            },
+        };
+
+        SerieInfo {
+            info: Info {
+                name: value.name,
+                icon: value.icon,
+                ..Default::default()
+            },
+            seasons: Vec::new(),
+            episodes: BTreeMap::from([("1".to_string(), vec![episode])]),
+        }
+    }
+}
+
 pub async fn get_serie_info<'a>(
     id: i64,
     mut params: Params<'a>,
-    client: ActixWeb::Data<reqwest::Client>,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<SerieInfo> {
+    if params.login.backend == Backend::M3u {
+        let value = catalog::get_value(params.login, Kind::Serie, id, db, client).await?;
+        return Ok(SerieInfo::from_value(value));
+    }
+
     params.action = Some("get_series_info");
     params.id = Some(IdType::Serie(id));
 
@@ -302,21 +558,21 @@ pub async fn get_serie_info<'a>(
 }
 
 #[derive(Serialize, Deserialize)]
-struct Category {
+pub struct Category {
     #[serde(alias = "category_id")]
     #[serde(deserialize_with = "num_from_str_or_num")]
-    id: i64,
+    pub id: i64,
 
     #[serde(default)]
     #[serde(alias = "category_name")]
     #[serde(deserialize_with = "default_on_null")]
-    name: String,
+    pub name: String,
 }
 
-async fn get_categories<'a>(
+pub async fn get_categories<'a>(
     kind: &'a Kind,
     mut params: Params<'a>,
-    client: ActixWeb::Data<reqwest::Client>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<Vec<Category>> {
     params.action = Some(match kind {
         Kind::Live => "get_live_categories",
@@ -327,7 +583,8 @@ async fn get_categories<'a>(
     get_json(&params, client).await
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, ToSchema, Deserialize, Serialize)]
+#[schema(as = CatalogValue)]
 pub struct Value {
     #[serde(alias = "series_id")]
     #[serde(alias = "stream_id")]
@@ -352,24 +609,70 @@ pub struct Value {
     #[serde(deserialize_with = "num_from_str_or_num")]
     pub rating: f64,
 
-    #[serde(skip_deserializing)]
+    #[serde(default)]
+    #[serde(deserialize_with = "opt_num_from_str_or_num")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category_id: Option<i64>,
+
+    #[serde(default)]
+    #[serde(deserialize_with = "opt_num_from_str_or_num")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<i64>,
+
+    #[serde(default)]
+    #[serde(deserialize_with = "ignore")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub episode_id: Option<i64>,
 
-    #[serde(skip_deserializing)]
+    #[serde(default)]
+    #[serde(deserialize_with = "default_on_null")]
     pub container_extension: String,
+
+    /// Login the id belongs to, passed as `source` wherever the id is used.
+    #[serde(default)]
+    #[serde(deserialize_with = "ignore")]
+    pub source: i64,
+
+    /// The same title on the other logins of the account.
+    #[serde(default)]
+    #[serde(deserialize_with = "ignore")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<Alternative>,
+
+    /// Channel of a live in XMLTV guides.
+    #[serde(default)]
+    #[serde(deserialize_with = "default_on_null")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epg_channel_id: Option<String>,
+
+    /// Playable url given by a playlist, links are built from it instead of
+    /// the panel.
+    #[serde(skip)]
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize)]
+pub struct Alternative {
+    pub source: i64,
+    pub id: i64,
 }
 
 impl Value {
-    pub fn from_movie_info(movie_info: MovieInfo) -> Value {
+    pub fn from_movie_info(movie_info: MovieInfo, source: i64) -> Value {
         Value {
             id: movie_info.data.id,
             name: movie_info.data.name,
             icon: movie_info.info.icon,
             added: movie_info.data.added,
             rating: movie_info.info.rating,
+            category_id: None,
+            year: None,
             episode_id: None,
             container_extension: movie_info.data.container_extension,
+            source,
+            alternatives: Vec::new(),
+            epg_channel_id: None,
+            url: None,
         }
     }
     pub fn from_serie_info(
@@ -377,6 +680,7 @@ impl Value {
         value_id: i64,
         episode_id: Option<i64>,
         container_extension: String,
+        source: i64,
     ) -> Value {
         Value {
             id: value_id,
@@ -384,8 +688,34 @@ impl Value {
             icon: serie_info.info.icon,
             added: serie_info.info.last_modified,
             rating: serie_info.info.rating,
+            category_id: None,
+            year: None,
             episode_id,
             container_extension,
+            source,
+            alternatives: Vec::new(),
+            epg_channel_id: None,
+            url: None,
+        }
+    }
+}
+
+impl From<CatalogStream> for Value {
+    fn from(stream: CatalogStream) -> Value {
+        Value {
+            id: stream.value_id,
+            name: stream.name,
+            icon: stream.icon,
+            added: stream.added,
+            rating: stream.rating,
+            category_id: stream.category_id,
+            year: stream.year,
+            episode_id: None,
+            container_extension: stream.container_extension,
+            source: stream.login_id,
+            alternatives: Vec::new(),
+            epg_channel_id: stream.epg_channel_id,
+            url: stream.url,
         }
     }
 }
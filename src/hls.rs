@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+
+use crate::api_error::{ApiError, ApiResult};
+
+/// Tags whose `URI` attribute points at another resource of the stream.
+const URI_TAGS: [&str; 8] = [
+    "#EXT-X-KEY",
+    "#EXT-X-SESSION-KEY",
+    "#EXT-X-MAP",
+    "#EXT-X-MEDIA",
+    "#EXT-X-I-FRAME-STREAM-INF",
+    "#EXT-X-PART",
+    "#EXT-X-PRELOAD-HINT",
+    "#EXT-X-RENDITION-REPORT",
+];
+
+/// Caps on the variant kept from a master playlist, the best one under them
+/// is kept, or the smallest when none is.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VariantLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u64>,
+}
+
+impl VariantLimit {
+    pub fn is_empty(&self) -> bool {
+        self.max_bandwidth.is_none() && self.max_height.is_none()
+    }
+
+    fn allows(&self, variant: &Variant) -> bool {
+        self.max_bandwidth.is_none_or(|x| variant.bandwidth <= x)
+            && self
+                .max_height
+                .is_none_or(|x| variant.height.is_none_or(|y| y <= x))
+    }
+}
+
+/// A rewritten playlist.
+pub struct Playlist {
+    pub body: String,
+    /// Media playlist of a live stream, reloaded by players every few
+    /// seconds so it must not be cached.
+    pub live: bool,
+}
+
+/// An `#EXT-X-STREAM-INF` of a master playlist and the line of its url.
+struct Variant {
+    tag: usize,
+    uri: usize,
+    bandwidth: u64,
+    height: Option<u64>,
+}
+
+/// Rewrites every url of a master or media playlist with `map`, keeping a
+/// single variant when `limit` is set.
+pub fn rewrite<F>(text: &str, limit: &VariantLimit, mut map: F) -> ApiResult<Playlist>
+where
+    F: FnMut(&str) -> ApiResult<String>,
+{
+    let text = text.trim_start_matches('\u{feff}');
+    if !text.trim_start().starts_with("#EXTM3U") {
+        return Err(ApiError::Serde("stream is not an hls playlist".into()));
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    let skipped = skipped_variants(&lines, limit);
+
+    let mut body = String::with_capacity(text.len());
+    let mut media = false;
+    let mut ended = false;
+
+    for (index, line) in lines.iter().enumerate() {
+        if skipped.contains(&index) {
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            body.push_str(line);
+        } else if let Some(tag) = trimmed.strip_prefix('#') {
+            media |= tag.starts_with("EXTINF") || tag.starts_with("EXT-X-TARGETDURATION");
+            ended |= tag.starts_with("EXT-X-ENDLIST") || tag == "EXT-X-PLAYLIST-TYPE:VOD";
+
+            body.push_str(&rewrite_tag(trimmed, &mut map)?);
+        } else {
+            body.push_str(&map(trimmed)?);
+        }
+        body.push('\n');
+    }
+
+    Ok(Playlist {
+        body,
+        live: media && !ended,
+    })
+}
+
+fn rewrite_tag<F>(line: &str, map: &mut F) -> ApiResult<String>
+where
+    F: FnMut(&str) -> ApiResult<String>,
+{
+    let Some((tag, list)) = line.split_once(':') else {
+        return Ok(line.to_string());
+    };
+    if !URI_TAGS.contains(&tag) {
+        return Ok(line.to_string());
+    }
+
+    let mut attributes = Vec::new();
+    for (key, value) in attributes_of(list) {
+        if key == "URI" {
+            let uri = map(value.trim_matches('"'))?;
+            attributes.push(format!("URI=\"{uri}\""));
+        } else {
+            attributes.push(format!("{key}={value}"));
+        }
+    }
+
+    Ok(format!("{}:{}", tag, attributes.join(",")))
+}
+
+/// Lines of the variants dropped by `limit`, tags and urls alike.
+fn skipped_variants(lines: &[&str], limit: &VariantLimit) -> Vec<usize> {
+    if limit.is_empty() {
+        return Vec::new();
+    }
+
+    let mut variants = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let Some(list) = line.trim().strip_prefix("#EXT-X-STREAM-INF:") else {
+            continue;
+        };
+        let Some(uri) = (index + 1..lines.len()).find(|&x| {
+            let line = lines[x].trim();
+            !line.is_empty() && !line.starts_with('#')
+        }) else {
+            continue;
+        };
+
+        let attributes = attributes_of(list);
+        let value = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| *value)
+        };
+
+        variants.push(Variant {
+            tag: index,
+            uri,
+            bandwidth: value("BANDWIDTH")
+                .and_then(|x| x.parse().ok())
+                .unwrap_or_default(),
+            height: value("RESOLUTION")
+                .and_then(|x| x.split_once('x'))
+                .and_then(|(_, x)| x.parse().ok()),
+        });
+    }
+
+    let kept = variants
+        .iter()
+        .filter(|x| limit.allows(x))
+        .max_by_key(|x| x.bandwidth)
+        .or_else(|| variants.iter().min_by_key(|x| x.bandwidth))
+        .map(|x| x.tag);
+
+    variants
+        .iter()
+        .filter(|x| Some(x.tag) != kept)
+        .flat_map(|x| [x.tag, x.uri])
+        .collect()
+}
+
+/// Splits an attribute list on the commas outside quoted strings.
+fn attributes_of(list: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (index, char) in list.char_indices() {
+        match char {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                attributes.extend(list[start..index].split_once('='));
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    attributes.extend(list[start..].split_once('='));
+
+    attributes
+        .into_iter()
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = "#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"en, main\",URI=\"audio/en.m3u8\"
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,CODECS=\"avc1,mp4a\"
+low.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,RESOLUTION=1280x720
+mid.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,RESOLUTION=1920x1080
+high.m3u8
+";
+
+    fn rewrite_urls(text: &str, limit: &VariantLimit) -> Playlist {
+        rewrite(text, limit, |x| Ok(format!("relay/{x}"))).unwrap()
+    }
+
+    fn urls(playlist: &Playlist) -> Vec<&str> {
+        playlist
+            .body
+            .lines()
+            .filter(|x| !x.starts_with('#') && !x.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn rewrites_every_url() {
+        let playlist = rewrite_urls(MASTER, &VariantLimit::default());
+
+        assert_eq!(
+            urls(&playlist),
+            ["relay/low.m3u8", "relay/mid.m3u8", "relay/high.m3u8"]
+        );
+        assert!(playlist
+            .body
+            .contains("NAME=\"en, main\",URI=\"relay/audio/en.m3u8\""));
+        assert!(playlist.body.contains("CODECS=\"avc1,mp4a\""));
+        assert!(!playlist.live);
+    }
+
+    #[test]
+    fn keeps_the_best_variant_under_the_limits() {
+        let limit = VariantLimit {
+            max_bandwidth: Some(3000000),
+            max_height: None,
+        };
+        assert_eq!(urls(&rewrite_urls(MASTER, &limit)), ["relay/mid.m3u8"]);
+
+        let limit = VariantLimit {
+            max_bandwidth: None,
+            max_height: Some(480),
+        };
+        assert_eq!(urls(&rewrite_urls(MASTER, &limit)), ["relay/low.m3u8"]);
+    }
+
+    #[test]
+    fn keeps_the_smallest_variant_when_none_fits() {
+        let limit = VariantLimit {
+            max_bandwidth: Some(1),
+            max_height: None,
+        };
+        let playlist = rewrite_urls(MASTER, &limit);
+
+        assert_eq!(urls(&playlist), ["relay/low.m3u8"]);
+        assert_eq!(playlist.body.matches("#EXT-X-STREAM-INF").count(), 1);
+    }
+
+    #[test]
+    fn tells_live_media_playlists() {
+        let live = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n#EXTINF:6,\nseg1.ts\n";
+        let playlist = rewrite_urls(live, &VariantLimit::default());
+
+        assert!(playlist.live);
+        assert!(playlist.body.contains("URI=\"relay/key.bin\""));
+        assert_eq!(urls(&playlist), ["relay/seg1.ts"]);
+
+        let vod = format!("{live}#EXT-X-ENDLIST\n");
+        assert!(!rewrite_urls(&vod, &VariantLimit::default()).live);
+    }
+
+    #[test]
+    fn refuses_what_is_not_a_playlist() {
+        assert!(rewrite("<html>", &VariantLimit::default(), |x| Ok(x.to_string())).is_err());
+    }
+}
@@ -1,36 +1,53 @@
 use actix_web::{web as ActixWeb, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use ordered_float::OrderedFloat;
-use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
 use serde::Serialize;
-use std::cmp::Reverse;
+use std::{cmp::Reverse, collections::BTreeMap};
+use utoipa::ToSchema;
 
 use crate::{
     api_error::ApiResult,
-    entities::{home::Kind, prelude::*},
-    extra::{get_days_ago, Params},
-    get::{get_movies, get_series, Value},
+    catalog,
+    client::Client,
+    crypto::Crypto,
+    entities::{catalog_stream::Kind as CatalogKind, home::Kind, prelude::*},
+    extra::get_days_ago,
+    get::Value,
     login,
 };
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(ToSchema, Serialize, Debug, Clone)]
 pub struct Homes {
     top: Vec<Home>,
     movies: Vec<Home>,
     series: Vec<Home>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/home",
+    tag = "home",
+    responses(
+        (status = 200, description = "Top, newest movies and newest series", body = Homes),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/home")]
 pub async fn home(
     credentials: BearerAuth,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     let homev = HomeEntity::find()
-        .filter(HomeColumn::SessionId.eq(session.id))
+        .filter(HomeColumn::AccountId.eq(session.account_id))
         .all(db.get_ref())
         .await?;
 
@@ -57,26 +74,39 @@ pub async fn home(
 
 pub async fn make_homes(
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+    home_days: i64,
 ) -> ApiResult<()> {
-    let month_ago = get_days_ago(30);
-    let logins = LoginEntity::find().all(db.get_ref()).await?;
+    let month_ago = get_days_ago(home_days);
+    let mut accounts: BTreeMap<i64, Vec<Login>> = BTreeMap::new();
 
-    for login in logins {
-        make(&login, month_ago, db.clone(), client.clone()).await?;
+    for login in LoginEntity::find()
+        .order_by_asc(LoginColumn::Id)
+        .all(db.get_ref())
+        .await?
+    {
+        let login = crypto.open_login(login)?;
+        accounts.entry(login.account_id).or_default().push(login);
+    }
+
+    for (account_id, logins) in accounts {
+        make(account_id, &logins, month_ago, db.clone(), client.clone()).await?;
     }
 
     Ok(())
 }
 
+/// Rebuilds the home rows of an account from the merged catalog of its logins.
 pub async fn make(
-    login: &Login,
+    account_id: i64,
+    logins: &[Login],
     month_ago: i64,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<()> {
-    let mut movies = get_movies(None, Params::new(login), client.clone()).await?;
-    let mut series = get_series(None, Params::new(login), client).await?;
+    let mut movies = catalog::get_values(logins, CatalogKind::Movie, &db, client.clone()).await?;
+    let mut series = catalog::get_values(logins, CatalogKind::Serie, &db, client).await?;
 
     let mut tops = movies
         .clone()
@@ -96,14 +126,15 @@ pub async fn make(
     series.truncate(20);
 
     HomeEntity::delete_many()
-        .filter(HomeColumn::SessionId.eq(login.id))
+        .filter(HomeColumn::AccountId.eq(account_id))
         .exec(db.get_ref())
         .await?;
 
     for (kind, top) in tops {
         HomeEntity::insert(HomeActiveModel {
             id: Default::default(),
-            session_id: ActiveValue::Set(login.id),
+            account_id: ActiveValue::Set(account_id),
+            login_id: ActiveValue::Set(top.source),
             kind: ActiveValue::Set(kind),
             value_id: ActiveValue::Set(top.id),
             name: ActiveValue::Set(top.name),
@@ -116,7 +147,8 @@ pub async fn make(
     for movie in movies {
         HomeEntity::insert(HomeActiveModel {
             id: Default::default(),
-            session_id: ActiveValue::Set(login.id),
+            account_id: ActiveValue::Set(account_id),
+            login_id: ActiveValue::Set(movie.source),
             kind: ActiveValue::Set(Kind::Movie),
             value_id: ActiveValue::Set(movie.id),
             name: ActiveValue::Set(movie.name),
@@ -129,7 +161,8 @@ pub async fn make(
     for serie in series {
         HomeEntity::insert(HomeActiveModel {
             id: Default::default(),
-            session_id: ActiveValue::Set(login.id),
+            account_id: ActiveValue::Set(account_id),
+            login_id: ActiveValue::Set(serie.source),
             kind: ActiveValue::Set(Kind::Serie),
             value_id: ActiveValue::Set(serie.id),
             name: ActiveValue::Set(serie.name),
@@ -0,0 +1,169 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use utoipa::ToSchema;
+
+use crate::{api_error::ApiError, config::UpstreamConfig, entities::prelude::*};
+
+#[derive(Default)]
+struct Host {
+    /// Failures since the last success.
+    failures: u32,
+    /// Set once `failures` reaches the limit, the host is skipped until then.
+    down_until: Option<Instant>,
+    last_success: Option<i64>,
+    last_failure: Option<i64>,
+}
+
+/// What an account sees of a host. Hosts are shared by every account whose
+/// logins point at them, so errors and counts of other accounts stay out.
+#[derive(ToSchema, Serialize)]
+pub struct HostHealth {
+    server: String,
+    /// Whether this is the server the login was added with.
+    primary: bool,
+    /// False while requests to the host fail fast after repeated failures.
+    up: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_success: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_failure: Option<i64>,
+}
+
+/// Health of every upstream host, shared by all requests so a host that keeps
+/// failing is skipped by everyone until its cooldown ends. The next request
+/// after that tries it again, and a success puts it back in its place.
+pub struct Hosts {
+    max_failures: u32,
+    cooldown: Duration,
+    state: Mutex<HashMap<String, Host>>,
+}
+
+impl Hosts {
+    pub fn new(config: &UpstreamConfig) -> Hosts {
+        Hosts {
+            max_failures: config.host_max_failures,
+            cooldown: Duration::from_secs(config.host_cooldown_secs),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Servers of a login in the order they should be tried. Hosts that are
+    /// up keep the order of the login, so the primary is preferred whenever it
+    /// works. A host that is down is left out until its cooldown ends, then
+    /// the first request to come along gets to probe it while the others keep
+    /// failing fast. Empty when every host of the login is down.
+    pub fn order<'a>(&self, login: &'a Login) -> Vec<&'a str> {
+        let Ok(mut state) = self.state.lock() else {
+            return login.servers().collect();
+        };
+
+        let now = Instant::now();
+        let mut up = Vec::new();
+        let mut probes = Vec::new();
+
+        for server in login.servers() {
+            match state.get_mut(&host_key(server)) {
+                Some(host) => match host.down_until {
+                    None => up.push(server),
+                    Some(until) if until <= now => {
+                        host.down_until = Some(now + self.cooldown);
+                        probes.push(server);
+                    }
+                    Some(_) => {}
+                },
+                None => up.push(server),
+            }
+        }
+
+        up.extend(probes);
+        up
+    }
+
+    /// Server links to streams should point at, the first one that is up, or
+    /// the primary when none is.
+    pub fn preferred<'a>(&self, login: &'a Login) -> &'a str {
+        let Ok(state) = self.state.lock() else {
+            return &login.server;
+        };
+
+        let now = Instant::now();
+        login
+            .servers()
+            .find(|server| {
+                state
+                    .get(&host_key(server))
+                    .and_then(|x| x.down_until)
+                    .is_none_or(|x| x <= now)
+            })
+            .unwrap_or(&login.server)
+    }
+
+    pub fn success(&self, server: &str) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+
+        let host = state.entry(host_key(server)).or_default();
+        host.failures = 0;
+        host.down_until = None;
+        host.last_success = Some(chrono::Utc::now().timestamp());
+    }
+
+    pub fn failure(&self, server: &str, error: &ApiError) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+
+        let host = state.entry(host_key(server)).or_default();
+        host.failures += 1;
+        host.last_failure = Some(chrono::Utc::now().timestamp());
+
+        if host.failures >= self.max_failures {
+            if host.down_until.is_none() {
+                tracing::warn!(host = host_key(server), %error, "upstream host is down");
+            }
+            host.down_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    pub fn health(&self, login: &Login) -> Vec<HostHealth> {
+        let state = self.state.lock().ok();
+        let now = Instant::now();
+
+        login
+            .servers()
+            .map(|server| {
+                let host = state.as_ref().and_then(|x| x.get(&host_key(server)));
+
+                HostHealth {
+                    server: server.to_string(),
+                    primary: server == login.server,
+                    up: host.and_then(|x| x.down_until).is_none_or(|x| x <= now),
+                    last_success: host.and_then(|x| x.last_success),
+                    last_failure: host.and_then(|x| x.last_failure),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether an upstream error says the host is unusable, rather than the
+/// request being wrong, so another host should be tried.
+pub fn is_host_failure(error: &ApiError) -> bool {
+    match error {
+        ApiError::Reqwest(_) | ApiError::Timeout(_) | ApiError::Dns(_) | ApiError::Tls(_) => true,
+        ApiError::RequestServerError(status) => *status >= 500,
+        _ => false,
+    }
+}
+
+/// Hosts are tracked by origin, every path on them shares the same health.
+fn host_key(server: &str) -> String {
+    url::Url::parse(server)
+        .map(|x| x.origin().ascii_serialization())
+        .unwrap_or_else(|_| server.to_string())
+}
@@ -1,37 +1,60 @@
 use actix_web::{web as ActixWeb, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter};
 
 use crate::{
     api_error::{ApiError, ApiResult},
+    client::Client,
+    crypto::Crypto,
     entities::prelude::*,
-    login::{self, get_login_info},
+    login::{self, get_login_info, SourceQuery},
 };
 
+#[utoipa::path(
+    get,
+    path = "/info",
+    tag = "info",
+    params(
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Account information from the server", body = UserInfo),
+        (status = 401, description = "Auth key is invalid or account expired", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/info")]
 async fn info(
     credentials: BearerAuth,
+    source: ActixWeb::Query<SourceQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<HttpResponse> {
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
-    let user_info = get_update_user_info(session, db, client).await?;
+    let user_info = get_update_user_info(session, source.source, db, crypto, client).await?;
 
     Ok(HttpResponse::Ok().json(user_info))
 }
 
 async fn get_update_user_info(
     session: Session,
+    source: Option<i64>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<UserInfo> {
-    let login = login::get_login(&session, &db).await?;
+    let login = login::get_login(&session, source, &db, &crypto).await?;
 
     let user_info = UserInfoEntity::find()
-        .filter(UserInfoColumn::Id.eq(session.id))
+        .filter(UserInfoColumn::Id.eq(login.id))
         .one(db.get_ref())
         .await?
         .ok_or(ApiError::AccountNotFound)?;
@@ -47,8 +70,21 @@ async fn get_update_user_info(
             Ok(user_info_ret)
         }
         Err(err) => {
+            // Every device is logged out when the account has no other login,
+            // the account is kept so avatars and progress are still there if
+            // the subscription comes back.
             if let ApiError::AccountNotFound = err {
-                SessionEntity::delete(Into::<SessionActiveModel>::into(session))
+                let logins = LoginEntity::find()
+                    .filter(LoginColumn::AccountId.eq(session.account_id))
+                    .count(db.get_ref())
+                    .await?;
+
+                if logins > 1 {
+                    return Err(err);
+                }
+
+                SessionEntity::delete_many()
+                    .filter(SessionColumn::AccountId.eq(session.account_id))
                     .exec(db.get_ref())
                     .await?;
             }
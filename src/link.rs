@@ -1,14 +1,24 @@
-use actix_web::{web as ActixWeb, HttpResponse};
+use actix_web::{http::header, web as ActixWeb, HttpRequest, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
-use sea_orm::DatabaseConnection;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    api_error::ApiResult,
-    login::{self},
+    api_error::{ApiError, ApiResult},
+    catalog,
+    client::Client,
+    config::Config,
+    crypto::Crypto,
+    entities::{catalog_stream::Kind as CatalogKind, login::Backend, prelude::*},
+    hls::VariantLimit,
+    login,
+    playback::{self, Playback},
+    relay, session,
 };
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Debug, PartialEq, ToSchema, Deserialize)]
+#[schema(as = LinkKind)]
 #[serde(rename_all = "snake_case")]
 pub enum Kind {
     Live,
@@ -16,6 +26,77 @@ pub enum Kind {
     Serie,
 }
 
+#[derive(IntoParams, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct LinkQuery {
+    /// Login the id belongs to, the first login of the account when missing
+    source: Option<i64>,
+    /// Avatar the stream is played on, signed links stop working when it is
+    /// removed
+    avatar: Option<i64>,
+    /// Keep the best hls variant of at most this many bits per second, needs
+    /// the stream relay
+    max_bandwidth: Option<u64>,
+    /// Keep the best hls variant of at most this many lines, needs the stream
+    /// relay
+    max_height: Option<u64>,
+}
+
+/// What a signed link grants, every field is covered by the signature.
+#[derive(IntoParams, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct PlayQuery {
+    source: i64,
+    session: i64,
+    avatar: Option<i64>,
+    max_bandwidth: Option<u64>,
+    max_height: Option<u64>,
+    /// Unix time the link stops working
+    expires: i64,
+    signature: String,
+}
+
+impl PlayQuery {
+    /// The signed part of the url, its path and query without the signature.
+    fn message(&self, kind: CatalogKind, id: i64, container_extension: &str) -> String {
+        let mut fields = vec![
+            ("source", Some(self.source.to_string())),
+            ("session", Some(self.session.to_string())),
+            ("avatar", self.avatar.map(|x| x.to_string())),
+            ("max_bandwidth", self.max_bandwidth.map(|x| x.to_string())),
+            ("max_height", self.max_height.map(|x| x.to_string())),
+            ("expires", Some(self.expires.to_string())),
+        ];
+        fields.retain(|(_, value)| value.is_some());
+
+        let query = fields
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value.unwrap_or_default()))
+            .collect::<Vec<String>>()
+            .join("&");
+
+        format!(
+            "play/{}/{}/{}?{}",
+            path_name(kind),
+            id,
+            urlencoding::encode(container_extension),
+            query
+        )
+    }
+}
+
+/// A stream handed to a player, and the session and avatar it was handed to.
+pub struct Target {
+    pub kind: CatalogKind,
+    pub id: i64,
+    pub container_extension: String,
+    pub session: i64,
+    pub avatar: Option<i64>,
+    pub variant: VariantLimit,
+    /// Lifetime of the url instead of the configured one.
+    pub ttl_secs: Option<u64>,
+}
+
 impl std::fmt::Display for Kind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -26,25 +107,288 @@ impl std::fmt::Display for Kind {
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/link/{kind}/{id}/{container_extension}",
+    tag = "link",
+    params(
+        ("kind" = LinkKind, Path, description = "Kind of the stream"),
+        ("id" = i64, Path, description = "Stream or episode id"),
+        ("container_extension" = String, Path, description = "Container extension, like ts, mp4 or m3u8, auto picks one for lives and movies"),
+        LinkQuery,
+    ),
+    responses(
+        (status = 200, description = "Playable url of the stream, signed when signed links are enabled", body = String),
+        (status = 400, description = "Variant selection without the stream relay, or auto extension of an episode", body = ApiErrorJson),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source, avatar or id does not exist", body = ApiErrorJson),
+        (status = 429, description = "Every stream the source allows is in use", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/link/{kind}/{id}/{container_extension}")]
 async fn link(
+    req: HttpRequest,
     credentials: BearerAuth,
     path: ActixWeb::Path<(Kind, i64, String)>,
+    query: ActixWeb::Query<LinkQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<HttpResponse> {
     let (kind, id, container_extension) = path.into_inner();
+    let query = query.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
-    let login = login::get_login(&session, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let login = login::get_login(&session, query.source, &db, &crypto).await?;
+
+    if let Some(avatar) = query.avatar {
+        AvatarEntity::find()
+            .filter(AvatarColumn::Id.eq(avatar))
+            .filter(AvatarColumn::AccountId.eq(session.account_id))
+            .one(db.get_ref())
+            .await?
+            .ok_or(ApiError::WrongAvatar)?;
+    }
+
+    let kind = match kind {
+        Kind::Live => CatalogKind::Live,
+        Kind::Movie => CatalogKind::Movie,
+        Kind::Serie => CatalogKind::Serie,
+    };
+
+    let url = upstream_url(&login, kind, id, &container_extension, &db, client.clone()).await?;
+
+    let target = Target {
+        kind,
+        id,
+        container_extension,
+        session: session.id,
+        avatar: query.avatar,
+        variant: VariantLimit {
+            max_bandwidth: query.max_bandwidth,
+            max_height: query.max_height,
+        },
+        ttl_secs: None,
+    };
+    let playback = Playback::new(session.id, query.avatar, kind, id);
+
+    // A url that can not be made does not hold a stream.
+    let url = player_url(&req, &login, target, url, &crypto)?;
+    playback::start(login.id, playback, &db, &client).await?;
+
+    Ok(HttpResponse::Ok().json(url))
+}
+
+#[utoipa::path(
+    get,
+    path = "/play/{kind}/{id}/{container_extension}",
+    tag = "link",
+    params(
+        ("kind" = LinkKind, Path, description = "Kind of the stream"),
+        ("id" = i64, Path, description = "Stream or episode id"),
+        ("container_extension" = String, Path, description = "Container extension the link was made for"),
+        PlayQuery,
+    ),
+    responses(
+        (status = 307, description = "Redirect to the stream, through the relay when it is enabled"),
+        (status = 403, description = "Link is invalid, expired or its session or avatar was removed", body = ApiErrorJson),
+        (status = 404, description = "Source or id does not exist", body = ApiErrorJson),
+        (status = 429, description = "Every stream the source allows is in use", body = ApiErrorJson),
+    )
+)]
+#[actix_web::route(
+    "/play/{kind}/{id}/{container_extension}",
+    method = "GET",
+    method = "HEAD"
+)]
+async fn play(
+    req: HttpRequest,
+    path: ActixWeb::Path<(Kind, i64, String)>,
+    query: ActixWeb::Query<PlayQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let (kind, id, container_extension) = path.into_inner();
+    let query = query.into_inner();
+
+    let kind = match kind {
+        Kind::Live => CatalogKind::Live,
+        Kind::Movie => CatalogKind::Movie,
+        Kind::Serie => CatalogKind::Serie,
+    };
+
+    let message = query.message(kind, id, &container_extension);
+    if !crypto.verify_signature(&message, &query.signature)
+        || query.expires <= chrono::Utc::now().timestamp()
+    {
+        return Err(ApiError::WrongStreamToken);
+    }
+
+    // Removing the session or the avatar revokes every link made for it.
+    let session = session::find_active(query.session, &db)
+        .await?
+        .ok_or(ApiError::WrongStreamToken)?;
+
+    if let Some(avatar) = query.avatar {
+        AvatarEntity::find()
+            .filter(AvatarColumn::Id.eq(avatar))
+            .filter(AvatarColumn::AccountId.eq(session.account_id))
+            .one(db.get_ref())
+            .await?
+            .ok_or(ApiError::WrongStreamToken)?;
+    }
+
+    let login = login::get_login(&session, Some(query.source), &db, &crypto).await?;
+    let url = upstream_url(&login, kind, id, &container_extension, &db, client.clone()).await?;
+
+    // The device the link was shared with is the one playing it now.
+    let playback = Playback::new(session.id, query.avatar, kind, id);
+    let variant = VariantLimit {
+        max_bandwidth: query.max_bandwidth,
+        max_height: query.max_height,
+    };
+    let url = relay::player_url(&req, url, login.id, &playback, variant, None, &crypto)?;
+    playback::start(login.id, playback, &db, &client).await?;
+
+    Ok(HttpResponse::TemporaryRedirect()
+        .insert_header((header::LOCATION, url))
+        .finish())
+}
+
+/// Url handed to the player: a signed link of PlayerApi when they are
+/// enabled, else the stream itself or its relay.
+pub fn player_url(
+    req: &HttpRequest,
+    login: &Login,
+    target: Target,
+    url: String,
+    crypto: &Crypto,
+) -> ApiResult<String> {
+    let signed = req
+        .app_data::<ActixWeb::Data<Config>>()
+        .filter(|x| x.link.signed);
+    let Some(config) = signed else {
+        let playback = Playback::new(target.session, target.avatar, target.kind, target.id);
+        return relay::player_url(
+            req,
+            url,
+            login.id,
+            &playback,
+            target.variant,
+            target.ttl_secs,
+            crypto,
+        );
+    };
+
+    if !config.relay.enabled && !target.variant.is_empty() {
+        return Err(ApiError::BadRequest(
+            "variant selection needs the stream relay".into(),
+        ));
+    }
+
+    let query = PlayQuery {
+        source: login.id,
+        session: target.session,
+        avatar: target.avatar,
+        max_bandwidth: target.variant.max_bandwidth,
+        max_height: target.variant.max_height,
+        expires: chrono::Utc::now().timestamp()
+            + target.ttl_secs.unwrap_or(config.link.ttl_secs) as i64,
+        signature: String::new(),
+    };
+    let message = query.message(target.kind, target.id, &target.container_extension);
 
-    let url = url::Url::parse(&login.server)?;
+    Ok(format!(
+        "{}/{}&signature={}",
+        relay::public_url(config),
+        message,
+        crypto.sign(&message)
+    ))
+}
+
+/// Url of a stream on the provider.
+async fn upstream_url(
+    login: &Login,
+    kind: CatalogKind,
+    id: i64,
+    container_extension: &str,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<String> {
+    // Playlists give every stream its own url.
+    if login.backend == Backend::M3u {
+        let value = catalog::get_value(login, kind, id, db, client).await?;
+        return value.url.ok_or(ApiError::WrongId);
+    }
+
+    let container_extension = match container_extension {
+        "auto" => auto_extension(login, kind, id, db, client.clone()).await?,
+        _ => container_extension.to_string(),
+    };
+
+    stream_url(login, kind, id, &container_extension, &client)
+}
+
+/// Name of a kind in the path of links.
+fn path_name(kind: CatalogKind) -> &'static str {
+    match kind {
+        CatalogKind::Live => "live",
+        CatalogKind::Movie => "movie",
+        CatalogKind::Serie => "serie",
+    }
+}
+
+/// Format panels serve a kind best in: hls for lives so the relay can
+/// rewrite it, and the container a movie was uploaded in.
+async fn auto_extension(
+    login: &Login,
+    kind: CatalogKind,
+    id: i64,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<String> {
+    match kind {
+        CatalogKind::Live => Ok("m3u8".to_string()),
+        CatalogKind::Movie => {
+            let value = catalog::get_value(login, kind, id, db, client).await?;
+            match value.container_extension.is_empty() {
+                true => Ok("mp4".to_string()),
+                false => Ok(value.container_extension),
+            }
+        }
+        // Episodes are not in the catalog, their container comes with the
+        // info of the serie.
+        CatalogKind::Serie => Err(ApiError::BadRequest(
+            "episodes need their container extension".into(),
+        )),
+    }
+}
+
+/// Url of a stream on the panel of a login.
+pub fn stream_url(
+    login: &Login,
+    kind: CatalogKind,
+    id: i64,
+    container_extension: &str,
+    client: &Client,
+) -> ApiResult<String> {
+    let kind = match kind {
+        CatalogKind::Live => Kind::Live,
+        CatalogKind::Movie => Kind::Movie,
+        CatalogKind::Serie => Kind::Serie,
+    };
+
+    // Streams come from the same host the api calls currently go to.
+    let url = url::Url::parse(client.hosts.preferred(login))?;
     let base = url.origin().unicode_serialization();
 
-    let result = format!(
+    Ok(format!(
         "{}/{}/{}/{}/{}.{}",
         base, kind, login.username, login.password, id, container_extension
-    );
-
-    Ok(HttpResponse::Ok().json(result))
+    ))
 }
@@ -0,0 +1,53 @@
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::config::{LogConfig, LogFormat};
+
+const REDACTED: &str = "REDACTED";
+const SECRET_PARAMS: [&str; 3] = ["password", "auth_key", "token"];
+
+pub fn init(config: &LogConfig) -> Result<(), String> {
+    let filter = EnvFilter::try_new(&config.level).map_err(|err| err.to_string())?;
+    let builder = fmt().with_env_filter(filter);
+
+    match config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    }
+    .map_err(|err| err.to_string())
+}
+
+/// Renders `url` with the values of credential query parameters replaced, so
+/// upstream requests can be logged without leaking provider passwords.
+pub fn redact_url(url: &url::Url) -> String {
+    let mut url = url.clone();
+
+    if url.query().is_some() {
+        let pairs = url
+            .query_pairs()
+            .map(|(key, value)| {
+                if SECRET_PARAMS.contains(&key.as_ref()) {
+                    (key.into_owned(), REDACTED.to_string())
+                } else {
+                    (key.into_owned(), value.into_owned())
+                }
+            })
+            .collect::<Vec<(String, String)>>();
+
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    if url.password().is_some() {
+        let _ = url.set_password(Some(REDACTED));
+    }
+
+    url.to_string()
+}
+
+/// Stand-in for secrets in hand written `Debug` implementations.
+pub struct Redacted;
+
+impl std::fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{REDACTED:?}")
+    }
+}
@@ -1,111 +1,165 @@
 use actix_web::{get, post, web as ActixWeb, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
-use rand::{rngs::OsRng, RngCore};
-use sea_orm::{ActiveValue, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter};
+use sea_orm::{
+    ActiveValue, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    TransactionTrait,
+};
 use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
     api_error::{ApiError, ApiResult},
-    entities::prelude::*,
-    extra::{get_days_ago, get_json, BoolResult, Params},
-    home,
+    client::Client,
+    config::Config,
+    crypto::{Crypto, TokenMatch},
+    entities::{
+        login::{Backend, Mirrors},
+        prelude::*,
+    },
+    extra::{default_on_null, get_days_ago, get_json, BoolResult, Params},
+    home, m3u, session,
 };
 
 #[derive(Deserialize)]
 pub struct LoginResponse {
     pub user_info: UserInfo,
+    #[serde(default)]
+    pub server_info: ServerInfo,
+}
+
+#[derive(Default, Deserialize)]
+pub struct ServerInfo {
+    #[serde(default)]
+    #[serde(deserialize_with = "default_on_null")]
+    pub timezone: Option<String>,
+}
+
+#[derive(ToSchema, Deserialize)]
+pub struct LoginRequest {
+    #[serde(flatten)]
+    pub login: Login,
+
+    /// Label shown when listing the sessions of the login.
+    #[schema(example = "Living room TV")]
+    #[serde(default)]
+    pub device: String,
+}
+
+#[derive(IntoParams, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct SourceQuery {
+    /// Login the id belongs to, the first login of the account when missing
+    pub source: Option<i64>,
 }
 
-// #[utoipa::path(
-//     params(
-//         Login
-//     ),
-//     responses(
-//         (status = 200, description = "Login auth key", body = String),
-// 		(status = 500, description = "Cant login", body = ApiErrorJson),
-//     )
-// )]
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "login",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Tokens of the new session", body = SessionTokens),
+        (status = 401, description = "Account not found or not active", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    )
+)]
 #[post("/login")]
 pub async fn login(
-    login: ActixWeb::Json<Login>,
+    request: ActixWeb::Json<LoginRequest>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+    config: ActixWeb::Data<Config>,
 ) -> ApiResult<HttpResponse> {
-    let mut login = login.into_inner();
-
-    let mut server = url::Url::parse(&login.server)?;
-
-    if server.path() == "/" {
-        server.set_path("player_api.php");
-    }
+    let LoginRequest { mut login, device } = request.into_inner();
 
-    login.server = server.to_string();
+    normalize_server(&mut login)?;
 
-    if let Some(login) = LoginEntity::find()
+    // Passwords are sealed with a random nonce, so rows are matched by server
+    // and username and the password is compared after opening it.
+    for candidate in LoginEntity::find()
         .filter(
             Condition::all()
                 .add(LoginColumn::Server.eq(&login.server))
-                .add(LoginColumn::Username.eq(&login.username))
-                .add(LoginColumn::Password.eq(&login.password)),
+                .add(LoginColumn::Username.eq(&login.username)),
         )
-        .one(db.get_ref())
+        .all(db.get_ref())
         .await?
     {
-        if let Some(session) = SessionEntity::find_by_id(login.id)
+        // Only the first login of an account signs in to it, the ones added
+        // to it later as sources do not give access to the others.
+        let first = LoginEntity::find()
+            .filter(LoginColumn::AccountId.eq(candidate.account_id))
+            .order_by_asc(LoginColumn::Id)
             .one(db.get_ref())
-            .await?
-        {
-            return Ok(HttpResponse::Ok().json(session));
+            .await?;
+        if first.is_none_or(|x| x.id != candidate.id) {
+            continue;
         }
-    }
 
-    let mut user_info = get_login_info(&login, client.clone()).await?;
+        let candidate = crypto.open_login(candidate)?;
+
+        if candidate.password != login.password {
+            continue;
+        }
+
+        set_mirrors(&candidate, &login.mirrors, &db).await?;
+
+        // Every device gets its own session on the account.
+        let tokens =
+            session::create(candidate.account_id, device, &config.session, &db, &crypto).await?;
 
-    let mut random_bytes = [0u8; 16];
-    OsRng.try_fill_bytes(&mut random_bytes)?;
-    let auth_key = hex::encode(random_bytes);
+        return Ok(HttpResponse::Ok().json(tokens));
+    }
 
-    let session_res = SessionEntity::insert(SessionActiveModel {
+    let user_info = get_login_info(&login, client.clone()).await?;
+
+    let account_res = AccountEntity::insert(AccountActiveModel {
         id: ActiveValue::default(),
-        auth_key: ActiveValue::Set(auth_key.clone()),
+        created: ActiveValue::Set(chrono::Utc::now().timestamp()),
     })
     .exec(db.get_ref())
     .await?;
 
-    login.id = session_res.last_insert_id;
-    user_info.id = session_res.last_insert_id;
-
-    let month_ago = get_days_ago(30);
-    home::make(&login, month_ago, db.clone(), client).await?;
+    login.account_id = account_res.last_insert_id;
+    let login = insert(login, user_info, &db, &crypto).await?;
 
-    LoginEntity::insert(Into::<LoginActiveModel>::into(login))
-        .exec(db.get_ref())
-        .await?;
+    let month_ago = get_days_ago(config.retention.home_days);
+    home::make(
+        login.account_id,
+        std::slice::from_ref(&login),
+        month_ago,
+        db.clone(),
+        client,
+    )
+    .await?;
 
-    UserInfoEntity::insert(Into::<UserInfoActiveModel>::into(user_info))
-        .exec(db.get_ref())
-        .await?;
+    let tokens = session::create(login.account_id, device, &config.session, &db, &crypto).await?;
 
-    Ok(HttpResponse::Ok().json(Session { id: 0, auth_key }))
+    Ok(HttpResponse::Ok().json(tokens))
 }
 
-// #[utoipa::path(
-//     responses(
-//         (status = 200, description = "Logoff"),
-// 		(status = 500, description = "Cant Logoff", body = ApiErrorJson),
-//     ),
-// 	security(
-// 		("auth_key" = [])
-// 	)
-// )]
+#[utoipa::path(
+    get,
+    path = "/logoff",
+    tag = "login",
+    responses(
+        (status = 200, description = "Logoff", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[get("/logoff")]
 pub async fn logoff(
     credentials: BearerAuth,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let auth_key = credentials.token();
 
-    let session: SessionActiveModel = get_session(auth_key, &db).await?.into();
+    let session: SessionActiveModel = get_session(auth_key, &db, &crypto).await?.into();
 
     let result = SessionEntity::delete(session).exec(db.get_ref()).await?;
 
@@ -114,33 +168,203 @@ pub async fn logoff(
     }))
 }
 
-pub async fn get_login_info(
+/// Points bare server urls at the player api, so the same provider is always
+/// stored the same way.
+pub fn normalize_server(loginv: &mut Login) -> ApiResult<()> {
+    let mut server = url::Url::parse(&loginv.server)?;
+
+    // Playlists are used as given, see `m3u::playlist_url`.
+    if server.path() == "/" && loginv.backend == Backend::Xtream {
+        server.set_path("player_api.php");
+    }
+
+    loginv.server = server.to_string();
+
+    // Mirrors given as a bare host serve the same path as the primary.
+    let mut mirrors: Vec<String> = Vec::new();
+    for mirror in &loginv.mirrors.0 {
+        let mut mirror = url::Url::parse(mirror)?;
+
+        if mirror.path() == "/" {
+            mirror.set_path(server.path());
+        }
+
+        let mirror = mirror.to_string();
+        if mirror != loginv.server && !mirrors.contains(&mirror) {
+            mirrors.push(mirror);
+        }
+    }
+
+    loginv.mirrors = Mirrors(mirrors);
+
+    if let Some(epg_url) = &loginv.epg_url {
+        url::Url::parse(epg_url)?;
+    }
+
+    Ok(())
+}
+
+/// Stores a login already checked with its provider, along with its user
+/// info. `account_id` must be set, the returned login has its new id.
+pub async fn insert(
+    mut loginv: Login,
+    mut user_info: UserInfo,
+    db: &DatabaseConnection,
+    crypto: &Crypto,
+) -> ApiResult<Login> {
+    let txn = db.begin().await?;
+
+    // The password is sealed to the id of the row, only known once inserted.
+    let mut login_model = LoginActiveModel::from(loginv.clone());
+    login_model.id = ActiveValue::NotSet;
+    login_model.password = ActiveValue::Set(String::new());
+
+    let login_res = LoginEntity::insert(login_model).exec(&txn).await?;
+
+    loginv.id = login_res.last_insert_id;
+    user_info.id = login_res.last_insert_id;
+
+    LoginEntity::update(LoginActiveModel {
+        id: ActiveValue::Unchanged(loginv.id),
+        password: ActiveValue::Set(crypto.seal_login(loginv.clone())?.password),
+        ..Default::default()
+    })
+    .exec(&txn)
+    .await?;
+
+    UserInfoEntity::insert(Into::<UserInfoActiveModel>::into(user_info))
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    Ok(loginv)
+}
+
+/// Replaces the mirrors of a stored login when the request listed any, so
+/// logging in again is enough to point a login at new hosts.
+pub async fn set_mirrors(
     loginv: &Login,
-    client: ActixWeb::Data<reqwest::Client>,
-) -> ApiResult<UserInfo> {
+    mirrors: &Mirrors,
+    db: &DatabaseConnection,
+) -> ApiResult<()> {
+    if mirrors.0.is_empty() || *mirrors == loginv.mirrors {
+        return Ok(());
+    }
+
+    LoginEntity::update(LoginActiveModel {
+        id: ActiveValue::Unchanged(loginv.id),
+        mirrors: ActiveValue::Set(mirrors.clone()),
+        ..Default::default()
+    })
+    .exec(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_login_info(loginv: &Login, client: ActixWeb::Data<Client>) -> ApiResult<UserInfo> {
+    if loginv.backend == Backend::M3u {
+        return m3u::get_login_info(loginv, client).await;
+    }
+
     let params = Params::new(loginv);
 
     let login_response = get_json::<LoginResponse>(&params, client).await?;
 
     if login_response.user_info.auth > 0 {
-        return Ok(login_response.user_info);
+        let mut user_info = login_response.user_info;
+        user_info.timezone = login_response
+            .server_info
+            .timezone
+            .filter(|x| !x.is_empty());
+        return Ok(user_info);
     }
 
     Err(ApiError::AccountNotFound)
 }
 
-pub async fn get_session(auth_key: &str, db: &DatabaseConnection) -> ApiResult<Session> {
-    SessionEntity::find()
-        .filter(SessionColumn::AuthKey.eq(auth_key))
+#[tracing::instrument(skip_all)]
+pub async fn get_session(
+    auth_key: &str,
+    db: &DatabaseConnection,
+    crypto: &Crypto,
+) -> ApiResult<Session> {
+    let candidates = SessionEntity::find()
+        .filter(SessionColumn::AuthPrefix.eq(session::token_prefix(auth_key)))
+        .filter(SessionColumn::Expires.gt(chrono::Utc::now().timestamp()))
+        .all(db)
+        .await?;
+
+    for mut session in candidates {
+        match crypto.verify_token(auth_key, &session.auth_key) {
+            TokenMatch::No => continue,
+            TokenMatch::Current => return Ok(session),
+            TokenMatch::Previous => {
+                session.auth_key = crypto.hash_token(auth_key);
+
+                SessionEntity::update(SessionActiveModel {
+                    id: ActiveValue::Set(session.id),
+                    auth_key: ActiveValue::Set(session.auth_key.clone()),
+                    ..Default::default()
+                })
+                .exec(db)
+                .await?;
+
+                return Ok(session);
+            }
+        }
+    }
+
+    Err(ApiError::WrongAuthKey)
+}
+
+/// Login a value id belongs to, the first login of the account when no
+/// `source` is given.
+#[tracing::instrument(skip_all, fields(account_id = session.account_id))]
+pub async fn get_login(
+    session: &Session,
+    source: Option<i64>,
+    db: &DatabaseConnection,
+    crypto: &Crypto,
+) -> ApiResult<Login> {
+    let mut query = LoginEntity::find().filter(LoginColumn::AccountId.eq(session.account_id));
+
+    if let Some(source) = source {
+        query = query.filter(LoginColumn::Id.eq(source));
+    }
+
+    query
+        .order_by_asc(LoginColumn::Id)
         .one(db)
         .await?
-        .ok_or(ApiError::WrongAuthKey)
+        .ok_or(match source {
+            Some(_) => ApiError::WrongSource,
+            None => ApiError::AccountNotFound,
+        })
+        .and_then(|x| crypto.open_login(x))
 }
 
-pub async fn get_login(session: &Session, db: &DatabaseConnection) -> ApiResult<Login> {
-    LoginEntity::find()
-        .filter(LoginColumn::Id.eq(session.id))
-        .one(db)
+/// Every login of the account, the first one being preferred when the same
+/// title is on several of them.
+#[tracing::instrument(skip_all, fields(account_id = session.account_id))]
+pub async fn get_logins(
+    session: &Session,
+    db: &DatabaseConnection,
+    crypto: &Crypto,
+) -> ApiResult<Vec<Login>> {
+    let logins = LoginEntity::find()
+        .filter(LoginColumn::AccountId.eq(session.account_id))
+        .order_by_asc(LoginColumn::Id)
+        .all(db)
         .await?
-        .ok_or(ApiError::AccountNotFound)
+        .into_iter()
+        .map(|x| crypto.open_login(x))
+        .collect::<ApiResult<Vec<Login>>>()?;
+
+    if logins.is_empty() {
+        return Err(ApiError::AccountNotFound);
+    }
+
+    Ok(logins)
 }
@@ -0,0 +1,354 @@
+use actix_web::web as ActixWeb;
+use sha2::{Digest, Sha256};
+use std::io::BufRead;
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    client::Client,
+    entities::prelude::*,
+    extra::{fetch, read_blocking},
+    get::{Category, Kind, Value},
+};
+
+/// What a playlist holds for one kind.
+pub struct Playlist {
+    /// Guide named by the `url-tvg` or `x-tvg-url` header attribute.
+    pub epg_url: Option<String>,
+    pub categories: Vec<Category>,
+    pub values: Vec<Value>,
+    /// Entries of every kind.
+    pub total: usize,
+}
+
+/// Reads the playlist of an `m3u` login, keeping the entries of `kind`.
+/// Playlists hold every kind at once and can be huge, so each kind is parsed
+/// while the body arrives instead of keeping the text around.
+pub async fn get_playlist(
+    login: &Login,
+    kind: Kind,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Playlist> {
+    fetch(
+        login,
+        "m3u",
+        &client,
+        |http, server| Ok(http.get(playlist_url(login, server)?)),
+        |response, max_bytes| {
+            read_blocking(response, max_bytes, move |reader| {
+                parse(std::io::BufReader::new(reader), kind)
+            })
+        },
+    )
+    .await
+}
+
+/// Playlists are accepted when they hold at least one entry, they carry no
+/// account details so the user info is made up.
+pub async fn get_login_info(login: &Login, client: ActixWeb::Data<Client>) -> ApiResult<UserInfo> {
+    let playlist = match get_playlist(login, Kind::Live, client).await {
+        Ok(playlist) => playlist,
+        Err(ApiError::RequestServerError(401 | 403 | 404)) => {
+            return Err(ApiError::AccountNotFound)
+        }
+        Err(err) => return Err(err),
+    };
+
+    if playlist.total == 0 {
+        return Err(ApiError::AccountNotFound);
+    }
+
+    Ok(UserInfo {
+        id: login.id,
+        auth: 1,
+        status: "Active".to_string(),
+        is_trial: 0,
+        exp_date: 0,
+        created_at: chrono::Utc::now().timestamp(),
+        active_cons: 0,
+        max_connections: 0,
+        timezone: None,
+    })
+}
+
+/// A server pointing at the root or at `get.php` of a panel gets the
+/// credentials of the login, any other url is the playlist itself.
+fn playlist_url(login: &Login, server: &str) -> ApiResult<url::Url> {
+    let mut url = url::Url::parse(server)?;
+
+    if url.query().is_none() && (url.path() == "/" || url.path().ends_with("/get.php")) {
+        if url.path() == "/" {
+            url.set_path("get.php");
+        }
+
+        url.query_pairs_mut()
+            .append_pair("username", &login.username)
+            .append_pair("password", &login.password)
+            .append_pair("type", "m3u_plus")
+            .append_pair("output", "ts");
+    }
+
+    Ok(url)
+}
+
+/// Guide of an `m3u` login, the one it was given or named by its playlist,
+/// else `xmltv.php` when the server is a panel.
+pub fn guide_url(login: &Login, server: &str) -> ApiResult<Option<url::Url>> {
+    if let Some(epg_url) = &login.epg_url {
+        return Ok(Some(url::Url::parse(epg_url)?));
+    }
+
+    let mut url = url::Url::parse(server)?;
+
+    if url.query().is_some() || (url.path() != "/" && !url.path().ends_with("/get.php")) {
+        return Ok(None);
+    }
+
+    url.set_path("xmltv.php");
+    url.query_pairs_mut()
+        .append_pair("username", &login.username)
+        .append_pair("password", &login.password);
+
+    Ok(Some(url))
+}
+
+pub fn parse(mut reader: impl BufRead, kind: Kind) -> ApiResult<Playlist> {
+    let mut playlist = Playlist {
+        epg_url: None,
+        categories: Vec::new(),
+        values: Vec::new(),
+        total: 0,
+    };
+
+    let mut buf = Vec::new();
+    let mut info: Option<(Vec<(String, String)>, String)> = None;
+    let mut group: Option<String> = None;
+
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim();
+
+        if let Some(header) = line.strip_prefix("#EXTM3U") {
+            let (attributes, _) = parse_attributes(header);
+            playlist.epg_url = attributes
+                .into_iter()
+                .find(|(key, _)| key == "url-tvg" || key == "x-tvg-url")
+                .and_then(|(_, value)| value.split(',').next().map(str::to_string))
+                .filter(|x| !x.is_empty());
+        } else if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            info = Some(parse_attributes(extinf));
+            group = None;
+        } else if let Some(extgrp) = line.strip_prefix("#EXTGRP:") {
+            group = Some(extgrp.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let Some((attributes, title)) = info.take() else {
+                continue;
+            };
+
+            playlist.total += 1;
+
+            let (entry_kind, group, value) = to_value(attributes, title, group.take(), line);
+            if entry_kind != kind {
+                continue;
+            }
+
+            if let (Some(category_id), Some(name)) = (value.category_id, group) {
+                if !playlist.categories.iter().any(|x| x.id == category_id) {
+                    playlist.categories.push(Category {
+                        id: category_id,
+                        name,
+                    });
+                }
+            }
+
+            playlist.values.push(value);
+        }
+    }
+
+    Ok(playlist)
+}
+
+fn to_value(
+    attributes: Vec<(String, String)>,
+    title: String,
+    extgrp: Option<String>,
+    url: &str,
+) -> (Kind, Option<String>, Value) {
+    let attribute = |name: &str| {
+        attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .filter(|x| !x.is_empty())
+    };
+
+    let path = url::Url::parse(url)
+        .map(|x| x.path().to_string())
+        .unwrap_or_default();
+
+    // Panels put the kind in the path, `/movie/user/pass/1.mp4`.
+    let kind = if path.contains("/movie/") {
+        Kind::Movie
+    } else if path.contains("/series/") {
+        Kind::Serie
+    } else {
+        Kind::Live
+    };
+
+    let file = path.rsplit('/').next().unwrap_or_default();
+    let (stem, container_extension) = file.rsplit_once('.').unwrap_or((file, ""));
+
+    let group = attribute("group-title").or(extgrp);
+    let name = Some(title)
+        .filter(|x| !x.is_empty())
+        .or_else(|| attribute("tvg-name"))
+        .unwrap_or_default();
+
+    let value = Value {
+        id: stem.parse().unwrap_or_else(|_| stable_id(url)),
+        name,
+        icon: attribute("tvg-logo").unwrap_or_default(),
+        added: 0,
+        rating: 0.0,
+        category_id: group.as_deref().map(stable_id),
+        year: None,
+        episode_id: None,
+        container_extension: container_extension.to_string(),
+        source: 0,
+        alternatives: Vec::new(),
+        epg_channel_id: attribute("tvg-id"),
+        url: Some(url.to_string()),
+    };
+
+    (kind, group, value)
+}
+
+/// Splits `-1 tvg-id="a" group-title="b",Title` into its attributes and its
+/// title, which follows the first comma outside quotes.
+fn parse_attributes(line: &str) -> (Vec<(String, String)>, String) {
+    let mut attributes = Vec::new();
+    let mut rest = line;
+
+    let title = loop {
+        rest = rest.trim_start();
+
+        let Some(position) = rest.find(['=', ',']) else {
+            break String::new();
+        };
+
+        if rest.as_bytes()[position] == b',' {
+            break rest[position + 1..].trim().to_string();
+        }
+
+        // The duration comes first and has no `=`, only the last word before
+        // it is the key.
+        let key = rest[..position]
+            .rsplit(' ')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        rest = &rest[position + 1..];
+
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end + 1..).unwrap_or_default();
+            &quoted[..end]
+        } else {
+            let end = rest.find([' ', ',']).unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+
+        attributes.push((key, value.to_string()));
+    };
+
+    (attributes, title)
+}
+
+/// Playlists without panel ids get one from the url, the same on every sync
+/// so favorites and progress stay attached. Kept within 53 bits for clients
+/// reading numbers as doubles.
+fn stable_id(text: &str) -> i64 {
+    let hash = Sha256::digest(text.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+
+    (u64::from_be_bytes(bytes) & ((1 << 53) - 1)) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_quoted_attributes_and_title() {
+        let (attributes, title) = parse_attributes(
+            "-1 tvg-id=\"one.uk\" tvg-name=\"One, HD\" Group-Title=\"News\",One HD",
+        );
+
+        assert_eq!(
+            attributes,
+            pairs(&[
+                ("tvg-id", "one.uk"),
+                ("tvg-name", "One, HD"),
+                ("group-title", "News")
+            ])
+        );
+        assert_eq!(title, "One HD");
+    }
+
+    #[test]
+    fn parses_bare_attributes_and_missing_title() {
+        let (attributes, title) = parse_attributes("0 tvg-id=two tvg-logo=\"\",");
+        assert_eq!(attributes, pairs(&[("tvg-id", "two"), ("tvg-logo", "")]));
+        assert_eq!(title, "");
+
+        let (attributes, title) = parse_attributes("-1,Title, with comma");
+        assert!(attributes.is_empty());
+        assert_eq!(title, "Title, with comma");
+    }
+
+    #[test]
+    fn reads_kinds_and_groups_from_the_playlist() {
+        let text = "#EXTM3U url-tvg=\"http://example.com/guide.xml\"
+#EXTINF:-1 tvg-id=\"one\" group-title=\"News\",One
+http://example.com/live/u/p/11.ts
+#EXTINF:-1,Film
+#EXTGRP:Movies
+http://example.com/movie/u/p/22.mkv
+";
+        let lives = parse(text.as_bytes(), Kind::Live).unwrap();
+        assert_eq!(
+            lives.epg_url.as_deref(),
+            Some("http://example.com/guide.xml")
+        );
+        assert_eq!(lives.total, 2);
+        assert_eq!(lives.values.len(), 1);
+        assert_eq!(lives.values[0].id, 11);
+        assert_eq!(lives.values[0].epg_channel_id.as_deref(), Some("one"));
+        assert_eq!(lives.categories[0].name, "News");
+
+        let movies = parse(text.as_bytes(), Kind::Movie).unwrap();
+        assert_eq!(movies.values[0].id, 22);
+        assert_eq!(movies.values[0].container_extension, "mkv");
+        assert_eq!(movies.categories[0].name, "Movies");
+    }
+
+    #[test]
+    fn stable_ids_fit_in_doubles() {
+        let id = stable_id("http://example.com/stream");
+        assert_eq!(id, stable_id("http://example.com/stream"));
+        assert!((0..1 << 53).contains(&id));
+    }
+}
@@ -1,148 +1,361 @@
 use actix_cors::Cors;
 use actix_web::{
-    dev::ServiceRequest, error::InternalError, web as ActixWeb, App, HttpResponse, HttpServer,
+    dev::ServiceRequest, middleware, web as ActixWeb, App, HttpResponse, HttpServer, ResponseError,
 };
 use actix_web_httpauth::{extractors::bearer::BearerAuth, middleware::HttpAuthentication};
+use api_error::ApiError;
+use cache::Cache;
+use client::{Client, Resolver, Retry};
+use config::Config;
+use crypto::Crypto;
+use hosts::Hosts;
+use metrics::Metrics;
 use migrator::Migrator;
+use playback::Playbacks;
 use sea_orm::{Database, DatabaseConnection};
 use sea_orm_migration::prelude::*;
-use std::{env, time::Duration};
-// use utoipa::{
-//     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
-//     Modify, OpenApi,
-// };
-// use utoipa_swagger_ui::SwaggerUi;
+use std::{env, sync::Arc};
+use tracing::Instrument;
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+use xmltv::Guides;
 
 mod entities;
 mod migrator;
 
 mod api_error;
 mod avatar;
+mod cache;
+mod catalog;
+mod client;
+mod config;
+mod crypto;
+mod epg;
 mod extra;
 mod favorite;
 mod get;
+mod hls;
 mod home;
+mod hosts;
 mod info;
 mod link;
+mod logging;
 mod login;
+mod m3u;
+mod metrics;
+mod playback;
+mod playlist;
+mod relay;
+mod request_id;
 mod search;
+mod session;
+mod source;
 mod watching;
+mod xmltv;
 
 #[actix_web::main]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    let port = if args.len() > 1 {
-        args[1].parse::<u16>().expect("Could not parse port")
-    } else {
-        panic!("Port is required")
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Could not load configuration: {error}");
+            std::process::exit(1)
+        }
     };
 
-    let cwd = env::current_dir().expect("Could not get current directory");
-    let user_agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    if let Err(error) = logging::init(&config.log) {
+        eprintln!("Could not setup logging: {error}");
+        std::process::exit(1)
+    }
 
-    println!("Starting server on 0.0.0.0:{port} user agent {user_agent} cwd {cwd:?}");
-    println!();
+    let cwd = env::current_dir().unwrap_or_default();
 
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        reqwest::header::USER_AGENT,
-        reqwest::header::HeaderValue::from_str(&user_agent)
-            .expect("Could not add user agent to reqwest header"),
+    tracing::info!(
+        bind = config.bind.join(", "),
+        user_agent = config.upstream.user_agent,
+        cwd = ?cwd,
+        "starting server"
     );
 
-    let client = ActixWeb::Data::new(
-        reqwest::Client::builder()
-            //.proxy(reqwest::Proxy::all("http://localhost:8888").unwrap())
-            .danger_accept_invalid_certs(true)
-            .default_headers(headers)
-            .build()
-            .expect("Could not build reqwest client"),
-    );
+    let mut headers = reqwest::header::HeaderMap::new();
+    match reqwest::header::HeaderValue::from_str(&config.upstream.user_agent) {
+        Ok(user_agent) => headers.insert(reqwest::header::USER_AGENT, user_agent),
+        Err(error) => exit_with("Could not add user agent to reqwest header", error),
+    };
 
-    //let openapi = ApiDoc::openapi();
+    let http = match reqwest::Client::builder()
+        //.proxy(reqwest::Proxy::all("http://localhost:8888").unwrap())
+        .danger_accept_invalid_certs(config.upstream.accept_invalid_certs)
+        .connect_timeout(config.upstream.connect_timeout())
+        .read_timeout(config.upstream.read_timeout())
+        .dns_resolver(Arc::new(Resolver))
+        .default_headers(headers)
+        .build()
+    {
+        Ok(http) => http,
+        Err(error) => exit_with("Could not build reqwest client", error),
+    };
 
-    let db = Database::connect("sqlite://data.db?mode=rwc")
-        .await
-        .expect("Could not connect to database");
+    let db = match Database::connect(&config.database_url).await {
+        Ok(db) => db,
+        Err(error) => exit_with("Could not connect to database", error),
+    };
 
     // Migrator::fresh(&db)
     //     .await
     //     .expect("Could not setup database");
-    Migrator::up(&db, None)
-        .await
-        .expect("Could not setup database");
+    if let Err(error) = Migrator::up(&db, None).await {
+        exit_with("Could not setup database", error);
+    }
+
+    let crypto = match Crypto::load(&config.crypto) {
+        Ok(crypto) => crypto,
+        Err(error) => exit_with("Could not load credentials key", error),
+    };
+
+    match crypto::reencrypt_logins(&db, &crypto).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(count, "re-encrypted stored credentials"),
+        Err(error) => exit_with("Could not re-encrypt stored credentials", error),
+    }
+
+    match crypto::hash_session_tokens(&db, &crypto).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(count, "hashed stored session tokens"),
+        Err(error) => exit_with("Could not hash stored session tokens", error),
+    }
+
+    let metrics = match Metrics::new() {
+        Ok(metrics) => Arc::new(metrics),
+        Err(error) => exit_with("Could not register metrics", error),
+    };
+
+    let client = ActixWeb::Data::new(Client {
+        http,
+        cache: Cache::new(&config.cache, &db, metrics.clone()),
+        hosts: Hosts::new(&config.upstream),
+        guides: Guides::new(&config.cache),
+        playbacks: Playbacks::new(&config.playback),
+        retry: Retry::new(&config.upstream),
+        max_body_bytes: config.upstream.max_body_bytes,
+        timeout: config.upstream.timeout(),
+        metrics: metrics.clone(),
+    });
 
     let db = ActixWeb::Data::new(db);
+    let crypto = ActixWeb::Data::new(crypto);
+    let bind = config.bind_addrs();
+    let config = ActixWeb::Data::new(config);
+    let metrics = ActixWeb::Data::from(metrics);
 
     let db_clone = db.clone();
     let client_clone = client.clone();
+    let crypto_clone = crypto.clone();
+    let config_clone = config.clone();
+    let metrics_clone = metrics.clone();
 
     actix_web::rt::spawn(async move {
         loop {
-            if let Err(error) = home::make_homes(db_clone.clone(), client_clone.clone()).await {
-                println!("{:?}", error);
-            }
-            if let Err(error) = watching::clean(db_clone.clone()).await {
-                println!("{:?}", error);
+            async {
+                let result = metrics_clone
+                    .time_task(
+                        "catalog_sync",
+                        catalog::sync_all(
+                            db_clone.clone(),
+                            crypto_clone.clone(),
+                            client_clone.clone(),
+                        ),
+                    )
+                    .await;
+                if let Err(error) = result {
+                    tracing::error!(%error, "catalog sync failed");
+                }
+
+                let result = metrics_clone
+                    .time_task(
+                        "make_homes",
+                        home::make_homes(
+                            db_clone.clone(),
+                            crypto_clone.clone(),
+                            client_clone.clone(),
+                            config_clone.retention.home_days,
+                        ),
+                    )
+                    .await;
+                if let Err(error) = result {
+                    tracing::error!(%error, "home refresh failed");
+                }
+
+                let result = metrics_clone
+                    .time_task(
+                        "watching_clean",
+                        watching::clean(db_clone.clone(), config_clone.retention.watching_days),
+                    )
+                    .await;
+                if let Err(error) = result {
+                    tracing::error!(%error, "watching cleanup failed");
+                }
+
+                let result = metrics_clone
+                    .time_task(
+                        "playback_clean",
+                        playback::clean(
+                            db_clone.clone(),
+                            client_clone.clone(),
+                            config_clone.retention.playback_event_days,
+                        ),
+                    )
+                    .await;
+                if let Err(error) = result {
+                    tracing::error!(%error, "playback cleanup failed");
+                }
+
+                let result = metrics_clone
+                    .time_task("session_purge", session::purge(db_clone.clone()))
+                    .await;
+                if let Err(error) = result {
+                    tracing::error!(%error, "session purge failed");
+                }
+
+                let result = metrics_clone
+                    .time_task("cache_purge", client_clone.cache.purge())
+                    .await;
+                if let Err(error) = result {
+                    tracing::error!(%error, "cache purge failed");
+                }
             }
+            .instrument(tracing::info_span!("maintenance"))
+            .await;
 
-            actix_web::rt::time::sleep(Duration::from_secs(24 * 60 * 60)).await;
+            actix_web::rt::time::sleep(config_clone.refresh.interval()).await;
         }
     });
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(Cors::permissive())
-            .service(index)
-            .service(login::login)
-            .service(
-                ActixWeb::scope("")
-                    .wrap(HttpAuthentication::bearer(validator))
-                    .service(info::info)
-                    .service(link::link)
-                    .service(home::home)
-                    .service(login::logoff)
-                    .service(search::search)
-                    .service(
-                        ActixWeb::scope("/avatar")
-                            .service(avatar::get)
-                            .service(avatar::store)
-                            .service(avatar::remove),
-                    )
-                    .service(
-                        ActixWeb::scope("/favorite")
-                            .service(favorite::get)
-                            .service(favorite::store)
-                            .service(favorite::remove),
-                    )
-                    .service(
-                        ActixWeb::scope("/watching")
-                            .service(watching::get)
-                            .service(watching::store)
-                            .service(watching::remove),
-                    )
-                    .service(
-                        ActixWeb::scope("/get")
-                            .service(get::get)
-                            .service(get::info)
-                            .service(get::categories),
-                    ),
-            )
-            // .service(
-            //     SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
-            // )
-            .app_data(ActixWeb::QueryConfig::default().error_handler(|err, _| {
-                InternalError::from_response(err, HttpResponse::BadRequest().finish()).into()
-            }))
+            .wrap(middleware::from_fn(metrics::middleware))
+            .wrap(middleware::from_fn(request_id::middleware))
+            .configure(configure)
             .app_data(client.clone())
+            .app_data(config.clone())
+            .app_data(crypto.clone())
             .app_data(db.clone())
+            .app_data(metrics.clone())
     })
-    .bind(("0.0.0.0", port))
-    .expect("Could not bind server port")
-    .run()
-    .await
-    .expect("Could not run server")
+    .bind(&bind[..]);
+
+    let server = match server {
+        Ok(server) => server,
+        Err(error) => exit_with("Could not bind server port", error),
+    };
+
+    if let Err(error) = server.run().await {
+        exit_with("Could not run server", error);
+    }
+}
+
+/// Registers every route, shared by the server and the tests so the OpenAPI
+/// document can be checked against what is actually served.
+fn configure(cfg: &mut ActixWeb::ServiceConfig) {
+    cfg.service(index)
+        .service(login::login)
+        .service(session::refresh)
+        .service(metrics::get)
+        .service(relay::stream)
+        .service(link::play)
+        .service(
+            SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", ApiDoc::openapi()),
+        )
+        .service(
+            ActixWeb::scope("")
+                .wrap(HttpAuthentication::bearer(validator))
+                .service(info::info)
+                .service(link::link)
+                .service(home::home)
+                .service(login::logoff)
+                .service(search::search)
+                .service(search::search_all)
+                .service(
+                    ActixWeb::scope("/session")
+                        .service(session::get)
+                        .service(session::remove),
+                )
+                .service(
+                    ActixWeb::scope("/source")
+                        .service(source::get)
+                        .service(source::health)
+                        .service(source::store)
+                        .service(source::remove),
+                )
+                .service(
+                    ActixWeb::scope("/avatar")
+                        .service(avatar::get)
+                        .service(avatar::store)
+                        .service(avatar::remove),
+                )
+                .service(
+                    ActixWeb::scope("/favorite")
+                        .service(favorite::get)
+                        .service(favorite::store)
+                        .service(favorite::remove),
+                )
+                .service(
+                    ActixWeb::scope("/watching")
+                        .service(watching::get)
+                        .service(watching::store)
+                        .service(watching::store_episode)
+                        .service(watching::remove),
+                )
+                .service(
+                    ActixWeb::scope("/epg")
+                        .service(epg::channel)
+                        .service(epg::grid)
+                        .service(epg::now),
+                )
+                .service(
+                    ActixWeb::scope("/playback")
+                        .service(playback::get)
+                        .service(playback::heartbeat),
+                )
+                .service(
+                    ActixWeb::scope("/playlist")
+                        .service(playlist::favorite_m3u)
+                        .service(playlist::favorite_xmltv)
+                        .service(playlist::category_m3u)
+                        .service(playlist::category_xmltv),
+                )
+                .service(
+                    ActixWeb::scope("/get")
+                        .service(get::all)
+                        .service(get::category)
+                        .service(get::info)
+                        .service(get::categories),
+                ),
+        )
+        .default_service(ActixWeb::to(|| async {
+            ApiError::NotFound.error_response()
+        }))
+        .app_data(
+            ActixWeb::QueryConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .app_data(
+            ActixWeb::PathConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .app_data(
+            ActixWeb::JsonConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        );
+}
+
+fn exit_with(context: &str, error: impl std::fmt::Display) -> ! {
+    tracing::error!(%error, "{context}");
+    std::process::exit(1)
 }
 
 #[actix_web::get("/")]
@@ -156,42 +369,335 @@ async fn validator(
 ) -> Result<ServiceRequest, (actix_web::Error, ServiceRequest)> {
     let auth_key = credentials.token();
 
-    if let Some(db) = req.app_data::<ActixWeb::Data<DatabaseConnection>>() {
-        if login::get_session(auth_key, db).await.ok().is_some() {
+    let db = req.app_data::<ActixWeb::Data<DatabaseConnection>>();
+    let crypto = req.app_data::<ActixWeb::Data<Crypto>>();
+    let config = req.app_data::<ActixWeb::Data<Config>>();
+
+    if let (Some(db), Some(crypto), Some(config)) = (db, crypto, config) {
+        // The stored hash is compared in constant time by `Crypto::verify_token`.
+        if let Ok(session) = login::get_session(auth_key, db, crypto).await {
+            if let Err(error) = session::touch(&session, &config.session, db).await {
+                tracing::warn!(%error, "could not extend session");
+            }
             return Ok(req);
         }
     }
 
-    Err((
-        actix_web::error::ErrorUnauthorized("auth key is invalid"),
-        req,
-    ))
+    Err((ApiError::WrongAuthKey.into(), req))
 }
 
-// #[derive(OpenApi)]
-// #[openapi(
-// 	paths(
-// 		login::login,
-// 		login::logoff,
-// 	),
-// 	components(
-// 		schemas(Login)
-// 	),
-// 	tags(
-// 		(name = "login", description = "Login management endpoints.")
-// 	),
-// 	modifiers(&SecurityAddon)
-// )]
-// struct ApiDoc;
-
-// struct SecurityAddon;
-
-// impl Modify for SecurityAddon {
-//     fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
-//         let components = openapi.components.as_mut().unwrap();
-//         components.add_security_scheme(
-//             "auth_key",
-//             SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Basic).build()),
-//         )
-//     }
-// }
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        login::login,
+        login::logoff,
+        session::refresh,
+        session::get,
+        session::remove,
+        source::get,
+        source::health,
+        source::store,
+        source::remove,
+        info::info,
+        link::link,
+        link::play,
+        relay::stream,
+        home::home,
+        search::search,
+        search::search_all,
+        avatar::get,
+        avatar::store,
+        avatar::remove,
+        favorite::get,
+        favorite::store,
+        favorite::remove,
+        watching::get,
+        watching::store,
+        watching::store_episode,
+        watching::remove,
+        get::all,
+        get::category,
+        get::info,
+        get::categories,
+        epg::channel,
+        epg::grid,
+        epg::now,
+        playback::get,
+        playback::heartbeat,
+        playlist::favorite_m3u,
+        playlist::favorite_xmltv,
+        playlist::category_m3u,
+        playlist::category_xmltv,
+    ),
+    components(
+        schemas(
+            api_error::ApiErrorJson,
+            extra::BoolResult,
+            entities::prelude::Login,
+            entities::login::Backend,
+            entities::prelude::Session,
+            login::LoginRequest,
+            session::SessionTokens,
+            session::SessionInfo,
+            session::RefreshRequest,
+            source::Source,
+            source::SourceHealth,
+            hosts::HostHealth,
+            entities::prelude::UserInfo,
+            entities::prelude::Avatar,
+            entities::prelude::Favorite,
+            entities::prelude::Watching,
+            entities::prelude::Home,
+            entities::prelude::CatalogCategory,
+            entities::catalog_stream::Kind,
+            entities::favorite::Kind,
+            entities::watching::Kind,
+            entities::home::Kind,
+            link::Kind,
+            get::Sort,
+            get::Direction,
+            get::Value,
+            get::Alternative,
+            get::ResultInfo,
+            get::Epg,
+            get::Info,
+            get::MovieData,
+            get::MovieInfo,
+            get::Season,
+            get::EpisodeInfo,
+            get::Episode,
+            get::SerieInfo,
+            epg::GuideChannel,
+            epg::NowNext,
+            playback::SourcePlaybacks,
+            playback::PlaybackInfo,
+            playback::Heartbeat,
+            playback::PlayerState,
+            favorite::Favorites,
+            home::Homes,
+            search::SearchValue,
+        )
+    ),
+    tags(
+        (name = "login", description = "Login management endpoints."),
+        (name = "session", description = "Device session endpoints."),
+        (name = "source", description = "Provider login endpoints."),
+        (name = "info", description = "Account information endpoints."),
+        (name = "get", description = "Catalog endpoints."),
+        (name = "search", description = "Catalog search endpoints."),
+        (name = "link", description = "Stream url endpoints."),
+        (name = "home", description = "Home screen endpoints."),
+        (name = "avatar", description = "Avatar management endpoints."),
+        (name = "favorite", description = "Favorite management endpoints."),
+        (name = "watching", description = "Watching progress endpoints."),
+        (name = "epg", description = "Programme guide endpoints."),
+        (name = "playback", description = "Streams being played endpoints."),
+        (name = "playlist", description = "M3U playlist and XMLTV guide export endpoints."),
+    ),
+    modifiers(&SecurityAddon)
+)]
+struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "auth_key",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::Method, test};
+    use sea_orm::{ActiveValue, EntityTrait};
+    use utoipa::openapi::PathItemType;
+
+    use entities::prelude::{
+        AccountActiveModel, AccountEntity, Login, LoginActiveModel, LoginEntity,
+        SessionActiveModel, SessionEntity,
+    };
+
+    /// Every documented route must reach a handler. A path missing from the
+    /// router falls through to the default service and answers `not_found`.
+    #[actix_web::test]
+    async fn openapi_matches_router() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let mut config = Config::default();
+        config.crypto.key = "00".repeat(32);
+        let crypto = Crypto::load(&config.crypto).unwrap();
+
+        let account = AccountEntity::insert(AccountActiveModel {
+            id: ActiveValue::default(),
+            created: ActiveValue::Set(0),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        SessionEntity::insert(SessionActiveModel {
+            id: ActiveValue::default(),
+            account_id: ActiveValue::Set(account.last_insert_id),
+            auth_prefix: ActiveValue::Set("test".to_string()),
+            auth_key: ActiveValue::Set(crypto.hash_token("test")),
+            device: ActiveValue::Set("test".to_string()),
+            created: ActiveValue::Set(0),
+            last_used: ActiveValue::Set(0),
+            expires: ActiveValue::Set(i64::MAX),
+            refresh_prefix: ActiveValue::Set(None),
+            refresh_token: ActiveValue::Set(None),
+            refresh_expires: ActiveValue::Set(0),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let login = crypto
+            .seal_login(Login {
+                id: account.last_insert_id,
+                account_id: account.last_insert_id,
+                server: "http://127.0.0.1:9/player_api.php".to_string(),
+                mirrors: Default::default(),
+                backend: Default::default(),
+                epg_url: None,
+                username: "test".to_string(),
+                password: "test".to_string(),
+            })
+            .unwrap();
+
+        LoginEntity::insert(LoginActiveModel::from(login))
+            .exec(&db)
+            .await
+            .unwrap();
+
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let client = Client {
+            http: reqwest::Client::new(),
+            cache: Cache::new(&config.cache, &db, metrics.clone()),
+            hosts: Hosts::new(&config.upstream),
+            guides: Guides::new(&config.cache),
+            playbacks: Playbacks::new(&config.playback),
+            retry: Retry::new(&config.upstream),
+            max_body_bytes: config.upstream.max_body_bytes,
+            timeout: config.upstream.timeout(),
+            metrics: metrics.clone(),
+        };
+
+        let app = test::init_service(
+            App::new()
+                .configure(configure)
+                .app_data(ActixWeb::Data::new(client))
+                .app_data(ActixWeb::Data::new(config))
+                .app_data(ActixWeb::Data::new(crypto))
+                .app_data(ActixWeb::Data::new(db))
+                .app_data(ActixWeb::Data::from(metrics)),
+        )
+        .await;
+
+        let openapi = ApiDoc::openapi();
+        assert!(!openapi.paths.paths.is_empty());
+
+        for (path, item) in openapi.paths.paths {
+            let uri = path
+                .split('/')
+                .map(|x| match x {
+                    "{kind}" => "movie",
+                    x if x.starts_with('{') => "1",
+                    x => x,
+                })
+                .collect::<Vec<&str>>()
+                .join("/");
+
+            for operation in item.operations.keys() {
+                let method = match operation {
+                    PathItemType::Get => Method::GET,
+                    PathItemType::Post => Method::POST,
+                    _ => panic!("{path} uses an unsupported method"),
+                };
+
+                let req = test::TestRequest::default()
+                    .method(method.clone())
+                    .uri(&uri)
+                    .insert_header(("Authorization", "Bearer test"))
+                    .to_request();
+                let res = test::call_service(&app, req).await;
+                let body = test::read_body(res).await;
+
+                let code = serde_json::from_slice::<serde_json::Value>(&body)
+                    .ok()
+                    .and_then(|x| x.get("code").cloned());
+
+                assert_ne!(
+                    code,
+                    Some(ApiError::NotFound.code().into()),
+                    "{method} {path} is documented but not routed"
+                );
+            }
+        }
+    }
+
+    /// Routes answered outside the api, left out of the documentation.
+    const UNDOCUMENTED: [&str; 2] = ["/", "/metrics"];
+
+    /// Every routed handler must be documented. Handlers are found by their
+    /// route attribute, which must follow a `utoipa::path` listed in `ApiDoc`.
+    #[actix_web::test]
+    async fn router_matches_openapi() {
+        let openapi = ApiDoc::openapi();
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let file = entry.unwrap().path();
+            if file.extension().is_none_or(|x| x != "rs") {
+                continue;
+            }
+
+            let source = std::fs::read_to_string(&file).unwrap();
+            let mut documented: Option<&str> = None;
+            let mut in_path = false;
+
+            for line in source.lines().map(str::trim) {
+                if line.starts_with("#[utoipa::path(") {
+                    in_path = true;
+                    continue;
+                }
+                if in_path {
+                    if let Some(path) = line.strip_prefix("path = \"") {
+                        documented = path.split('"').next();
+                    }
+                    in_path = line != ")]";
+                    continue;
+                }
+
+                let route = ["get(", "post(", "route("].iter().find_map(|x| {
+                    line.strip_prefix("#[actix_web::")
+                        .or_else(|| line.strip_prefix("#["))
+                        .and_then(|y| y.strip_prefix(x))
+                });
+                let Some(route) = route else {
+                    continue;
+                };
+                let route = route.split('"').nth(1).unwrap_or_default();
+
+                match documented.take() {
+                    Some(path) => assert!(
+                        openapi.paths.paths.contains_key(path),
+                        "{path} in {} is not listed in ApiDoc",
+                        file.display()
+                    ),
+                    None => assert!(
+                        UNDOCUMENTED.contains(&route),
+                        "{route} in {} is routed but not documented",
+                        file.display()
+                    ),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web as ActixWeb, HttpRequest, HttpResponse,
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter};
+use sha2::{Digest, Sha256};
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    config::Config,
+    entities::prelude::*,
+};
+
+pub struct Metrics {
+    registry: Registry,
+
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+
+    upstream_requests: IntCounterVec,
+    upstream_duration: HistogramVec,
+
+    cache_lookups: IntCounterVec,
+
+    task_runs: IntCounterVec,
+    task_duration: HistogramVec,
+
+    active_sessions: IntGauge,
+    db_pool_size: IntGauge,
+    db_pool_idle: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics, prometheus::Error> {
+        let registry = Registry::new_custom(Some("playerapi".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled HTTP requests"),
+            &["route", "method", "status"],
+        )?;
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["route", "method"],
+        )?;
+        let upstream_requests = IntCounterVec::new(
+            Opts::new("upstream_requests_total", "Requests made to Xtream servers"),
+            &["action", "result"],
+        )?;
+        let upstream_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Xtream server request latency",
+            ),
+            &["action"],
+        )?;
+        let cache_lookups = IntCounterVec::new(
+            Opts::new("cache_lookups_total", "Upstream cache lookups"),
+            &["action", "result"],
+        )?;
+        let task_runs = IntCounterVec::new(
+            Opts::new("task_runs_total", "Background maintenance task runs"),
+            &["task", "result"],
+        )?;
+        let task_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "task_duration_seconds",
+                "Background maintenance task duration",
+            )
+            .buckets(vec![0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0]),
+            &["task"],
+        )?;
+        let active_sessions = IntGauge::new("active_sessions", "Sessions that have not expired")?;
+        let db_pool_size = IntGauge::new("db_pool_connections", "Open database connections")?;
+        let db_pool_idle = IntGauge::new("db_pool_idle_connections", "Idle database connections")?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_duration.clone()))?;
+        registry.register(Box::new(upstream_requests.clone()))?;
+        registry.register(Box::new(upstream_duration.clone()))?;
+        registry.register(Box::new(cache_lookups.clone()))?;
+        registry.register(Box::new(task_runs.clone()))?;
+        registry.register(Box::new(task_duration.clone()))?;
+        registry.register(Box::new(active_sessions.clone()))?;
+        registry.register(Box::new(db_pool_size.clone()))?;
+        registry.register(Box::new(db_pool_idle.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            http_duration,
+            upstream_requests,
+            upstream_duration,
+            cache_lookups,
+            task_runs,
+            task_duration,
+            active_sessions,
+            db_pool_size,
+            db_pool_idle,
+        })
+    }
+
+    pub fn observe_upstream(&self, action: &str, ok: bool, elapsed: Duration) {
+        let result = if ok { "ok" } else { "error" };
+
+        self.upstream_requests
+            .with_label_values(&[action, result])
+            .inc();
+        self.upstream_duration
+            .with_label_values(&[action])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_cache(&self, action: &str, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+
+        self.cache_lookups
+            .with_label_values(&[action, result])
+            .inc();
+    }
+
+    /// Runs a background task, recording how long it took and whether it failed.
+    pub async fn time_task<F>(&self, task: &str, future: F) -> ApiResult<()>
+    where
+        F: Future<Output = ApiResult<()>>,
+    {
+        let start = Instant::now();
+        let result = future.await;
+        let outcome = if result.is_ok() { "ok" } else { "error" };
+
+        self.task_runs.with_label_values(&[task, outcome]).inc();
+        self.task_duration
+            .with_label_values(&[task])
+            .observe(start.elapsed().as_secs_f64());
+
+        result
+    }
+
+    async fn refresh_gauges(&self, db: &DatabaseConnection) -> ApiResult<()> {
+        let sessions = SessionEntity::find()
+            .filter(SessionColumn::Expires.gt(chrono::Utc::now().timestamp()))
+            .count(db)
+            .await?;
+        self.active_sessions.set(sessions as i64);
+
+        let pool = db.get_sqlite_connection_pool();
+        self.db_pool_size.set(pool.size() as i64);
+        self.db_pool_idle.set(pool.num_idle() as i64);
+
+        Ok(())
+    }
+}
+
+#[actix_web::get("/metrics")]
+pub async fn get(
+    req: HttpRequest,
+    metrics: ActixWeb::Data<Metrics>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    config: ActixWeb::Data<Config>,
+) -> ApiResult<HttpResponse> {
+    // Every scrape counts the sessions, so only scrapers holding the token
+    // get to run it.
+    if config.metrics.token.is_empty() {
+        return Err(ApiError::NotFound);
+    }
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Digests are compared so the time taken does not tell how much of the
+    // token matched.
+    if Sha256::digest(token) != Sha256::digest(&config.metrics.token) {
+        return Err(ApiError::WrongAuthKey);
+    }
+
+    metrics.refresh_gauges(&db).await?;
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    if encoder
+        .encode(&metrics.registry.gather(), &mut buffer)
+        .is_err()
+    {
+        return Ok(HttpResponse::InternalServerError().finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer))
+}
+
+/// Counts requests and their latency per matched route pattern, so path
+/// parameters like ids do not explode the label cardinality.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<ActixWeb::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let start = Instant::now();
+
+    let res = next.call(req).await;
+
+    if let Some(metrics) = metrics {
+        // Errors raised by middleware, like a rejected bearer token, never
+        // reach a route and come back without the request.
+        let (route, status) = match &res {
+            Ok(res) => (res.request().match_pattern(), res.status()),
+            Err(err) => (None, err.as_response_error().status_code()),
+        };
+        let route = route.unwrap_or_else(|| "unmatched".to_string());
+        let status = status.as_u16().to_string();
+
+        metrics
+            .http_requests
+            .with_label_values(&[&route, &method, &status])
+            .inc();
+        metrics
+            .http_duration
+            .with_label_values(&[&route, &method])
+            .observe(start.elapsed().as_secs_f64());
+    }
+
+    res
+}
@@ -0,0 +1,55 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_catalog_stream_table::CatalogStream;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(CatalogStream::Table)
+                    .add_column(ColumnDef::new(Url::Url).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(CatalogStream::Table)
+                    .add_column(ColumnDef::new(Url::EpgChannelId).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(CatalogStream::Table)
+                    .drop_column(Url::EpgChannelId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(CatalogStream::Table)
+                    .drop_column(Url::Url)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Url {
+    Url,
+    EpgChannelId,
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_catalog_stream_table::CatalogStream;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(CatalogStream::Table)
+                    .add_column(ColumnDef::new(Year::Year).integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(CatalogStream::Table)
+                    .drop_column(Year::Year)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Year {
+    Year,
+}
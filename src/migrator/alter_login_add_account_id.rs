@@ -0,0 +1,94 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Lets an account hold several provider logins. Logins get their own id and
+/// point at their account, user info moves from the account to its login, and
+/// favorites, watching progress and home rows remember the login their value
+/// id belongs to. Existing logins keep their id, which was the account id, so
+/// the mirrored catalog stays attached.
+///
+/// Same as `create_account_table`, the tables are rebuilt in a single script
+/// with foreign keys turned off.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared(
+            "PRAGMA foreign_keys = OFF;
+            BEGIN;
+
+            CREATE TABLE login_new (
+                id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                account_id integer NOT NULL REFERENCES account(id) ON DELETE CASCADE,
+                server text NOT NULL,
+                username text NOT NULL,
+                password text NOT NULL
+            );
+
+            INSERT INTO login_new (id, account_id, server, username, password)
+                SELECT id, id, server, username, password FROM login;
+
+            CREATE TABLE user_info_new (
+                id integer NOT NULL PRIMARY KEY REFERENCES login(id) ON DELETE CASCADE,
+                auth integer NOT NULL,
+                status text NOT NULL,
+                is_trial integer NOT NULL,
+                exp_date integer NOT NULL,
+                created_at integer NOT NULL,
+                active_cons integer NOT NULL,
+                max_connections integer NOT NULL
+            );
+
+            INSERT INTO user_info_new SELECT * FROM user_info;
+
+            -- Dropping keeps the catalog tables pointing at the name login,
+            -- which the new table takes over.
+            DROP TABLE user_info;
+            DROP TABLE login;
+            ALTER TABLE login_new RENAME TO login;
+            ALTER TABLE user_info_new RENAME TO user_info;
+
+            CREATE INDEX idx_login_account_id ON login(account_id);
+
+            ALTER TABLE favorite ADD COLUMN
+                login_id integer NULL REFERENCES login(id) ON DELETE CASCADE;
+            ALTER TABLE watching ADD COLUMN
+                login_id integer NULL REFERENCES login(id) ON DELETE CASCADE;
+            ALTER TABLE home ADD COLUMN
+                login_id integer NULL REFERENCES login(id) ON DELETE CASCADE;
+
+            UPDATE favorite SET login_id = (
+                SELECT min(login.id) FROM login
+                JOIN avatar ON avatar.account_id = login.account_id
+                WHERE avatar.id = favorite.avatar_id
+            );
+            UPDATE watching SET login_id = (
+                SELECT min(login.id) FROM login
+                JOIN avatar ON avatar.account_id = login.account_id
+                WHERE avatar.id = watching.avatar_id
+            );
+            UPDATE home SET login_id = (
+                SELECT min(login.id) FROM login WHERE login.account_id = home.account_id
+            );
+
+            DELETE FROM favorite WHERE login_id IS NULL;
+            DELETE FROM watching WHERE login_id IS NULL;
+            DELETE FROM home WHERE login_id IS NULL;
+
+            COMMIT;
+            PRAGMA foreign_keys = ON;",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Migration(
+            "logins of an account can not be merged back".to_string(),
+        ))
+    }
+}
@@ -0,0 +1,60 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_login_table::Login;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Login::Table)
+                    .add_column(
+                        ColumnDef::new(Backend::Backend)
+                            .string()
+                            .not_null()
+                            .default("xtream"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Login::Table)
+                    .add_column(ColumnDef::new(Backend::EpgUrl).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Login::Table)
+                    .drop_column(Backend::EpgUrl)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Login::Table)
+                    .drop_column(Backend::Backend)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Backend {
+    Backend,
+    EpgUrl,
+}
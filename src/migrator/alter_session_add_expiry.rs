@@ -0,0 +1,77 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Turns sessions into per-device tokens. The session created on the first
+/// login of an account owns its login, user info and avatars, later logins
+/// point at it through `parent_id`. Existing sessions get a fresh lifetime so
+/// nobody is logged out by the upgrade.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // SQLite only adds one column per statement, and only through raw SQL
+        // when the column references another table.
+        for column in [
+            "parent_id INTEGER NULL REFERENCES session(id) ON DELETE CASCADE",
+            "device TEXT NOT NULL DEFAULT ''",
+            "created INTEGER NOT NULL DEFAULT 0",
+            "last_used INTEGER NOT NULL DEFAULT 0",
+            "expires INTEGER NOT NULL DEFAULT 0",
+            "refresh_token TEXT NULL",
+            "refresh_expires INTEGER NOT NULL DEFAULT 0",
+        ] {
+            db.execute_unprepared(&format!("ALTER TABLE session ADD COLUMN {column}"))
+                .await?;
+        }
+
+        db.execute_unprepared(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_session_refresh_token
+                ON session(refresh_token)",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE INDEX IF NOT EXISTS idx_session_parent_id ON session(parent_id)",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "UPDATE session SET
+                created = strftime('%s', 'now'),
+                last_used = strftime('%s', 'now'),
+                expires = strftime('%s', 'now') + 30 * 24 * 60 * 60",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared("DELETE FROM session WHERE parent_id IS NOT NULL")
+            .await?;
+        db.execute_unprepared("DROP INDEX IF EXISTS idx_session_parent_id")
+            .await?;
+        db.execute_unprepared("DROP INDEX IF EXISTS idx_session_refresh_token")
+            .await?;
+
+        for column in [
+            "refresh_expires",
+            "refresh_token",
+            "expires",
+            "last_used",
+            "created",
+            "device",
+            "parent_id",
+        ] {
+            db.execute_unprepared(&format!("ALTER TABLE session DROP COLUMN {column}"))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
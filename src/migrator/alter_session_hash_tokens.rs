@@ -0,0 +1,55 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Adds the public prefixes sessions are looked up by once `auth_key` and
+/// `refresh_token` hold hashes. Rows with an empty `auth_prefix` still hold
+/// plain tokens and are hashed on startup, where the key is known.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        for column in [
+            "auth_prefix TEXT NOT NULL DEFAULT ''",
+            "refresh_prefix TEXT NULL",
+        ] {
+            db.execute_unprepared(&format!("ALTER TABLE session ADD COLUMN {column}"))
+                .await?;
+        }
+
+        db.execute_unprepared(
+            "CREATE INDEX IF NOT EXISTS idx_session_auth_prefix ON session(auth_prefix)",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE INDEX IF NOT EXISTS idx_session_refresh_prefix ON session(refresh_prefix)",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // Hashed tokens can not be turned back into working ones.
+        db.execute_unprepared("DELETE FROM session WHERE parent_id IS NOT NULL")
+            .await?;
+        db.execute_unprepared("UPDATE session SET expires = 0, refresh_token = NULL")
+            .await?;
+        db.execute_unprepared("DROP INDEX IF EXISTS idx_session_refresh_prefix")
+            .await?;
+        db.execute_unprepared("DROP INDEX IF EXISTS idx_session_auth_prefix")
+            .await?;
+
+        for column in ["refresh_prefix", "auth_prefix"] {
+            db.execute_unprepared(&format!("ALTER TABLE session DROP COLUMN {column}"))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
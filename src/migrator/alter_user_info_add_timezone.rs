@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_userinfo_table::UserInfo;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserInfo::Table)
+                    .add_column(ColumnDef::new(Timezone::Timezone).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserInfo::Table)
+                    .drop_column(Timezone::Timezone)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Timezone {
+    Timezone,
+}
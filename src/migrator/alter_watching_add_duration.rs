@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_watching_table::Watching;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Watching::Table)
+                    .add_column(ColumnDef::new(Duration::Duration).integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Watching::Table)
+                    .drop_column(Duration::Duration)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Duration {
+    Duration,
+}
@@ -0,0 +1,79 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Moves ownership of logins, user info, avatars and home rows from the
+/// session that created them to a new `account` table, keeping the same ids
+/// so favorites and watching progress stay attached to their avatars.
+/// Sessions become tokens pointing at an account.
+///
+/// SQLite can not change a foreign key in place, so the tables are rebuilt.
+/// Foreign keys are turned off meanwhile, otherwise dropping the old tables
+/// would cascade into the rows being kept. The pragma only applies to the
+/// connection it runs on, hence a single script.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared(
+            "PRAGMA foreign_keys = OFF;
+            BEGIN;
+
+            CREATE TABLE account_new (
+                id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                created integer NOT NULL DEFAULT 0
+            );
+
+            INSERT INTO account_new (id, created)
+                SELECT id, created FROM session WHERE parent_id IS NULL;
+
+            CREATE TABLE session_new (
+                id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                account_id integer NOT NULL REFERENCES account(id) ON DELETE CASCADE,
+                auth_prefix varchar NOT NULL DEFAULT '',
+                auth_key varchar NOT NULL,
+                device varchar NOT NULL DEFAULT '',
+                created integer NOT NULL DEFAULT 0,
+                last_used integer NOT NULL DEFAULT 0,
+                expires integer NOT NULL DEFAULT 0,
+                refresh_prefix varchar NULL,
+                refresh_token varchar NULL,
+                refresh_expires integer NOT NULL DEFAULT 0
+            );
+
+            INSERT INTO session_new
+                SELECT id, COALESCE(parent_id, id), auth_prefix, auth_key, device, created,
+                    last_used, expires, refresh_prefix, refresh_token, refresh_expires
+                FROM session
+                WHERE parent_id IS NULL OR parent_id IN (SELECT id FROM account_new);
+
+            -- Renaming rewrites the references of login, user_info, avatar and
+            -- home to point at account.
+            ALTER TABLE session RENAME TO account;
+            DROP TABLE account;
+            ALTER TABLE account_new RENAME TO account;
+            ALTER TABLE session_new RENAME TO session;
+
+            ALTER TABLE avatar RENAME COLUMN session_id TO account_id;
+            ALTER TABLE home RENAME COLUMN session_id TO account_id;
+
+            CREATE INDEX idx_session_account_id ON session(account_id);
+            CREATE INDEX idx_session_auth_prefix ON session(auth_prefix);
+            CREATE INDEX idx_session_refresh_prefix ON session(refresh_prefix);
+
+            COMMIT;
+            PRAGMA foreign_keys = ON;",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Migration(
+            "accounts can not be merged back into sessions".to_string(),
+        ))
+    }
+}
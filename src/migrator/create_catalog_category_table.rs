@@ -0,0 +1,74 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_login_table::Login;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(CatalogCategory::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(CatalogCategory::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(CatalogCategory::LoginId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-catalog_category-login_id")
+                            .from(CatalogCategory::Table, CatalogCategory::LoginId)
+                            .to(Login::Table, Login::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(CatalogCategory::Kind).string().not_null())
+                    .col(
+                        ColumnDef::new(CatalogCategory::CategoryId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(CatalogCategory::Name).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-catalog_category-login_id-kind")
+                    .table(CatalogCategory::Table)
+                    .col(CatalogCategory::LoginId)
+                    .col(CatalogCategory::Kind)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CatalogCategory::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum CatalogCategory {
+    Table,
+    Id,
+    LoginId,
+    Kind,
+    CategoryId,
+    Name,
+}
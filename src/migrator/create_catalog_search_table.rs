@@ -0,0 +1,79 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Full-text index over `catalog_stream.name`, kept in sync by triggers so
+/// every catalog sync updates it incrementally. `remove_diacritics` folds
+/// accents on both the indexed names and the queries.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS catalog_search USING fts5(
+                name,
+                content = 'catalog_stream',
+                content_rowid = 'id',
+                tokenize = 'unicode61 remove_diacritics 2'
+            )",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS catalog_search_vocab
+                USING fts5vocab(catalog_search, row)",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE TRIGGER IF NOT EXISTS catalog_stream_search_insert
+                AFTER INSERT ON catalog_stream BEGIN
+                INSERT INTO catalog_search(rowid, name) VALUES (new.id, new.name);
+            END",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE TRIGGER IF NOT EXISTS catalog_stream_search_delete
+                AFTER DELETE ON catalog_stream BEGIN
+                INSERT INTO catalog_search(catalog_search, rowid, name)
+                    VALUES ('delete', old.id, old.name);
+            END",
+        )
+        .await?;
+
+        db.execute_unprepared(
+            "CREATE TRIGGER IF NOT EXISTS catalog_stream_search_update
+                AFTER UPDATE OF name ON catalog_stream BEGIN
+                INSERT INTO catalog_search(catalog_search, rowid, name)
+                    VALUES ('delete', old.id, old.name);
+                INSERT INTO catalog_search(rowid, name) VALUES (new.id, new.name);
+            END",
+        )
+        .await?;
+
+        db.execute_unprepared("INSERT INTO catalog_search(catalog_search) VALUES ('rebuild')")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared("DROP TRIGGER IF EXISTS catalog_stream_search_insert")
+            .await?;
+        db.execute_unprepared("DROP TRIGGER IF EXISTS catalog_stream_search_delete")
+            .await?;
+        db.execute_unprepared("DROP TRIGGER IF EXISTS catalog_stream_search_update")
+            .await?;
+        db.execute_unprepared("DROP TABLE IF EXISTS catalog_search_vocab")
+            .await?;
+        db.execute_unprepared("DROP TABLE IF EXISTS catalog_search")
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_login_table::Login;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(CatalogStream::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(CatalogStream::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(CatalogStream::LoginId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-catalog_stream-login_id")
+                            .from(CatalogStream::Table, CatalogStream::LoginId)
+                            .to(Login::Table, Login::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(CatalogStream::Kind).string().not_null())
+                    .col(ColumnDef::new(CatalogStream::ValueId).integer().not_null())
+                    .col(ColumnDef::new(CatalogStream::CategoryId).integer())
+                    .col(ColumnDef::new(CatalogStream::Name).string().not_null())
+                    .col(ColumnDef::new(CatalogStream::Icon).string().not_null())
+                    .col(ColumnDef::new(CatalogStream::Added).integer().not_null())
+                    .col(ColumnDef::new(CatalogStream::Rating).double().not_null())
+                    .col(
+                        ColumnDef::new(CatalogStream::ContainerExtension)
+                            .string()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-catalog_stream-login_id-kind-value_id")
+                    .table(CatalogStream::Table)
+                    .col(CatalogStream::LoginId)
+                    .col(CatalogStream::Kind)
+                    .col(CatalogStream::ValueId)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CatalogStream::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum CatalogStream {
+    Table,
+    Id,
+    LoginId,
+    Kind,
+    ValueId,
+    CategoryId,
+    Name,
+    Icon,
+    Added,
+    Rating,
+    ContainerExtension,
+}
@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_login_table::Login;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(CatalogSync::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(CatalogSync::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(CatalogSync::LoginId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-catalog_sync-login_id")
+                            .from(CatalogSync::Table, CatalogSync::LoginId)
+                            .to(Login::Table, Login::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(CatalogSync::Kind).string().not_null())
+                    .col(ColumnDef::new(CatalogSync::SyncedAt).integer().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CatalogSync::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum CatalogSync {
+    Table,
+    Id,
+    LoginId,
+    Kind,
+    SyncedAt,
+}
@@ -37,7 +37,7 @@ impl MigrationTrait for Migration {
 }
 
 #[derive(DeriveIden)]
-enum Login {
+pub enum Login {
     Table,
     Id,
     Server,
@@ -0,0 +1,80 @@
+use sea_orm_migration::prelude::*;
+
+use super::create_login_table::Login;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PlaybackEvent::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PlaybackEvent::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PlaybackEvent::LoginId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-playback_event-login_id")
+                            .from(PlaybackEvent::Table, PlaybackEvent::LoginId)
+                            .to(Login::Table, Login::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(PlaybackEvent::SessionId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PlaybackEvent::AvatarId).integer())
+                    .col(ColumnDef::new(PlaybackEvent::Kind).string().not_null())
+                    .col(ColumnDef::new(PlaybackEvent::ValueId).integer().not_null())
+                    .col(ColumnDef::new(PlaybackEvent::EpisodeId).integer())
+                    .col(ColumnDef::new(PlaybackEvent::Event).string().not_null())
+                    .col(ColumnDef::new(PlaybackEvent::Position).integer().not_null())
+                    .col(ColumnDef::new(PlaybackEvent::Date).integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-playback_event-avatar_id-date")
+                    .table(PlaybackEvent::Table)
+                    .col(PlaybackEvent::AvatarId)
+                    .col(PlaybackEvent::Date)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PlaybackEvent::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PlaybackEvent {
+    Table,
+    Id,
+    LoginId,
+    SessionId,
+    AvatarId,
+    Kind,
+    ValueId,
+    EpisodeId,
+    Event,
+    Position,
+    Date,
+}
@@ -50,7 +50,7 @@ impl MigrationTrait for Migration {
 }
 
 #[derive(DeriveIden)]
-enum UserInfo {
+pub enum UserInfo {
     Table,
     Id,
     Auth,
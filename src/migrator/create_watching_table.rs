@@ -53,7 +53,7 @@ impl MigrationTrait for Migration {
 }
 
 #[derive(DeriveIden)]
-enum Watching {
+pub enum Watching {
     Table,
     Id,
     AvatarId,
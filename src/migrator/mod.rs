@@ -1,9 +1,25 @@
 pub use sea_orm_migration::prelude::*;
 
+mod alter_catalog_stream_add_url;
+mod alter_catalog_stream_add_year;
+mod alter_login_add_account_id;
+mod alter_login_add_backend;
+mod alter_login_add_mirrors;
+mod alter_session_add_expiry;
+mod alter_session_hash_tokens;
+mod alter_user_info_add_timezone;
+mod alter_watching_add_duration;
+mod create_account_table;
 mod create_avatar_table;
+mod create_cache_table;
+mod create_catalog_category_table;
+mod create_catalog_search_table;
+mod create_catalog_stream_table;
+mod create_catalog_sync_table;
 mod create_favorite_table;
 mod create_home_table;
 mod create_login_table;
+mod create_playback_event_table;
 mod create_session_table;
 mod create_userinfo_table;
 mod create_watching_table;
@@ -21,6 +37,110 @@ impl MigratorTrait for Migrator {
             Box::new(create_favorite_table::Migration),
             Box::new(create_watching_table::Migration),
             Box::new(create_home_table::Migration),
+            Box::new(create_cache_table::Migration),
+            Box::new(create_catalog_category_table::Migration),
+            Box::new(create_catalog_stream_table::Migration),
+            Box::new(create_catalog_sync_table::Migration),
+            Box::new(alter_catalog_stream_add_year::Migration),
+            Box::new(create_catalog_search_table::Migration),
+            Box::new(alter_session_add_expiry::Migration),
+            Box::new(alter_session_hash_tokens::Migration),
+            Box::new(create_account_table::Migration),
+            Box::new(alter_login_add_account_id::Migration),
+            Box::new(alter_login_add_mirrors::Migration),
+            Box::new(alter_login_add_backend::Migration),
+            Box::new(alter_catalog_stream_add_url::Migration),
+            Box::new(alter_user_info_add_timezone::Migration),
+            Box::new(alter_watching_add_duration::Migration),
+            Box::new(create_playback_event_table::Migration),
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbBackend, Statement};
+
+    /// Migrations up to sessions with hashed tokens, before accounts.
+    const BEFORE_ACCOUNTS: u32 = 15;
+
+    async fn rows(db: &DatabaseConnection, sql: &str) -> Vec<Vec<i64>> {
+        db.query_all(Statement::from_string(DbBackend::Sqlite, sql))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| {
+                (0..)
+                    .map_while(|x| row.try_get_by_index::<i64>(x).ok())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Rows written when sessions owned their login and avatars end up with
+    /// the account that replaces the session, under the same ids.
+    #[actix_web::test]
+    async fn moves_sessions_to_accounts() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, Some(BEFORE_ACCOUNTS)).await.unwrap();
+
+        db.execute_unprepared(
+            "INSERT INTO session (id, auth_key, auth_prefix, device, created)
+                VALUES (1, 'hash1', 'prefix1', 'phone', 100);
+            INSERT INTO session (id, auth_key, auth_prefix, device, parent_id)
+                VALUES (2, 'hash2', 'prefix2', 'tv', 1);
+            INSERT INTO login (id, server, username, password)
+                VALUES (1, 'http://example.com/player_api.php', 'user', 'plain');
+            INSERT INTO user_info (id, auth, status, is_trial, exp_date, created_at,
+                active_cons, max_connections)
+                VALUES (1, 1, 'Active', 0, 0, 0, 0, 2);
+            INSERT INTO avatar (id, session_id, name) VALUES (5, 1, 'Kid');
+            INSERT INTO watching (avatar_id, kind, value_id, name, icon, date, time,
+                container_extension)
+                VALUES (5, 'Movie', 101, 'Movie', '', 0, 30, 'mp4');",
+        )
+        .await
+        .unwrap();
+
+        Migrator::up(&db, None).await.unwrap();
+
+        assert_eq!(
+            rows(&db, "SELECT id, created FROM account").await,
+            [[1, 100]]
+        );
+        assert_eq!(
+            rows(&db, "SELECT id, account_id FROM session ORDER BY id").await,
+            [[1, 1], [2, 1]]
+        );
+        assert_eq!(
+            rows(&db, "SELECT id, account_id FROM login").await,
+            [[1, 1]]
+        );
+        assert_eq!(
+            rows(&db, "SELECT id, max_connections FROM user_info").await,
+            [[1, 2]]
+        );
+        assert_eq!(
+            rows(&db, "SELECT id, account_id FROM avatar").await,
+            [[5, 1]]
+        );
+        assert_eq!(
+            rows(
+                &db,
+                "SELECT avatar_id, login_id, value_id, time FROM watching"
+            )
+            .await,
+            [[5, 1, 101, 30]]
+        );
+        assert!(rows(&db, "PRAGMA foreign_key_check").await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn migrates_down_to_before_accounts() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, Some(BEFORE_ACCOUNTS)).await.unwrap();
+        Migrator::down(&db, Some(BEFORE_ACCOUNTS)).await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+    }
+}
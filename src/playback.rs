@@ -0,0 +1,726 @@
+use actix_web::{web as ActixWeb, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use sea_orm::{ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
+use utoipa::ToSchema;
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    catalog,
+    client::Client,
+    config::PlaybackConfig,
+    crypto::Crypto,
+    entities::{
+        catalog_stream::Kind as CatalogKind, playback_event::Event, prelude::*,
+        watching::Kind as WatchingKind,
+    },
+    extra::{get_days_ago, BoolResult},
+    login,
+    watching::{self, Position},
+};
+
+/// A stream being played on a device, counted against the connections the
+/// provider allows its login.
+#[derive(Clone)]
+pub struct Playback {
+    pub session: i64,
+    pub avatar: Option<i64>,
+    pub kind: CatalogKind,
+    /// Stream id, the episode id for series.
+    pub id: i64,
+    /// Serie of the episode, known once the player sends heartbeats.
+    pub serie: Option<i64>,
+    pub started: i64,
+    pub last_seen: i64,
+    /// Position in seconds from the last heartbeat, none until the player
+    /// sends one.
+    pub position: Option<i64>,
+    pub duration: Option<i64>,
+    pub paused: bool,
+    /// Whether its start event was recorded, only those get a stop event.
+    reported: bool,
+    /// Position last saved to the watching list of the avatar and when.
+    saved: Option<i64>,
+    saved_at: i64,
+}
+
+impl Playback {
+    pub fn new(session: i64, avatar: Option<i64>, kind: CatalogKind, id: i64) -> Playback {
+        let now = chrono::Utc::now().timestamp();
+        Playback {
+            session,
+            avatar,
+            kind,
+            id,
+            serie: None,
+            started: now,
+            last_seen: now,
+            position: None,
+            duration: None,
+            paused: false,
+            reported: false,
+            saved: None,
+            saved_at: 0,
+        }
+    }
+
+    fn same_stream(&self, other: &Playback) -> bool {
+        self.kind == other.kind && self.id == other.id
+    }
+
+    /// Position to write to the watching list, only movies and series
+    /// played by an avatar have one.
+    fn watching(&self, login_id: i64) -> Option<Position> {
+        let kind = match self.kind {
+            CatalogKind::Movie => WatchingKind::Movie,
+            CatalogKind::Serie => WatchingKind::Serie,
+            CatalogKind::Live => return None,
+        };
+
+        Some(Position {
+            avatar: self.avatar?,
+            login_id,
+            kind,
+            id: self.serie.unwrap_or(self.id),
+            episode_id: self.serie.map(|_| self.id),
+            time: self.position?,
+            duration: self.duration,
+        })
+    }
+}
+
+/// What a heartbeat changed.
+pub struct Beat {
+    /// The player had not reported this stream yet.
+    pub started: bool,
+    /// The position moved and was not saved for `write_interval_secs`.
+    pub save: bool,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct SourcePlaybacks {
+    source: i64,
+    /// Streams the provider allows at once, 0 when it sets no limit.
+    max_connections: i64,
+    /// Streams the provider counted when the source was last checked, from
+    /// every app using the login.
+    active_cons: i64,
+    playbacks: Vec<PlaybackInfo>,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct PlaybackInfo {
+    session: i64,
+    device: String,
+    /// Whether this is the session making the request.
+    current: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar_name: Option<String>,
+    kind: CatalogKind,
+    id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serie: Option<i64>,
+    started: i64,
+    last_seen: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<i64>,
+    paused: bool,
+}
+
+#[derive(Default)]
+struct State {
+    playing: HashMap<i64, Vec<Playback>>,
+    /// Playbacks with heartbeats that are over, waiting for their stop
+    /// event and last position to be written, with their login.
+    ended: Vec<(i64, Playback)>,
+}
+
+/// Streams being played per login. Links and heartbeats keep a playback
+/// going, one that hears from neither for `idle_secs` is over.
+pub struct Playbacks {
+    idle_secs: i64,
+    write_interval_secs: i64,
+    state: Mutex<State>,
+}
+
+impl Playbacks {
+    pub fn new(config: &PlaybackConfig) -> Playbacks {
+        Playbacks {
+            idle_secs: config.idle_secs as i64,
+            write_interval_secs: config.write_interval_secs as i64,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Counts a playback against its login, refused when `max_connections`
+    /// streams are already played on other devices. A device playing another
+    /// stream of the login is switching to this one, so it takes its place.
+    pub fn start(
+        &self,
+        login_id: i64,
+        playback: Playback,
+        max_connections: i64,
+    ) -> ApiResult<Beat> {
+        let mut state = self.state.lock()?;
+        let State { playing, ended } = &mut *state;
+        let playbacks = playing.entry(login_id).or_default();
+        self.prune(login_id, playbacks, ended, playback.last_seen);
+
+        let previous = playbacks
+            .iter()
+            .position(|x| x.session == playback.session && x.avatar == playback.avatar)
+            .map(|x| playbacks.remove(x));
+
+        if max_connections > 0 && playbacks.len() as i64 >= max_connections {
+            playbacks.extend(previous);
+            return Err(ApiError::TooManyStreams(max_connections));
+        }
+
+        let mut playback = playback;
+        match previous {
+            Some(x) if x.same_stream(&playback) => {
+                playback.started = x.started;
+                playback.reported = x.reported;
+                playback.saved = x.saved;
+                playback.saved_at = x.saved_at;
+                // A link to the stream being played says nothing of where
+                // the player is.
+                if playback.position.is_none() {
+                    playback.serie = x.serie;
+                    playback.position = x.position;
+                    playback.duration = x.duration;
+                    playback.paused = x.paused;
+                }
+            }
+            Some(x) if x.reported => ended.push((login_id, x)),
+            _ => {}
+        }
+
+        let beat = Beat {
+            started: playback.position.is_some() && !playback.reported,
+            save: playback.position.is_some()
+                && playback.position != playback.saved
+                && playback.last_seen - playback.saved_at >= self.write_interval_secs,
+        };
+        playbacks.push(playback);
+
+        Ok(beat)
+    }
+
+    /// Keeps a playback going while its stream is being read.
+    pub fn touch(&self, login_id: i64, playback: &Playback) {
+        let now = chrono::Utc::now().timestamp();
+        self.update(login_id, playback, |x| x.last_seen = now);
+    }
+
+    /// Notes that the start event of a playback was recorded.
+    fn reported(&self, login_id: i64, playback: &Playback) {
+        self.update(login_id, playback, |x| x.reported = true);
+    }
+
+    /// Notes that the position of a playback was written, so the next one is
+    /// only due after `write_interval_secs`.
+    fn saved(&self, login_id: i64, playback: &Playback) {
+        self.update(login_id, playback, |x| {
+            x.saved = playback.position;
+            x.saved_at = playback.last_seen;
+        });
+    }
+
+    fn update<F>(&self, login_id: i64, playback: &Playback, update: F)
+    where
+        F: FnOnce(&mut Playback),
+    {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let found = state.playing.get_mut(&login_id).and_then(|x| {
+            x.iter_mut().find(|x| {
+                x.session == playback.session
+                    && x.avatar == playback.avatar
+                    && x.same_stream(playback)
+            })
+        });
+        if let Some(found) = found {
+            update(found);
+        }
+    }
+
+    /// Ends the playback of a device when it is still playing the same
+    /// stream, returned when it was going.
+    fn stop(&self, login_id: i64, playback: &Playback) -> Option<Playback> {
+        let mut state = self.state.lock().ok()?;
+        let playbacks = state.playing.get_mut(&login_id)?;
+
+        playbacks
+            .iter()
+            .position(|x| {
+                x.session == playback.session
+                    && x.avatar == playback.avatar
+                    && x.same_stream(playback)
+            })
+            .map(|x| playbacks.remove(x))
+    }
+
+    /// Playbacks of a login that are still going.
+    pub fn list(&self, login_id: i64) -> Vec<Playback> {
+        let Ok(mut state) = self.state.lock() else {
+            return Vec::new();
+        };
+        let State { playing, ended } = &mut *state;
+        let Some(playbacks) = playing.get_mut(&login_id) else {
+            return Vec::new();
+        };
+
+        self.prune(login_id, playbacks, ended, chrono::Utc::now().timestamp());
+        playbacks.clone()
+    }
+
+    /// Playbacks that ended since the last call, idle ones included.
+    fn take_ended(&self) -> Vec<(i64, Playback)> {
+        let Ok(mut state) = self.state.lock() else {
+            return Vec::new();
+        };
+        let State { playing, ended } = &mut *state;
+
+        let now = chrono::Utc::now().timestamp();
+        for (login_id, playbacks) in playing.iter_mut() {
+            self.prune(*login_id, playbacks, ended, now);
+        }
+        playing.retain(|_, x| !x.is_empty());
+
+        std::mem::take(ended)
+    }
+
+    fn prune(
+        &self,
+        login_id: i64,
+        playbacks: &mut Vec<Playback>,
+        ended: &mut Vec<(i64, Playback)>,
+        now: i64,
+    ) {
+        let (idle, going): (Vec<Playback>, Vec<Playback>) = std::mem::take(playbacks)
+            .into_iter()
+            .partition(|x| now - x.last_seen >= self.idle_secs);
+        *playbacks = going;
+
+        ended.extend(
+            idle.into_iter()
+                .filter(|x| x.reported)
+                .map(|x| (login_id, x)),
+        );
+    }
+}
+
+/// Counts a stream about to be played against the connections its login
+/// is allowed.
+pub async fn start(
+    login_id: i64,
+    playback: Playback,
+    db: &DatabaseConnection,
+    client: &Client,
+) -> ApiResult<Beat> {
+    let max_connections = UserInfoEntity::find_by_id(login_id)
+        .one(db)
+        .await?
+        .map(|x| x.max_connections)
+        .unwrap_or_default();
+
+    let beat = client
+        .playbacks
+        .start(login_id, playback, max_connections)?;
+    finish(db, client).await?;
+
+    Ok(beat)
+}
+
+/// Writes the stop event and the last position of the playbacks that are
+/// over.
+async fn finish(db: &DatabaseConnection, client: &Client) -> ApiResult<()> {
+    for (login_id, playback) in client.playbacks.take_ended() {
+        if playback.position != playback.saved {
+            if let Some(position) = playback.watching(login_id) {
+                watching::update(&position, db).await?;
+            }
+        }
+        record(login_id, &playback, Event::Stop, playback.last_seen, db).await?;
+    }
+
+    Ok(())
+}
+
+async fn record(
+    login_id: i64,
+    playback: &Playback,
+    event: Event,
+    date: i64,
+    db: &DatabaseConnection,
+) -> ApiResult<()> {
+    PlaybackEventEntity::insert(PlaybackEventActiveModel {
+        id: Default::default(),
+        login_id: ActiveValue::Set(login_id),
+        session_id: ActiveValue::Set(playback.session),
+        avatar_id: ActiveValue::Set(playback.avatar),
+        kind: ActiveValue::Set(playback.kind),
+        value_id: ActiveValue::Set(playback.serie.unwrap_or(playback.id)),
+        episode_id: ActiveValue::Set(playback.serie.map(|_| playback.id)),
+        event: ActiveValue::Set(event),
+        position: ActiveValue::Set(playback.position.unwrap_or_default()),
+        date: ActiveValue::Set(date),
+    })
+    .exec(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Closes the playbacks that went idle and deletes old events.
+pub async fn clean(
+    db: ActixWeb::Data<DatabaseConnection>,
+    client: ActixWeb::Data<Client>,
+    event_days: i64,
+) -> ApiResult<()> {
+    finish(&db, &client).await?;
+
+    PlaybackEventEntity::delete_many()
+        .filter(PlaybackEventColumn::Date.lte(get_days_ago(event_days)))
+        .exec(db.get_ref())
+        .await?;
+
+    Ok(())
+}
+
+#[derive(ToSchema, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+#[derive(ToSchema, Deserialize)]
+pub struct Heartbeat {
+    /// Login the id belongs to, the first login of the account when missing
+    source: Option<i64>,
+    /// Avatar watching, the position is only saved for one.
+    avatar: Option<i64>,
+    kind: CatalogKind,
+    /// Stream id, the serie id for series.
+    id: i64,
+    /// Episode being played, required for series.
+    episode_id: Option<i64>,
+    /// Position in seconds.
+    #[serde(default)]
+    position: i64,
+    /// Length in seconds, when the player knows it.
+    duration: Option<i64>,
+    state: PlayerState,
+}
+
+#[utoipa::path(
+    post,
+    path = "/playback/heartbeat",
+    tag = "playback",
+    request_body = Heartbeat,
+    responses(
+        (status = 200, description = "Playback was updated", body = BoolResult),
+        (status = 400, description = "Episode id is missing for a serie", body = ApiErrorJson),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar, source, id or episode id does not exist", body = ApiErrorJson),
+        (status = 429, description = "Every stream the source allows is in use", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::post("/heartbeat")]
+pub async fn heartbeat(
+    credentials: BearerAuth,
+    request: ActixWeb::Json<Heartbeat>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let request = request.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let login = login::get_login(&session, request.source, &db, &crypto).await?;
+
+    if let Some(avatar) = request.avatar {
+        AvatarEntity::find()
+            .filter(AvatarColumn::Id.eq(avatar))
+            .filter(AvatarColumn::AccountId.eq(session.account_id))
+            .one(db.get_ref())
+            .await?
+            .ok_or(ApiError::WrongAvatar)?;
+    }
+
+    if request.kind == CatalogKind::Serie && request.episode_id.is_none() {
+        return Err(ApiError::BadRequest("episode_id is required".to_string()));
+    }
+    if request.position < 0 || request.duration.is_some_and(|x| x < 0) {
+        return Err(ApiError::BadRequest(
+            "position and duration can not be negative".to_string(),
+        ));
+    }
+
+    // Unknown ids must neither hold a stream nor get events.
+    catalog::get_value(&login, request.kind, request.id, &db, client.clone()).await?;
+
+    let episode_id = request
+        .episode_id
+        .filter(|_| request.kind == CatalogKind::Serie);
+    let mut playback = Playback::new(
+        session.id,
+        request.avatar,
+        request.kind,
+        episode_id.unwrap_or(request.id),
+    );
+    playback.serie = episode_id.map(|_| request.id);
+    playback.position = Some(request.position);
+    playback.duration = request.duration;
+    playback.paused = request.state == PlayerState::Paused;
+
+    let save = if request.state == PlayerState::Stopped {
+        let stopped = client.playbacks.stop(login.id, &playback);
+        if stopped.is_some_and(|x| x.reported) {
+            record(login.id, &playback, Event::Stop, playback.last_seen, &db).await?;
+        }
+        true
+    } else {
+        let beat = start(login.id, playback.clone(), &db, &client).await?;
+        if beat.started {
+            record(login.id, &playback, Event::Start, playback.started, &db).await?;
+            client.playbacks.reported(login.id, &playback);
+        }
+        beat.save
+    };
+
+    // Only marked saved once written, so a failed write is tried again on
+    // the next heartbeat.
+    if save {
+        if let Some(position) = playback.watching(login.id) {
+            watching::save_position(&login, position, &db, client.clone()).await?;
+        }
+        client.playbacks.saved(login.id, &playback);
+    }
+
+    Ok(HttpResponse::Ok().json(BoolResult { result: true }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/playback/get",
+    tag = "playback",
+    responses(
+        (status = 200, description = "Streams being played on every source of the account", body = Vec<SourcePlaybacks>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/get")]
+pub async fn get(
+    credentials: BearerAuth,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let logins = login::get_logins(&session, &db, &crypto).await?;
+
+    let sessions: HashMap<i64, Session> = SessionEntity::find()
+        .filter(SessionColumn::AccountId.eq(session.account_id))
+        .all(db.get_ref())
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x))
+        .collect();
+
+    let avatars: HashMap<i64, Avatar> = AvatarEntity::find()
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
+        .all(db.get_ref())
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x))
+        .collect();
+
+    let mut result = Vec::new();
+    for login in logins {
+        let user_info = UserInfoEntity::find_by_id(login.id)
+            .one(db.get_ref())
+            .await?;
+
+        let playbacks = client
+            .playbacks
+            .list(login.id)
+            .into_iter()
+            .map(|x| PlaybackInfo {
+                session: x.session,
+                device: sessions
+                    .get(&x.session)
+                    .map(|x| x.device.clone())
+                    .unwrap_or_default(),
+                current: x.session == session.id,
+                avatar: x.avatar,
+                avatar_name: x
+                    .avatar
+                    .and_then(|x| avatars.get(&x))
+                    .map(|x| x.name.clone()),
+                kind: x.kind,
+                id: x.id,
+                serie: x.serie,
+                started: x.started,
+                last_seen: x.last_seen,
+                position: x.position,
+                duration: x.duration,
+                paused: x.paused,
+            })
+            .collect();
+
+        result.push(SourcePlaybacks {
+            source: login.id,
+            max_connections: user_info
+                .as_ref()
+                .map(|x| x.max_connections)
+                .unwrap_or_default(),
+            active_cons: user_info.map(|x| x.active_cons).unwrap_or_default(),
+            playbacks,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playbacks() -> Playbacks {
+        Playbacks::new(&PlaybackConfig {
+            idle_secs: 120,
+            write_interval_secs: 30,
+        })
+    }
+
+    /// Playback seen `secs` after the test started.
+    fn playback(session: i64, id: i64, secs: i64) -> Playback {
+        let now = chrono::Utc::now().timestamp();
+        let mut playback = Playback::new(session, None, CatalogKind::Movie, id);
+        playback.started = now + secs;
+        playback.last_seen = now + secs;
+        playback
+    }
+
+    fn heartbeat(session: i64, id: i64, secs: i64, position: i64) -> Playback {
+        let mut playback = playback(session, id, secs);
+        playback.position = Some(position);
+        playback
+    }
+
+    #[test]
+    fn refuses_devices_over_the_limit() {
+        let playbacks = playbacks();
+
+        playbacks.start(1, playback(1, 10, 0), 2).unwrap();
+        playbacks.start(1, playback(2, 10, 0), 2).unwrap();
+        assert!(matches!(
+            playbacks.start(1, playback(3, 10, 0), 2),
+            Err(ApiError::TooManyStreams(2))
+        ));
+
+        // Other logins and unlimited ones are counted apart.
+        playbacks.start(2, playback(3, 10, 0), 2).unwrap();
+        playbacks.start(1, playback(3, 10, 0), 0).unwrap();
+    }
+
+    #[test]
+    fn switching_streams_keeps_the_slot() {
+        let playbacks = playbacks();
+
+        playbacks.start(1, playback(1, 10, 0), 1).unwrap();
+        playbacks.start(1, playback(1, 10, 5), 1).unwrap();
+        playbacks.start(1, playback(1, 20, 9), 1).unwrap();
+
+        let list = playbacks.list(1);
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].id, 20);
+
+        let started = list[0].started;
+        playbacks.start(1, playback(1, 20, 12), 1).unwrap();
+        assert_eq!(playbacks.list(1)[0].started, started);
+    }
+
+    #[test]
+    fn idle_playbacks_free_their_slot() {
+        let playbacks = playbacks();
+
+        playbacks.start(1, playback(1, 10, 0), 1).unwrap();
+        assert!(playbacks.start(1, playback(2, 10, 119), 1).is_err());
+        playbacks.start(1, playback(2, 10, 120), 1).unwrap();
+    }
+
+    #[test]
+    fn heartbeats_are_due_for_saving_once_an_interval() {
+        let playbacks = playbacks();
+
+        let first = heartbeat(1, 10, 0, 5);
+        let beat = playbacks.start(1, first.clone(), 1).unwrap();
+        assert!(beat.started && beat.save);
+        playbacks.reported(1, &first);
+        playbacks.saved(1, &first);
+
+        let beat = playbacks.start(1, heartbeat(1, 10, 10, 15), 1).unwrap();
+        assert!(!beat.started && !beat.save);
+
+        // Not saved until written, so a failed write is due again.
+        let beat = playbacks.start(1, heartbeat(1, 10, 30, 35), 1).unwrap();
+        assert!(beat.save);
+        let beat = playbacks.start(1, heartbeat(1, 10, 40, 45), 1).unwrap();
+        assert!(beat.save);
+
+        // A paused player does not move, there is nothing to save.
+        let paused = heartbeat(1, 10, 50, 45);
+        playbacks.saved(1, &paused);
+        let beat = playbacks.start(1, heartbeat(1, 10, 90, 45), 1).unwrap();
+        assert!(!beat.save);
+    }
+
+    #[test]
+    fn links_keep_the_position_of_heartbeats() {
+        let playbacks = playbacks();
+
+        playbacks.start(1, heartbeat(1, 10, 0, 5), 1).unwrap();
+        playbacks.start(1, playback(1, 10, 10), 1).unwrap();
+
+        assert_eq!(playbacks.list(1)[0].position, Some(5));
+    }
+
+    #[test]
+    fn only_reported_playbacks_end_with_a_stop() {
+        let playbacks = playbacks();
+
+        let reported = heartbeat(1, 10, 0, 5);
+        playbacks.start(1, reported.clone(), 0).unwrap();
+        playbacks.reported(1, &reported);
+        playbacks.start(1, heartbeat(2, 10, 0, 5), 0).unwrap();
+
+        // Switching away ends the reported playback of the first device.
+        playbacks.start(1, heartbeat(1, 20, 10, 0), 0).unwrap();
+        let ended = playbacks.take_ended();
+        assert_eq!(ended.len(), 1);
+        assert_eq!((ended[0].0, ended[0].1.session, ended[0].1.id), (1, 1, 10));
+
+        assert!(playbacks.stop(1, &heartbeat(2, 20, 20, 0)).is_none());
+        assert!(playbacks.stop(1, &heartbeat(2, 10, 20, 0)).is_some());
+    }
+}
@@ -0,0 +1,434 @@
+use actix_web::{web as ActixWeb, HttpRequest, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use futures::{stream, StreamExt, TryStreamExt};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use std::collections::HashMap;
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    catalog,
+    client::Client,
+    config::Config,
+    crypto::Crypto,
+    entities::{catalog_stream::Kind as CatalogKind, favorite::Kind as FavoriteKind, prelude::*},
+    epg,
+    get::{Page, Value},
+    hls::VariantLimit,
+    link::{self, Target},
+    login::{self, SourceQuery},
+    xmltv,
+};
+
+/// A stream written to an exported playlist.
+struct Entry {
+    kind: CatalogKind,
+    value: Value,
+    group: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/playlist/favorite/{avatar}/m3u",
+    tag = "playlist",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+    ),
+    responses(
+        (status = 200, description = "M3U playlist of the live and movie favorites of the avatar", body = String, content_type = "audio/x-mpegurl"),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/favorite/{avatar}/m3u")]
+async fn favorite_m3u(
+    req: HttpRequest,
+    credentials: BearerAuth,
+    path: ActixWeb::Path<i64>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let avatar = path.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let logins = login::get_logins(&session, &db, &crypto).await?;
+
+    let entries = get_favorites(&session, avatar, &logins, &db, client.clone()).await?;
+
+    m3u_response(
+        &req,
+        &entries,
+        &logins,
+        &session,
+        Some(avatar),
+        &crypto,
+        &client,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/playlist/favorite/{avatar}/xmltv",
+    tag = "playlist",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+    ),
+    responses(
+        (status = 200, description = "XMLTV guide of the live favorites of the avatar", body = String, content_type = "application/xml"),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/favorite/{avatar}/xmltv")]
+async fn favorite_xmltv(
+    credentials: BearerAuth,
+    path: ActixWeb::Path<i64>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let avatar = path.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let logins = login::get_logins(&session, &db, &crypto).await?;
+
+    let entries = get_favorites(&session, avatar, &logins, &db, client.clone()).await?;
+
+    xmltv_response(&entries, &logins, &db, client).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/playlist/category/{kind}/{category_id}/m3u",
+    tag = "playlist",
+    params(
+        ("kind" = Kind, Path, description = "Kind of the values, live or movie"),
+        ("category_id" = i64, Path, description = "Category of the values"),
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "M3U playlist of the category", body = String, content_type = "audio/x-mpegurl"),
+        (status = 400, description = "Series have no playable stream", body = ApiErrorJson),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/category/{kind}/{category_id}/m3u")]
+async fn category_m3u(
+    req: HttpRequest,
+    credentials: BearerAuth,
+    path: ActixWeb::Path<(CatalogKind, i64)>,
+    source: ActixWeb::Query<SourceQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let (kind, category_id) = path.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let login = login::get_login(&session, source.source, &db, &crypto).await?;
+
+    let entries = get_category(&login, kind, category_id, &db, client.clone()).await?;
+
+    m3u_response(&req, &entries, &[login], &session, None, &crypto, &client)
+}
+
+#[utoipa::path(
+    get,
+    path = "/playlist/category/{kind}/{category_id}/xmltv",
+    tag = "playlist",
+    params(
+        ("kind" = Kind, Path, description = "Kind of the values, live or movie"),
+        ("category_id" = i64, Path, description = "Category of the values"),
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "XMLTV guide of the lives of the category", body = String, content_type = "application/xml"),
+        (status = 400, description = "Series have no playable stream", body = ApiErrorJson),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/category/{kind}/{category_id}/xmltv")]
+async fn category_xmltv(
+    credentials: BearerAuth,
+    path: ActixWeb::Path<(CatalogKind, i64)>,
+    source: ActixWeb::Query<SourceQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let (kind, category_id) = path.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let login = login::get_login(&session, source.source, &db, &crypto).await?;
+
+    let entries = get_category(&login, kind, category_id, &db, client.clone()).await?;
+
+    xmltv_response(&entries, &[login], &db, client).await
+}
+
+/// Live and movie favorites of an avatar, looked up in the catalog of their
+/// login. Series have no single stream and are left out, as are favorites the
+/// provider no longer lists.
+async fn get_favorites(
+    session: &Session,
+    avatar: i64,
+    logins: &[Login],
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<Entry>> {
+    AvatarEntity::find()
+        .filter(AvatarColumn::Id.eq(avatar))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
+        .one(db)
+        .await?
+        .ok_or(ApiError::WrongAvatar)?;
+
+    let favorites = FavoriteEntity::find()
+        .filter(FavoriteColumn::AvatarId.eq(avatar))
+        .filter(FavoriteColumn::Kind.ne(FavoriteKind::Serie))
+        .order_by_asc(FavoriteColumn::Id)
+        .all(db)
+        .await?;
+
+    let mut categories = HashMap::new();
+    for kind in [CatalogKind::Live, CatalogKind::Movie] {
+        for category in catalog::get_catalog_categories(logins, kind, db, client.clone()).await? {
+            categories.insert(
+                (category.login_id, kind, category.category_id),
+                category.name,
+            );
+        }
+    }
+
+    let mut entries = Vec::new();
+
+    for favorite in favorites {
+        let Some(login) = logins.iter().find(|x| x.id == favorite.login_id) else {
+            continue;
+        };
+
+        let kind = match favorite.kind {
+            FavoriteKind::Live => CatalogKind::Live,
+            FavoriteKind::Movie | FavoriteKind::Serie => CatalogKind::Movie,
+        };
+
+        let value =
+            match catalog::get_value(login, kind, favorite.value_id, db, client.clone()).await {
+                Ok(value) => value,
+                Err(ApiError::WrongId) => continue,
+                Err(err) => return Err(err),
+            };
+
+        let group = value
+            .category_id
+            .and_then(|x| categories.get(&(login.id, kind, x)))
+            .cloned();
+
+        entries.push(Entry { kind, value, group });
+    }
+
+    Ok(entries)
+}
+
+async fn get_category(
+    login: &Login,
+    kind: CatalogKind,
+    category_id: i64,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<Entry>> {
+    if kind == CatalogKind::Serie {
+        return Err(ApiError::BadRequest(
+            "series have no playable stream, only their episodes".to_string(),
+        ));
+    }
+
+    let group =
+        catalog::get_catalog_categories(std::slice::from_ref(login), kind, db, client.clone())
+            .await?
+            .into_iter()
+            .find(|x| x.category_id == category_id)
+            .map(|x| x.name);
+
+    let (values, _) = catalog::get_page(
+        std::slice::from_ref(login),
+        kind,
+        Some(category_id),
+        &Page::default(),
+        db,
+        client,
+    )
+    .await?;
+
+    Ok(values
+        .into_iter()
+        .map(|value| Entry {
+            kind,
+            value,
+            group: group.clone(),
+        })
+        .collect())
+}
+
+/// Channel id of a live in guides, the one of its provider or made up from
+/// its id when it has none.
+fn channel_id(value: &Value) -> String {
+    value
+        .epg_channel_id
+        .clone()
+        .unwrap_or_else(|| format!("{}.{}", value.source, value.id))
+}
+
+fn m3u_response(
+    req: &HttpRequest,
+    entries: &[Entry],
+    logins: &[Login],
+    session: &Session,
+    avatar: Option<i64>,
+    crypto: &Crypto,
+    client: &Client,
+) -> ApiResult<HttpResponse> {
+    let mut playlist = String::from("#EXTM3U\n");
+
+    // Players keep playlists for days, so their links outlive the ones of
+    // the api.
+    let ttl_secs = req
+        .app_data::<ActixWeb::Data<Config>>()
+        .map(|x| x.link.playlist_ttl_secs);
+
+    for entry in entries {
+        let Some(login) = logins.iter().find(|x| x.id == entry.value.source) else {
+            continue;
+        };
+
+        // Panels only list the container of movies.
+        let container_extension = match &entry.value.container_extension[..] {
+            "" if entry.kind == CatalogKind::Live => "ts",
+            "" => "mp4",
+            extension => extension,
+        };
+
+        let url = match &entry.value.url {
+            Some(url) => url.clone(),
+            None => link::stream_url(
+                login,
+                entry.kind,
+                entry.value.id,
+                container_extension,
+                client,
+            )?,
+        };
+
+        let target = Target {
+            kind: entry.kind,
+            id: entry.value.id,
+            container_extension: container_extension.to_string(),
+            session: session.id,
+            avatar,
+            variant: VariantLimit::default(),
+            ttl_secs,
+        };
+        let url = link::player_url(req, login, target, url, crypto)?;
+
+        let mut attributes = Vec::new();
+        if entry.kind == CatalogKind::Live {
+            attributes.push(("tvg-id", channel_id(&entry.value)));
+        }
+        attributes.push(("tvg-name", entry.value.name.clone()));
+        if !entry.value.icon.is_empty() {
+            attributes.push(("tvg-logo", entry.value.icon.clone()));
+        }
+        if let Some(group) = &entry.group {
+            attributes.push(("group-title", group.clone()));
+        }
+
+        playlist.push_str("#EXTINF:-1");
+        for (key, value) in attributes {
+            // Quotes can not be escaped in attributes.
+            playlist.push_str(&format!(" {}=\"{}\"", key, value.replace('"', "'")));
+        }
+        playlist.push_str(&format!(
+            ",{}\n{}\n",
+            one_line(&entry.value.name),
+            one_line(&url)
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("audio/x-mpegurl; charset=utf-8")
+        .body(playlist))
+}
+
+/// Guide of the lives among `entries`, every programme their server knows.
+async fn xmltv_response(
+    entries: &[Entry],
+    logins: &[Login],
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let mut lives: Vec<(&Login, &Value, String)> = Vec::new();
+
+    for entry in entries.iter().filter(|x| x.kind == CatalogKind::Live) {
+        let Some(login) = logins.iter().find(|x| x.id == entry.value.source) else {
+            continue;
+        };
+
+        let id = channel_id(&entry.value);
+
+        // The same channel can be on several logins, or favorited twice.
+        if lives.iter().any(|x| x.2 == id) {
+            continue;
+        }
+
+        lives.push((login, &entry.value, id));
+    }
+
+    let channels: Vec<xmltv::Channel> = stream::iter(lives)
+        .map(|(login, value, id)| {
+            let client = client.clone();
+            async move {
+                let programmes = epg::get_full_epg(login, value, db, client).await?;
+
+                Ok::<_, ApiError>(xmltv::Channel {
+                    id,
+                    name: value.name.clone(),
+                    icon: value.icon.clone(),
+                    programmes,
+                })
+            }
+        })
+        .buffered(epg::CONCURRENT_CHANNELS)
+        .try_collect()
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/xml; charset=utf-8")
+        .body(xmltv::write(&channels)))
+}
+
+/// Line breaks would start a new entry.
+fn one_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
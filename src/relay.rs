@@ -0,0 +1,264 @@
+use actix_web::{
+    http::{Method, StatusCode},
+    web as ActixWeb, HttpRequest, HttpResponse,
+};
+use futures::StreamExt;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    client::Client,
+    config::Config,
+    crypto::Crypto,
+    entities::catalog_stream::Kind as CatalogKind,
+    extra,
+    hls::{self, VariantLimit},
+    playback::{self, Playback},
+    session,
+};
+
+const TOKEN_PURPOSE: &str = "stream";
+
+/// Headers passed from the player to the provider, so seeking works.
+const REQUEST_HEADERS: [&str; 2] = ["range", "if-range"];
+
+/// Headers passed from the provider back to the player.
+const RESPONSE_HEADERS: [&str; 6] = [
+    "content-type",
+    "content-length",
+    "content-range",
+    "accept-ranges",
+    "last-modified",
+    "etag",
+];
+
+/// What a stream token carries, sealed so the player never sees the
+/// provider url and its credentials.
+#[derive(Clone, Serialize, Deserialize)]
+struct Payload {
+    login: i64,
+    /// The link stops working once this session is removed.
+    session: i64,
+    /// Playback kept going while the player fetches the stream.
+    avatar: Option<i64>,
+    kind: CatalogKind,
+    id: i64,
+    url: String,
+    expires: i64,
+    #[serde(default)]
+    variant: VariantLimit,
+}
+
+/// Url handed to the player for an upstream stream url, pointing at the
+/// relay when it is enabled. Variants can only be picked by the relay.
+pub fn player_url(
+    req: &HttpRequest,
+    url: String,
+    login_id: i64,
+    playback: &Playback,
+    variant: VariantLimit,
+    ttl_secs: Option<u64>,
+    crypto: &Crypto,
+) -> ApiResult<String> {
+    let enabled = req
+        .app_data::<ActixWeb::Data<Config>>()
+        .filter(|x| x.relay.enabled);
+    let Some(config) = enabled else {
+        if !variant.is_empty() {
+            return Err(ApiError::BadRequest(
+                "variant selection needs the stream relay".into(),
+            ));
+        }
+        return Ok(url);
+    };
+
+    let file = file_name(&url)?;
+    let payload = Payload {
+        login: login_id,
+        session: playback.session,
+        avatar: playback.avatar,
+        kind: playback.kind,
+        id: playback.id,
+        url,
+        expires: chrono::Utc::now().timestamp()
+            + ttl_secs.unwrap_or(config.relay.token_ttl_secs) as i64,
+        variant,
+    };
+
+    Ok(format!(
+        "{}/stream/{}/{}",
+        public_url(config),
+        seal(&payload, crypto)?,
+        file
+    ))
+}
+
+/// Url clients reach the api at, never taken from the request since its
+/// `Host` is up to the client.
+pub fn public_url(config: &Config) -> &str {
+    config.public_url.trim_end_matches('/')
+}
+
+fn seal(payload: &Payload, crypto: &Crypto) -> ApiResult<String> {
+    crypto.seal_token(&serde_json::to_string(payload)?, TOKEN_PURPOSE)
+}
+
+fn open(token: &str, crypto: &Crypto) -> ApiResult<Payload> {
+    let payload = crypto
+        .open_token(token, TOKEN_PURPOSE)
+        .map_err(|_| ApiError::WrongStreamToken)?;
+    let payload: Payload =
+        serde_json::from_str(&payload).map_err(|_| ApiError::WrongStreamToken)?;
+
+    if payload.expires <= chrono::Utc::now().timestamp() {
+        return Err(ApiError::WrongStreamToken);
+    }
+
+    Ok(payload)
+}
+
+/// Last path segment of the upstream url, so players that look at the
+/// extension still know what they are playing.
+fn file_name(url: &str) -> ApiResult<String> {
+    let url = url::Url::parse(url)?;
+    let name = url
+        .path_segments()
+        .and_then(|mut x| x.next_back())
+        .filter(|x| !x.is_empty())
+        .unwrap_or("stream");
+
+    Ok(name.to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/stream/{token}/{file}",
+    tag = "link",
+    params(
+        ("token" = String, Path, description = "Token from a relayed link"),
+        ("file" = String, Path, description = "File name of the stream"),
+    ),
+    responses(
+        (status = 200, description = "Stream bytes from the provider"),
+        (status = 206, description = "Requested range of the stream"),
+        (status = 403, description = "Link is invalid, expired or its session was removed", body = ApiErrorJson),
+    )
+)]
+#[actix_web::route("/stream/{token}/{file}", method = "GET", method = "HEAD")]
+async fn stream(
+    req: HttpRequest,
+    path: ActixWeb::Path<(String, String)>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let (token, _) = path.into_inner();
+    let payload = open(&token, &crypto)?;
+
+    session::find_active(payload.session, &db)
+        .await?
+        .ok_or(ApiError::WrongStreamToken)?;
+
+    // Players fetching through the relay hold their stream without
+    // heartbeats.
+    let playback = Playback::new(payload.session, payload.avatar, payload.kind, payload.id);
+    playback::start(payload.login, playback.clone(), &db, &client).await?;
+
+    let head = req.method() == Method::HEAD;
+
+    let mut request = if head {
+        client.http.head(&payload.url)
+    } else {
+        client.http.get(&payload.url)
+    };
+    for name in REQUEST_HEADERS {
+        if let Some(value) = req.headers().get(name) {
+            request = request.header(name, value.as_bytes());
+        }
+    }
+
+    let response = request.send().await?;
+    let status = response.status().as_u16();
+
+    tracing::debug!(status, "relay response");
+
+    let status = StatusCode::from_u16(status).map_err(|_| ApiError::RequestServerError(status))?;
+    if !status.is_success() && status != StatusCode::RANGE_NOT_SATISFIABLE {
+        return Err(ApiError::RequestServerError(status.as_u16()));
+    }
+
+    if !head && is_playlist(&response) {
+        return playlist(response, &payload, &client, &crypto).await;
+    }
+
+    let mut builder = HttpResponse::build(status);
+    for name in RESPONSE_HEADERS {
+        if let Some(value) = response.headers().get(name) {
+            builder.insert_header((name, value.as_bytes()));
+        }
+    }
+
+    // A whole movie can come in one response, it keeps the playback going
+    // while it is read.
+    let login_id = payload.login;
+    let mut touched = chrono::Utc::now().timestamp();
+    let body = response.bytes_stream().inspect(move |_| {
+        let now = chrono::Utc::now().timestamp();
+        if now > touched {
+            touched = now;
+            client.playbacks.touch(login_id, &playback);
+        }
+    });
+
+    Ok(builder.streaming(body))
+}
+
+fn is_playlist(response: &reqwest::Response) -> bool {
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    response.url().path().ends_with(".m3u8") || content_type.contains("mpegurl")
+}
+
+/// Hls playlists point at more urls of the provider, every one of them is
+/// sealed into a token of its own with the same expiry, so the player only
+/// ever talks to the relay.
+async fn playlist(
+    response: reqwest::Response,
+    payload: &Payload,
+    client: &Client,
+    crypto: &Crypto,
+) -> ApiResult<HttpResponse> {
+    // Relative urls are resolved against where the provider redirected to.
+    let base = response.url().clone();
+    let text = extra::read_text(response, client.max_body_bytes).await?;
+
+    let playlist = hls::rewrite(&text, &payload.variant, |uri| {
+        let url = base.join(uri)?;
+        // Data urls and DRM key uris are not fetched over http.
+        if !matches!(url.scheme(), "http" | "https") {
+            return Ok(uri.to_string());
+        }
+
+        let file = file_name(url.as_str())?;
+        let payload = Payload {
+            url: url.into(),
+            ..payload.clone()
+        };
+
+        Ok(format!("../{}/{}", seal(&payload, crypto)?, file))
+    })?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.content_type("application/vnd.apple.mpegurl");
+    if playlist.live {
+        builder.insert_header(("cache-control", "no-cache"));
+    }
+
+    Ok(builder.body(playlist.body))
+}
@@ -0,0 +1,69 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+};
+use rand::{rngs::OsRng, RngCore};
+use tracing::Instrument;
+
+pub const HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request being handled by the current task, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|x| x.clone()).ok()
+}
+
+/// Reuses a sane `X-Request-Id` sent by a proxy or generates a new one, makes it
+/// available through [`current`] and the tracing span while the request is
+/// handled and echoes it back.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(HEADER)
+        .and_then(|x| x.to_str().ok())
+        .filter(|x| !x.is_empty() && x.len() <= 64 && x.bytes().all(|x| x.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(generate);
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+    );
+    let start = std::time::Instant::now();
+
+    let mut res = REQUEST_ID
+        .scope(request_id.clone(), next.call(req))
+        .instrument(span.clone())
+        .await?;
+
+    span.in_scope(|| {
+        tracing::info!(
+            status = res.status().as_u16(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "request finished"
+        )
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static(HEADER), value);
+    }
+
+    Ok(res)
+}
+
+fn generate() -> String {
+    let mut random_bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut random_bytes);
+    hex::encode(random_bytes)
+}
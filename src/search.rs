@@ -1,52 +1,320 @@
-use actix_web::{web as ActixWeb, HttpResponse};
+use actix_web::{http::header::ContentType, web as ActixWeb, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
-use indicium::simple::{SearchIndexBuilder, SearchType};
-use sea_orm::DatabaseConnection;
+use sea_orm::{
+    ActiveEnum, ConnectionTrait, DatabaseConnection, DbBackend, EntityTrait, Statement,
+    Value as DbValue,
+};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
     api_error::ApiResult,
-    extra::Params,
-    get::{get_lives, get_movies, get_series, Kind},
+    catalog,
+    client::Client,
+    crypto::Crypto,
+    entities::prelude::*,
+    extra::JsonArray,
+    get::{Kind, Value},
     login,
 };
 
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 500;
+
+#[derive(IntoParams, Deserialize)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Only search this login, category ids belong to a single login
+    source: Option<i64>,
+    category_id: Option<i64>,
+    year: Option<i64>,
+    min_rating: Option<f64>,
+    /// Maximum number of values returned, at most 500
+    limit: Option<u64>,
+    offset: Option<u64>,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct SearchValue {
+    kind: Kind,
+
+    #[serde(flatten)]
+    #[schema(value_type = CatalogValue)]
+    value: Value,
+}
+
+#[utoipa::path(
+    get,
+    path = "/search/{kind}/{text}",
+    tag = "search",
+    params(
+        ("kind" = Kind, Path, description = "Kind of the values"),
+        ("text" = String, Path, description = "Words to search, typos are tolerated"),
+        SearchQuery,
+    ),
+    responses(
+        (status = 200, description = "Values ordered by relevance", body = Vec<CatalogValue>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/search/{kind}/{text}")]
 pub async fn search(
     credentials: BearerAuth,
     path: ActixWeb::Path<(Kind, String)>,
+    query: ActixWeb::Query<SearchQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<HttpResponse> {
     let auth_key = credentials.token();
 
     let (kind, mut text) = path.into_inner();
     text = urlencoding::decode(&text)?.into_owned();
 
-    let session = login::get_session(auth_key, &db).await?;
-    let login = login::get_login(&session, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let logins = search_logins(&session, &query, &db, &crypto).await?;
 
-    let params = Params::new(&login);
+    let result = find(&logins, &[kind], &text, &query, &db, client)
+        .await?
+        .into_iter()
+        .map(|(_, x)| Ok(x));
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(JsonArray::new(result)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/search/{text}",
+    tag = "search",
+    params(
+        ("text" = String, Path, description = "Words to search, typos are tolerated"),
+        SearchQuery,
+    ),
+    responses(
+        (status = 200, description = "Values of every kind ordered by relevance", body = Vec<SearchValue>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/search/{text}")]
+pub async fn search_all(
+    credentials: BearerAuth,
+    path: ActixWeb::Path<String>,
+    query: ActixWeb::Query<SearchQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let auth_key = credentials.token();
+
+    let text = urlencoding::decode(&path.into_inner())?.into_owned();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let logins = search_logins(&session, &query, &db, &crypto).await?;
+
+    let kinds = [Kind::Live, Kind::Movie, Kind::Serie];
+
+    let result = find(&logins, &kinds, &text, &query, &db, client)
+        .await?
+        .into_iter()
+        .map(|(kind, value)| Ok(SearchValue { kind, value }));
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(JsonArray::new(result)))
+}
+
+async fn search_logins(
+    session: &Session,
+    query: &SearchQuery,
+    db: &DatabaseConnection,
+    crypto: &Crypto,
+) -> ApiResult<Vec<Login>> {
+    match query.source {
+        Some(_) => Ok(vec![
+            login::get_login(session, query.source, db, crypto).await?,
+        ]),
+        None => login::get_logins(session, db, crypto).await,
+    }
+}
+
+/// Searches the mirrored catalog of `logins`, ranking by relevance first and
+/// then by rating and date added. Titles found on several logins are merged,
+/// so enough rows are read for every login to fill the page.
+async fn find(
+    logins: &[Login],
+    kinds: &[Kind],
+    text: &str,
+    query: &SearchQuery,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<(Kind, Value)>> {
+    for login in logins {
+        for kind in kinds {
+            catalog::ensure_synced(login, *kind, db, client.clone()).await?;
+        }
+    }
 
-    let list = match kind {
-        Kind::Live => get_lives(None, params, client).await?,
-        Kind::Movie => get_movies(None, params, client).await?,
-        Kind::Serie => get_series(None, params, client).await?,
+    let Some(expression) = match_expression(text, db).await? else {
+        return Ok(Vec::new());
     };
 
-    let mut search_index = SearchIndexBuilder::default()
-        .search_type(SearchType::And)
-        .exclude_keywords(None)
-        .build();
+    let mut sql = String::from(
+        "SELECT catalog_stream.* FROM catalog_search \
+        JOIN catalog_stream ON catalog_stream.id = catalog_search.rowid \
+        WHERE catalog_search MATCH ?",
+    );
+    let mut values: Vec<DbValue> = vec![expression.into()];
 
-    for (i, item) in list.iter().enumerate() {
-        search_index.insert(&i, &item.name);
+    sql.push_str(" AND catalog_stream.login_id IN (");
+    sql.push_str(&vec!["?"; logins.len()].join(", "));
+    sql.push(')');
+    values.extend(logins.iter().map(|x| DbValue::from(x.id)));
+
+    sql.push_str(" AND catalog_stream.kind IN (");
+    sql.push_str(&vec!["?"; kinds.len()].join(", "));
+    sql.push(')');
+    values.extend(kinds.iter().map(|x| DbValue::from(x.to_value())));
+
+    if let Some(category_id) = query.category_id {
+        sql.push_str(" AND catalog_stream.category_id = ?");
+        values.push(category_id.into());
+    }
+    if let Some(year) = query.year {
+        sql.push_str(" AND catalog_stream.year = ?");
+        values.push(year.into());
+    }
+    if let Some(min_rating) = query.min_rating {
+        sql.push_str(" AND catalog_stream.rating >= ?");
+        values.push(min_rating.into());
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+
+    sql.push_str(
+        " ORDER BY bm25(catalog_search), catalog_stream.rating DESC, catalog_stream.added DESC \
+        LIMIT ?",
+    );
+    let rows = offset
+        .saturating_add(limit)
+        .saturating_mul(logins.len() as u64);
+    values.push((rows.min(i64::MAX as u64) as i64).into());
+
+    let streams = CatalogStreamEntity::find()
+        .from_raw_sql(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            sql,
+            values,
+        ))
+        .all(db)
+        .await?;
+
+    Ok(catalog::merge(streams)
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect())
+}
+
+/// Builds an FTS5 expression where every word must match as a prefix. Words
+/// that are not a prefix of any indexed term also accept indexed terms a small
+/// edit distance away, so typos still find results.
+async fn match_expression(text: &str, db: &DatabaseConnection) -> ApiResult<Option<String>> {
+    let folded = fold(text);
+    let terms = folded
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<&str>>();
+
+    if terms.is_empty() {
+        return Ok(None);
     }
 
-    let mut result = Vec::new();
+    let mut expressions = Vec::new();
+
+    for term in terms {
+        let prefix = db
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "SELECT term FROM catalog_search_vocab WHERE term GLOB ? LIMIT 1",
+                [format!("{term}*").into()],
+            ))
+            .await?;
+
+        if prefix.is_some() {
+            expressions.push(format!("\"{term}\"*"));
+            continue;
+        }
+
+        let length = term.chars().count() as i64;
+        let max_distance = if length <= 4 { 1 } else { 2 };
+
+        let candidates = db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "SELECT term FROM catalog_search_vocab \
+                WHERE length(term) BETWEEN ? AND ? AND substr(term, 1, 1) = ?",
+                [
+                    (length - max_distance).into(),
+                    (length + max_distance).into(),
+                    term.chars().take(1).collect::<String>().into(),
+                ],
+            ))
+            .await?
+            .into_iter()
+            .filter_map(|x| x.try_get::<String>("", "term").ok())
+            .filter(|x| levenshtein(term, x) <= max_distance as usize)
+            .map(|x| format!("\"{x}\""))
+            .collect::<Vec<String>>();
+
+        if candidates.is_empty() {
+            expressions.push(format!("\"{term}\"*"));
+        } else {
+            expressions.push(format!("({})", candidates.join(" OR ")));
+        }
+    }
+
+    Ok(Some(expressions.join(" AND ")))
+}
+
+pub fn fold(text: &str) -> String {
+    text.nfd()
+        .filter(|x| !is_combining_mark(*x))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut row = (0..=b.len()).collect::<Vec<usize>>();
+
+    for (i, x) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
 
-    for i in search_index.search(&text) {
-        result.push(&list[*i]);
+        for (j, y) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if x == *y {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(row[j + 1])
+            };
+            previous = current;
+        }
     }
 
-    Ok(HttpResponse::Ok().json(result))
+    row[b.len()]
 }
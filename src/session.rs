@@ -0,0 +1,295 @@
+use actix_web::{web as ActixWeb, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use rand::{rngs::OsRng, RngCore};
+use sea_orm::{
+    ActiveValue, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    config::{Config, SessionConfig},
+    crypto::{Crypto, TokenMatch},
+    entities::prelude::*,
+    extra::BoolResult,
+    login,
+};
+
+/// Sessions are only written back once this many seconds passed since the
+/// last use, so every request does not turn into a database write.
+const TOUCH_INTERVAL_SECS: i64 = 60;
+
+#[derive(ToSchema, Serialize)]
+pub struct SessionTokens {
+    /// Session id, as listed by `/session/get`.
+    pub id: i64,
+    pub auth_key: String,
+    pub refresh_token: String,
+    /// Unix time the auth key stops working unless it is used before.
+    pub expires: i64,
+    /// Unix time the refresh token stops working.
+    pub refresh_expires: i64,
+}
+
+#[derive(ToSchema, Serialize)]
+pub struct SessionInfo {
+    #[serde(flatten)]
+    pub session: Session,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+#[derive(ToSchema, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/session/get",
+    tag = "session",
+    responses(
+        (status = 200, description = "Sessions of the account", body = Vec<SessionInfo>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/get")]
+async fn get(
+    credentials: BearerAuth,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+) -> ApiResult<HttpResponse> {
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+    let now = chrono::Utc::now().timestamp();
+
+    let sessions = SessionEntity::find()
+        .filter(SessionColumn::AccountId.eq(session.account_id))
+        .filter(
+            Condition::any()
+                .add(SessionColumn::Expires.gt(now))
+                .add(SessionColumn::RefreshExpires.gt(now)),
+        )
+        .order_by_desc(SessionColumn::LastUsed)
+        .all(db.get_ref())
+        .await?
+        .into_iter()
+        .map(|x| SessionInfo {
+            current: x.id == session.id,
+            session: x,
+        })
+        .collect::<Vec<SessionInfo>>();
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/session/remove/{id}",
+    tag = "session",
+    params(
+        ("id" = i64, Path, description = "Session id"),
+    ),
+    responses(
+        (status = 200, description = "Whether the session was revoked", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Session does not exist", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/remove/{id}")]
+async fn remove(
+    credentials: BearerAuth,
+    path: ActixWeb::Path<i64>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+) -> ApiResult<HttpResponse> {
+    let id = path.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+
+    let result = SessionEntity::delete_many()
+        .filter(SessionColumn::Id.eq(id))
+        .filter(SessionColumn::AccountId.eq(session.account_id))
+        .exec(db.get_ref())
+        .await?;
+
+    if result.rows_affected == 0 {
+        return Err(ApiError::WrongId);
+    }
+
+    Ok(HttpResponse::Ok().json(BoolResult { result: true }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/refresh",
+    tag = "session",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New auth key and refresh token", body = SessionTokens),
+        (status = 401, description = "Refresh token is invalid or expired", body = ApiErrorJson),
+    )
+)]
+#[actix_web::post("/refresh")]
+pub async fn refresh(
+    request: ActixWeb::Json<RefreshRequest>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    config: ActixWeb::Data<Config>,
+) -> ApiResult<HttpResponse> {
+    let now = chrono::Utc::now().timestamp();
+
+    // Both tokens are replaced below, so a match with a previous key needs no
+    // special care.
+    let session = SessionEntity::find()
+        .filter(SessionColumn::RefreshPrefix.eq(token_prefix(&request.refresh_token)))
+        .filter(SessionColumn::RefreshExpires.gt(now))
+        .all(db.get_ref())
+        .await?
+        .into_iter()
+        .find(|x| {
+            x.refresh_token.as_ref().is_some_and(|hash| {
+                crypto.verify_token(&request.refresh_token, hash) != TokenMatch::No
+            })
+        })
+        .ok_or(ApiError::WrongRefreshToken)?;
+
+    // Both tokens are replaced, so a refresh token can only be used once.
+    let tokens = new_tokens(session.id, &config.session)?;
+
+    SessionEntity::update(SessionActiveModel {
+        id: ActiveValue::Set(session.id),
+        auth_prefix: ActiveValue::Set(token_prefix(&tokens.auth_key).to_string()),
+        auth_key: ActiveValue::Set(crypto.hash_token(&tokens.auth_key)),
+        last_used: ActiveValue::Set(now),
+        expires: ActiveValue::Set(tokens.expires),
+        refresh_prefix: ActiveValue::Set(Some(token_prefix(&tokens.refresh_token).to_string())),
+        refresh_token: ActiveValue::Set(Some(crypto.hash_token(&tokens.refresh_token))),
+        refresh_expires: ActiveValue::Set(tokens.refresh_expires),
+        ..Default::default()
+    })
+    .exec(db.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Starts a session for a device of an account.
+pub async fn create(
+    account_id: i64,
+    device: String,
+    config: &SessionConfig,
+    db: &DatabaseConnection,
+    crypto: &Crypto,
+) -> ApiResult<SessionTokens> {
+    let now = chrono::Utc::now().timestamp();
+    let mut tokens = new_tokens(0, config)?;
+
+    let session_res = SessionEntity::insert(SessionActiveModel {
+        id: ActiveValue::default(),
+        account_id: ActiveValue::Set(account_id),
+        auth_prefix: ActiveValue::Set(token_prefix(&tokens.auth_key).to_string()),
+        auth_key: ActiveValue::Set(crypto.hash_token(&tokens.auth_key)),
+        device: ActiveValue::Set(device),
+        created: ActiveValue::Set(now),
+        last_used: ActiveValue::Set(now),
+        expires: ActiveValue::Set(tokens.expires),
+        refresh_prefix: ActiveValue::Set(Some(token_prefix(&tokens.refresh_token).to_string())),
+        refresh_token: ActiveValue::Set(Some(crypto.hash_token(&tokens.refresh_token))),
+        refresh_expires: ActiveValue::Set(tokens.refresh_expires),
+    })
+    .exec(db)
+    .await?;
+
+    tokens.id = session_res.last_insert_id;
+
+    Ok(tokens)
+}
+
+/// Slides the expiry of a session that is being used.
+pub async fn touch(
+    session: &Session,
+    config: &SessionConfig,
+    db: &DatabaseConnection,
+) -> ApiResult<()> {
+    let now = chrono::Utc::now().timestamp();
+
+    if now - session.last_used < TOUCH_INTERVAL_SECS {
+        return Ok(());
+    }
+
+    SessionEntity::update(SessionActiveModel {
+        id: ActiveValue::Set(session.id),
+        last_used: ActiveValue::Set(now),
+        expires: ActiveValue::Set(now + config.ttl_secs),
+        ..Default::default()
+    })
+    .exec(db)
+    .await?;
+
+    Ok(())
+}
+
+/// A session that was not removed and can still be used or refreshed, links
+/// handed out to it stop working when it is gone.
+pub async fn find_active(id: i64, db: &DatabaseConnection) -> ApiResult<Option<Session>> {
+    let now = chrono::Utc::now().timestamp();
+
+    let session = SessionEntity::find_by_id(id)
+        .filter(
+            Condition::any()
+                .add(SessionColumn::Expires.gt(now))
+                .add(SessionColumn::RefreshExpires.gt(now)),
+        )
+        .one(db)
+        .await?;
+
+    Ok(session)
+}
+
+/// Deletes sessions that can neither be used nor refreshed anymore.
+pub async fn purge(db: ActixWeb::Data<DatabaseConnection>) -> ApiResult<()> {
+    let now = chrono::Utc::now().timestamp();
+
+    let result = SessionEntity::delete_many()
+        .filter(SessionColumn::Expires.lte(now))
+        .filter(SessionColumn::RefreshExpires.lte(now))
+        .exec(db.get_ref())
+        .await?;
+
+    tracing::debug!(count = result.rows_affected, "purged expired sessions");
+
+    Ok(())
+}
+
+/// Public start of a token, stored next to its hash so lookups stay indexed.
+pub fn token_prefix(token: &str) -> &str {
+    token.get(..8).unwrap_or(token)
+}
+
+fn new_tokens(id: i64, config: &SessionConfig) -> ApiResult<SessionTokens> {
+    let now = chrono::Utc::now().timestamp();
+
+    Ok(SessionTokens {
+        id,
+        auth_key: random_hex::<16>()?,
+        refresh_token: random_hex::<32>()?,
+        expires: now + config.ttl_secs,
+        refresh_expires: now + config.refresh_ttl_secs,
+    })
+}
+
+fn random_hex<const N: usize>() -> ApiResult<String> {
+    let mut random_bytes = [0u8; N];
+    OsRng.try_fill_bytes(&mut random_bytes)?;
+    Ok(hex::encode(random_bytes))
+}
@@ -0,0 +1,269 @@
+use actix_web::{web as ActixWeb, HttpResponse};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    client::Client,
+    config::Config,
+    crypto::Crypto,
+    entities::{login::Backend, prelude::*},
+    extra::{get_days_ago, BoolResult},
+    home,
+    hosts::HostHealth,
+    login::{self, get_login_info},
+};
+
+/// A provider login of the account, without its password.
+#[derive(ToSchema, Serialize)]
+pub struct Source {
+    /// Passed as `source` to the endpoints taking a value id.
+    id: i64,
+    server: String,
+    /// Other hosts tried when `server` is down.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    mirrors: Vec<String>,
+    backend: Backend,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epg_url: Option<String>,
+    username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_info: Option<UserInfo>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/source/get",
+    tag = "source",
+    responses(
+        (status = 200, description = "Logins of the account, the first one is the default source", body = Vec<Source>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/get")]
+pub async fn get(
+    credentials: BearerAuth,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+) -> ApiResult<HttpResponse> {
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+
+    let sources = LoginEntity::find()
+        .find_also_related(UserInfoEntity)
+        .filter(LoginColumn::AccountId.eq(session.account_id))
+        .order_by_asc(LoginColumn::Id)
+        .all(db.get_ref())
+        .await?
+        .into_iter()
+        .map(|(login, user_info)| Source {
+            id: login.id,
+            server: login.server,
+            mirrors: login.mirrors.0,
+            backend: login.backend,
+            epg_url: login.epg_url,
+            username: login.username,
+            user_info,
+        })
+        .collect::<Vec<Source>>();
+
+    Ok(HttpResponse::Ok().json(sources))
+}
+
+/// Health of the hosts of a provider login.
+#[derive(ToSchema, Serialize)]
+pub struct SourceHealth {
+    source: i64,
+    /// Primary server first, then its mirrors.
+    hosts: Vec<HostHealth>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/source/health",
+    tag = "source",
+    responses(
+        (status = 200, description = "Health of the hosts of every login of the account", body = Vec<SourceHealth>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/health")]
+async fn health(
+    credentials: BearerAuth,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+
+    let health = LoginEntity::find()
+        .filter(LoginColumn::AccountId.eq(session.account_id))
+        .order_by_asc(LoginColumn::Id)
+        .all(db.get_ref())
+        .await?
+        .iter()
+        .map(|login| SourceHealth {
+            source: login.id,
+            hosts: client.hosts.health(login),
+        })
+        .collect::<Vec<SourceHealth>>();
+
+    Ok(HttpResponse::Ok().json(health))
+}
+
+#[utoipa::path(
+    post,
+    path = "/source/store",
+    tag = "source",
+    request_body = Login,
+    responses(
+        (status = 200, description = "Login added to the account, or the same one already there", body = Source),
+        (status = 401, description = "Auth key is invalid, or account not found or not active", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::post("/store")]
+async fn store(
+    credentials: BearerAuth,
+    request: ActixWeb::Json<Login>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+    config: ActixWeb::Data<Config>,
+) -> ApiResult<HttpResponse> {
+    let mut login = request.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+
+    login::normalize_server(&mut login)?;
+
+    for candidate in LoginEntity::find()
+        .filter(
+            Condition::all()
+                .add(LoginColumn::AccountId.eq(session.account_id))
+                .add(LoginColumn::Server.eq(&login.server))
+                .add(LoginColumn::Username.eq(&login.username)),
+        )
+        .all(db.get_ref())
+        .await?
+    {
+        let candidate = crypto.open_login(candidate)?;
+
+        if candidate.password == login.password {
+            login::set_mirrors(&candidate, &login.mirrors, &db).await?;
+
+            let user_info = UserInfoEntity::find_by_id(candidate.id)
+                .one(db.get_ref())
+                .await?;
+
+            return Ok(HttpResponse::Ok().json(Source {
+                id: candidate.id,
+                server: candidate.server,
+                mirrors: if login.mirrors.0.is_empty() {
+                    candidate.mirrors.0
+                } else {
+                    login.mirrors.0
+                },
+                backend: candidate.backend,
+                epg_url: candidate.epg_url,
+                username: candidate.username,
+                user_info,
+            }));
+        }
+    }
+
+    let user_info = get_login_info(&login, client.clone()).await?;
+
+    login.account_id = session.account_id;
+    let login = login::insert(login, user_info.clone(), &db, &crypto).await?;
+
+    let logins = login::get_logins(&session, &db, &crypto).await?;
+    let month_ago = get_days_ago(config.retention.home_days);
+    home::make(session.account_id, &logins, month_ago, db.clone(), client).await?;
+
+    Ok(HttpResponse::Ok().json(Source {
+        id: login.id,
+        server: login.server,
+        mirrors: login.mirrors.0,
+        backend: login.backend,
+        epg_url: login.epg_url,
+        username: login.username,
+        user_info: Some(user_info),
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/source/remove/{id}",
+    tag = "source",
+    params(
+        ("id" = i64, Path, description = "Source id"),
+    ),
+    responses(
+        (status = 200, description = "Whether the login was removed", body = BoolResult),
+        (status = 400, description = "Login is the last one of the account", body = ApiErrorJson),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Source does not exist", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/remove/{id}")]
+async fn remove(
+    credentials: BearerAuth,
+    path: ActixWeb::Path<i64>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+) -> ApiResult<HttpResponse> {
+    let id = path.into_inner();
+    let auth_key = credentials.token();
+
+    let session = login::get_session(auth_key, &db, &crypto).await?;
+
+    let login = LoginEntity::find()
+        .filter(LoginColumn::Id.eq(id))
+        .filter(LoginColumn::AccountId.eq(session.account_id))
+        .one(db.get_ref())
+        .await?
+        .ok_or(ApiError::WrongSource)?;
+
+    let count = LoginEntity::find()
+        .filter(LoginColumn::AccountId.eq(session.account_id))
+        .count(db.get_ref())
+        .await?;
+
+    if count <= 1 {
+        return Err(ApiError::BadRequest(
+            "the last source of an account can not be removed".to_string(),
+        ));
+    }
+
+    // Its catalog, favorites, progress and home rows go along with it, the
+    // home is filled from the other logins on the next refresh.
+    let result = LoginEntity::delete(Into::<LoginActiveModel>::into(login))
+        .exec(db.get_ref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(BoolResult {
+        result: result.rows_affected > 0,
+    }))
+}
@@ -5,26 +5,45 @@ use serde::Deserialize;
 
 use crate::{
     api_error::{ApiError, ApiResult},
+    client::Client,
+    crypto::Crypto,
     entities::{prelude::*, watching::Kind},
     extra::{get_days_ago, BoolResult, Params},
     get::{get_movie_info, get_serie_info, Value},
-    login,
+    login::{self, SourceQuery},
 };
 
+#[utoipa::path(
+    get,
+    path = "/watching/get/{avatar}",
+    tag = "watching",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+    ),
+    responses(
+        (status = 200, description = "Values the avatar is watching", body = Vec<Watching>),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar does not exist", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/get/{avatar}")]
 async fn get(
     credentials: BearerAuth,
     path: ActixWeb::Path<i64>,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let avatar = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     AvatarEntity::find()
         .filter(AvatarColumn::Id.eq(avatar))
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .one(db.get_ref())
         .await?
         .ok_or(ApiError::WrongAvatar)?;
@@ -46,51 +65,175 @@ struct Store {
     time: i64,
 }
 
-#[actix_web::routes]
-#[get("/store/{avatar}/{kind}/{id}/{time}")]
-#[get("/store/{avatar}/{kind}/{id}/{episode_id}/{time}")]
+#[utoipa::path(
+    get,
+    path = "/watching/store/{avatar}/{kind}/{id}/{time}",
+    tag = "watching",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+        ("kind" = WatchingKind, Path, description = "Kind of the value"),
+        ("id" = i64, Path, description = "Value id"),
+        ("time" = i64, Path, description = "Position in seconds"),
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Position was saved", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar, source or id does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/store/{avatar}/{kind}/{id}/{time}")]
 async fn store(
     credentials: BearerAuth,
     path: ActixWeb::Path<Store>,
+    source: ActixWeb::Query<SourceQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
-    client: ActixWeb::Data<reqwest::Client>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    save(
+        credentials,
+        path.into_inner(),
+        source.source,
+        db,
+        crypto,
+        client,
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/watching/store/{avatar}/{kind}/{id}/{episode_id}/{time}",
+    tag = "watching",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+        ("kind" = WatchingKind, Path, description = "Kind of the value"),
+        ("id" = i64, Path, description = "Value id"),
+        ("episode_id" = i64, Path, description = "Episode id"),
+        ("time" = i64, Path, description = "Position in seconds"),
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Position was saved", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar, source, id or episode id does not exist", body = ApiErrorJson),
+        (status = 502, description = "Server could not be reached", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
+#[actix_web::get("/store/{avatar}/{kind}/{id}/{episode_id}/{time}")]
+async fn store_episode(
+    credentials: BearerAuth,
+    path: ActixWeb::Path<Store>,
+    source: ActixWeb::Query<SourceQuery>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<HttpResponse> {
+    save(
+        credentials,
+        path.into_inner(),
+        source.source,
+        db,
+        crypto,
+        client,
+    )
+    .await
+}
+
+async fn save(
+    credentials: BearerAuth,
+    watch: Store,
+    source: Option<i64>,
+    db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
+    client: ActixWeb::Data<Client>,
 ) -> ApiResult<HttpResponse> {
-    let store = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     AvatarEntity::find()
-        .filter(AvatarColumn::Id.eq(store.avatar))
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::Id.eq(watch.avatar))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .one(db.get_ref())
         .await?
         .ok_or(ApiError::WrongAvatar)?;
 
-    let login = login::get_login(&session, &db).await?;
+    let login = login::get_login(&session, source, &db, &crypto).await?;
+
+    let position = Position {
+        avatar: watch.avatar,
+        login_id: login.id,
+        kind: watch.kind,
+        id: watch.id,
+        episode_id: watch.episode_id,
+        time: watch.time,
+        duration: None,
+    };
+    save_position(&login, position, &db, client).await?;
+
+    Ok(HttpResponse::Ok().json(BoolResult { result: true }))
+}
+
+/// Where an avatar is in a value, from the store endpoints or the
+/// heartbeats of a player.
+pub struct Position {
+    pub avatar: i64,
+    pub login_id: i64,
+    pub kind: Kind,
+    pub id: i64,
+    pub episode_id: Option<i64>,
+    pub time: i64,
+    pub duration: Option<i64>,
+}
 
-    let value = match store.kind {
+/// Saves a position. The value is only looked up the first time the avatar
+/// watches it, or a new episode of it, later positions are written in place.
+pub async fn save_position(
+    login: &Login,
+    position: Position,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<()> {
+    if position.kind == Kind::Serie && position.episode_id.is_none() {
+        return Err(ApiError::BadRequest("episode_id is required".to_string()));
+    }
+
+    if update(&position, db).await? {
+        return Ok(());
+    }
+
+    let value = match position.kind {
         Kind::Movie => {
-            let movie_info = get_movie_info(store.id, Params::new(&login), client).await?;
-            Value::from_movie_info(movie_info)
+            let movie_info = get_movie_info(position.id, Params::new(login), db, client).await?;
+            Value::from_movie_info(movie_info, login.id)
         }
         Kind::Serie => {
-            let episode_id = store.episode_id.ok_or(ApiError::NotFound)?;
+            let episode_id = position.episode_id.unwrap_or_default();
 
             for episodes in WatchingEntity::find()
-                .filter(WatchingColumn::AvatarId.eq(store.avatar))
-                .filter(WatchingColumn::Kind.eq(store.kind.clone()))
-                .filter(WatchingColumn::ValueId.eq(store.id))
+                .filter(WatchingColumn::AvatarId.eq(position.avatar))
+                .filter(WatchingColumn::LoginId.eq(login.id))
+                .filter(WatchingColumn::Kind.eq(position.kind.clone()))
+                .filter(WatchingColumn::ValueId.eq(position.id))
                 .filter(WatchingColumn::EpisodeId.ne(episode_id))
-                .all(db.get_ref())
+                .all(db)
                 .await?
             {
                 WatchingEntity::delete(Into::<WatchingActiveModel>::into(episodes))
-                    .exec(db.get_ref())
+                    .exec(db)
                     .await?;
             }
 
-            let serie_info = get_serie_info(store.id, Params::new(&login), client).await?;
+            let serie_info = get_serie_info(position.id, Params::new(login), db, client).await?;
 
             let container_extension = serie_info
                 .episodes
@@ -101,68 +244,118 @@ async fn store(
                 .container_extension
                 .clone();
 
-            Value::from_serie_info(serie_info, store.id, Some(episode_id), container_extension)
+            Value::from_serie_info(
+                serie_info,
+                position.id,
+                Some(episode_id),
+                container_extension,
+                login.id,
+            )
         }
     };
 
+    WatchingEntity::insert(WatchingActiveModel {
+        id: Default::default(),
+        avatar_id: ActiveValue::Set(position.avatar),
+        login_id: ActiveValue::Set(login.id),
+        kind: ActiveValue::Set(position.kind),
+        value_id: ActiveValue::Set(value.id),
+        name: ActiveValue::Set(value.name),
+        icon: ActiveValue::Set(value.icon),
+        date: ActiveValue::Set(chrono::Utc::now().timestamp()),
+        time: ActiveValue::Set(position.time),
+        duration: ActiveValue::Set(position.duration),
+        episode_id: ActiveValue::Set(value.episode_id),
+        container_extension: ActiveValue::Set(value.container_extension),
+    })
+    .exec(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Writes a position over the one saved for the same value and episode,
+/// false when the avatar was not watching it yet.
+pub async fn update(position: &Position, db: &DatabaseConnection) -> ApiResult<bool> {
+    let episode = match position.episode_id {
+        Some(episode_id) => WatchingColumn::EpisodeId.eq(episode_id),
+        None => WatchingColumn::EpisodeId.is_null(),
+    };
+
     let exist = WatchingEntity::find()
-        .filter(WatchingColumn::AvatarId.eq(store.avatar))
-        .filter(WatchingColumn::Kind.eq(store.kind.clone()))
-        .filter(WatchingColumn::ValueId.eq(value.id))
-        .one(db.get_ref())
+        .filter(WatchingColumn::AvatarId.eq(position.avatar))
+        .filter(WatchingColumn::LoginId.eq(position.login_id))
+        .filter(WatchingColumn::Kind.eq(position.kind.clone()))
+        .filter(WatchingColumn::ValueId.eq(position.id))
+        .filter(episode)
+        .one(db)
         .await?;
 
-    let date = chrono::Utc::now().timestamp();
+    let Some(watching) = exist else {
+        return Ok(false);
+    };
 
-    if let Some(watching) = exist {
-        WatchingEntity::update(WatchingActiveModel {
-            id: ActiveValue::Set(watching.id),
-            date: ActiveValue::Set(date),
-            time: ActiveValue::Set(store.time),
-            ..Default::default()
-        })
-        .exec(db.get_ref())
-        .await?;
-    } else {
-        WatchingEntity::insert(WatchingActiveModel {
-            id: Default::default(),
-            avatar_id: ActiveValue::Set(store.avatar),
-            kind: ActiveValue::Set(store.kind),
-            value_id: ActiveValue::Set(value.id),
-            name: ActiveValue::Set(value.name),
-            icon: ActiveValue::Set(value.icon),
-            date: ActiveValue::Set(date),
-            time: ActiveValue::Set(store.time),
-            episode_id: ActiveValue::Set(value.episode_id),
-            container_extension: ActiveValue::Set(value.container_extension),
-        })
-        .exec(db.get_ref())
-        .await?;
-    }
+    WatchingEntity::update(WatchingActiveModel {
+        id: ActiveValue::Set(watching.id),
+        date: ActiveValue::Set(chrono::Utc::now().timestamp()),
+        time: ActiveValue::Set(position.time),
+        // Players that never reported a length keep the one saved before.
+        duration: match position.duration {
+            Some(duration) => ActiveValue::Set(Some(duration)),
+            None => ActiveValue::NotSet,
+        },
+        ..Default::default()
+    })
+    .exec(db)
+    .await?;
 
-    Ok(HttpResponse::Ok().json(BoolResult { result: true }))
+    Ok(true)
 }
 
+#[utoipa::path(
+    get,
+    path = "/watching/remove/{avatar}/{kind}/{id}",
+    tag = "watching",
+    params(
+        ("avatar" = i64, Path, description = "Avatar id"),
+        ("kind" = WatchingKind, Path, description = "Kind of the value"),
+        ("id" = i64, Path, description = "Value id"),
+        SourceQuery,
+    ),
+    responses(
+        (status = 200, description = "Whether the value was removed", body = BoolResult),
+        (status = 401, description = "Auth key is invalid", body = ApiErrorJson),
+        (status = 404, description = "Avatar, source or id does not exist", body = ApiErrorJson),
+    ),
+    security(
+        ("auth_key" = [])
+    )
+)]
 #[actix_web::get("/remove/{avatar}/{kind}/{id}")]
 async fn remove(
     credentials: BearerAuth,
     path: ActixWeb::Path<(i64, Kind, i64)>,
+    source: ActixWeb::Query<SourceQuery>,
     db: ActixWeb::Data<DatabaseConnection>,
+    crypto: ActixWeb::Data<Crypto>,
 ) -> ApiResult<HttpResponse> {
     let (avatar, kind, id) = path.into_inner();
     let auth_key = credentials.token();
 
-    let session = login::get_session(auth_key, &db).await?;
+    let session = login::get_session(auth_key, &db, &crypto).await?;
 
     AvatarEntity::find()
         .filter(AvatarColumn::Id.eq(avatar))
-        .filter(AvatarColumn::SessionId.eq(session.id))
+        .filter(AvatarColumn::AccountId.eq(session.account_id))
         .one(db.get_ref())
         .await?
         .ok_or(ApiError::WrongAvatar)?;
 
+    let login = login::get_login(&session, source.source, &db, &crypto).await?;
+
     let watching: WatchingActiveModel = WatchingEntity::find()
         .filter(WatchingColumn::AvatarId.eq(avatar))
+        .filter(WatchingColumn::LoginId.eq(login.id))
         .filter(WatchingColumn::Kind.eq(kind))
         .filter(WatchingColumn::ValueId.eq(id))
         .one(db.get_ref())
@@ -177,8 +370,8 @@ async fn remove(
     }))
 }
 
-pub async fn clean(db: ActixWeb::Data<DatabaseConnection>) -> ApiResult<()> {
-    let weak_ago = get_days_ago(7);
+pub async fn clean(db: ActixWeb::Data<DatabaseConnection>, watching_days: i64) -> ApiResult<()> {
+    let weak_ago = get_days_ago(watching_days);
 
     let watchings = WatchingEntity::find()
         .filter(WatchingColumn::Date.lte(weak_ago))
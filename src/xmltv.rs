@@ -0,0 +1,321 @@
+use actix_web::web as ActixWeb;
+use quick_xml::{
+    escape::escape,
+    events::{BytesStart, Event},
+};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect};
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api_error::{ApiError, ApiResult},
+    catalog,
+    client::Client,
+    config::CacheConfig,
+    entities::{catalog_stream::Kind, prelude::*},
+    extra::{fetch, read_blocking},
+    get::Epg,
+    m3u,
+};
+
+/// Programmes shown for a channel, same as `get_short_epg` of panels.
+const SHORT_EPG_LIMIT: usize = 4;
+
+/// Programmes that ended this long ago are still kept, so guides can be
+/// looked at back to the start of the day.
+const HISTORY_SECS: i64 = 24 * 60 * 60;
+
+/// Programmes of each channel, ordered by start.
+pub type Guide = HashMap<String, Vec<Epg>>;
+
+type Slot = Arc<tokio::sync::Mutex<Option<(Instant, Arc<Guide>)>>>;
+
+/// Guides of `m3u` logins, read once for every channel of their catalog and
+/// kept for the time to live of the `xmltv` action. Each login has its own
+/// lock, so requests arriving together share a single download.
+pub struct Guides {
+    ttl: Duration,
+    state: Mutex<HashMap<i64, Slot>>,
+}
+
+impl Guides {
+    pub fn new(config: &CacheConfig) -> Guides {
+        Guides {
+            ttl: Duration::from_secs(config.ttl_for("xmltv")),
+            state: Mutex::default(),
+        }
+    }
+
+    fn slot(&self, login_id: i64) -> ApiResult<Slot> {
+        Ok(self.state.lock()?.entry(login_id).or_default().clone())
+    }
+}
+
+/// Upcoming programmes of a channel from the guide of an `m3u` login, empty
+/// when the login has no guide.
+pub async fn get_short_epg(
+    login: &Login,
+    channel: &str,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Vec<Epg>> {
+    let guide = get_guide(login, db, client).await?;
+    let now = chrono::Utc::now().timestamp();
+
+    Ok(guide
+        .get(channel)
+        .map(|x| {
+            x.iter()
+                .filter(|x| x.stop_timestamp > now)
+                .take(SHORT_EPG_LIMIT)
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Guide of an `m3u` login for the channels of its catalog, empty when the
+/// login has no guide.
+pub async fn get_guide(
+    login: &Login,
+    db: &DatabaseConnection,
+    client: ActixWeb::Data<Client>,
+) -> ApiResult<Arc<Guide>> {
+    if m3u::guide_url(login, &login.server)?.is_none() {
+        return Ok(Arc::default());
+    }
+
+    let slot = client.guides.slot(login.id)?;
+    let mut slot = slot.lock().await;
+
+    if let Some((expires, guide)) = &*slot {
+        if *expires > Instant::now() {
+            return Ok(guide.clone());
+        }
+    }
+
+    catalog::ensure_synced(login, Kind::Live, db, client.clone()).await?;
+
+    let channels = CatalogStreamEntity::find()
+        .select_only()
+        .column(CatalogStreamColumn::EpgChannelId)
+        .filter(CatalogStreamColumn::LoginId.eq(login.id))
+        .filter(CatalogStreamColumn::Kind.eq(Kind::Live))
+        .filter(CatalogStreamColumn::EpgChannelId.is_not_null())
+        .into_tuple::<String>()
+        .all(db)
+        .await?
+        .into_iter()
+        .collect::<HashSet<String>>();
+
+    let from = chrono::Utc::now().timestamp() - HISTORY_SECS;
+
+    let guide = fetch(
+        login,
+        "xmltv",
+        &client,
+        |http, server| {
+            let url = m3u::guide_url(login, server)?.ok_or(ApiError::NotFound)?;
+            Ok(http.get(url))
+        },
+        |response, max_bytes| {
+            let channels = channels.clone();
+            read_blocking(response, max_bytes, move |reader| {
+                parse(std::io::BufReader::new(reader), &channels, from, usize::MAX)
+            })
+        },
+    )
+    .await?;
+
+    let guide = Arc::new(guide);
+    if !client.guides.ttl.is_zero() {
+        *slot = Some((Instant::now() + client.guides.ttl, guide.clone()));
+    }
+
+    Ok(guide)
+}
+
+/// The first `limit` programmes of each of `channels` that have not ended at
+/// `from`. Guides are big and mostly about other channels, so the document is
+/// scanned once and only the wanted programmes are kept.
+pub fn parse(
+    reader: impl BufRead,
+    channels: &HashSet<String>,
+    from: i64,
+    limit: usize,
+) -> ApiResult<Guide> {
+    let mut reader = quick_xml::Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    let mut guide = Guide::new();
+
+    let mut current: Option<(String, Epg)> = None;
+    let mut field: Option<Field> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(tag) if tag.name().as_ref() == b"programme" => {
+                current = programme(&tag)?.filter(|(channel, epg)| {
+                    epg.stop_timestamp > from && channels.contains(channel)
+                });
+            }
+            Event::End(tag) if tag.name().as_ref() == b"programme" => {
+                if let Some((channel, epg)) = current.take() {
+                    guide.entry(channel).or_default().push(epg);
+                }
+            }
+            Event::Start(tag) if current.is_some() => {
+                field = match tag.name().as_ref() {
+                    b"title" => Some(Field::Title),
+                    b"desc" => Some(Field::Description),
+                    _ => None,
+                };
+            }
+            Event::End(_) => field = None,
+            Event::Text(text) => {
+                if let (Some(field), Some((_, epg))) = (&field, &mut current) {
+                    field.push(epg, &text.unescape()?);
+                }
+            }
+            Event::CData(text) => {
+                if let (Some(field), Some((_, epg))) = (&field, &mut current) {
+                    field.push(epg, &String::from_utf8_lossy(&text));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    // Guides are not always in order.
+    for programmes in guide.values_mut() {
+        programmes.sort_by_key(|x| x.start_timestamp);
+        programmes.truncate(limit);
+    }
+
+    Ok(guide)
+}
+
+enum Field {
+    Title,
+    Description,
+}
+
+impl Field {
+    fn push(&self, epg: &mut Epg, text: &str) {
+        match self {
+            Field::Title => epg.title.push_str(text),
+            Field::Description => epg.description.push_str(text),
+        }
+    }
+}
+
+fn programme(tag: &BytesStart) -> ApiResult<Option<(String, Epg)>> {
+    let mut channel = None;
+    let mut start = None;
+    let mut stop = None;
+
+    for attribute in tag.attributes().flatten() {
+        let value = attribute.unescape_value()?;
+
+        match attribute.key.as_ref() {
+            b"channel" => channel = Some(value.into_owned()),
+            b"start" => start = parse_time(&value),
+            b"stop" => stop = parse_time(&value),
+            _ => {}
+        }
+    }
+
+    let (Some(channel), Some(start)) = (channel, start) else {
+        return Ok(None);
+    };
+
+    Ok(Some((
+        channel,
+        Epg {
+            title: String::new(),
+            description: String::new(),
+            start_timestamp: start,
+            stop_timestamp: stop.unwrap_or(start),
+            start: None,
+            stop: None,
+        },
+    )))
+}
+
+/// A channel written to a guide.
+pub struct Channel {
+    pub id: String,
+    pub name: String,
+    pub icon: String,
+    pub programmes: Vec<Epg>,
+}
+
+/// Writes an XMLTV document holding `channels` and their programmes.
+pub fn write(channels: &[Channel]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <!DOCTYPE tv SYSTEM \"xmltv.dtd\">\n\
+        <tv generator-info-name=\"PlayerApi\">\n",
+    );
+
+    for channel in channels {
+        xml.push_str(&format!(
+            "  <channel id=\"{}\">\n    <display-name>{}</display-name>\n",
+            escape(&channel.id),
+            escape(&channel.name)
+        ));
+        if !channel.icon.is_empty() {
+            xml.push_str(&format!("    <icon src=\"{}\"/>\n", escape(&channel.icon)));
+        }
+        xml.push_str("  </channel>\n");
+    }
+
+    for channel in channels {
+        for programme in &channel.programmes {
+            xml.push_str(&format!(
+                "  <programme start=\"{}\" stop=\"{}\" channel=\"{}\">\n    <title>{}</title>\n",
+                format_time(programme.start_timestamp),
+                format_time(programme.stop_timestamp),
+                escape(&channel.id),
+                escape(&programme.title)
+            ));
+            if !programme.description.is_empty() {
+                xml.push_str(&format!(
+                    "    <desc>{}</desc>\n",
+                    escape(&programme.description)
+                ));
+            }
+            xml.push_str("  </programme>\n");
+        }
+    }
+
+    xml.push_str("</tv>\n");
+    xml
+}
+
+fn format_time(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%Y%m%d%H%M%S +0000")
+        .to_string()
+}
+
+/// XMLTV times look like `20240101120000 +0100`, the offset is optional and
+/// UTC when missing.
+fn parse_time(text: &str) -> Option<i64> {
+    let text = text.trim();
+
+    if let Ok(time) = chrono::DateTime::parse_from_str(text, "%Y%m%d%H%M%S %z") {
+        return Some(time.timestamp());
+    }
+
+    chrono::NaiveDateTime::parse_from_str(text.get(..14)?, "%Y%m%d%H%M%S")
+        .ok()
+        .map(|x| x.and_utc().timestamp())
+}